@@ -0,0 +1,261 @@
+use std::path::PathBuf;
+
+use reqwest::Url;
+
+use crate::config::{self, Config};
+use crate::doc::escape;
+use crate::error::RetumiError;
+use crate::event::Response;
+use crate::history::History;
+use crate::tls::{CertInfo, CertProblem, Inspection, TlsSettings};
+
+const PAGES: [&str; 6] = [
+    "about",
+    "blank",
+    "bookmarks",
+    "config",
+    "history",
+    "version",
+];
+
+/// Generates the internal `about:` pages as HTML
+pub struct About {
+    config: Config,
+    history: History,
+    tls: TlsSettings,
+}
+
+impl About {
+    pub fn new(config: Config, history: History, tls: TlsSettings) -> Self {
+        Self {
+            config,
+            history,
+            tls,
+        }
+    }
+
+    pub fn page(&self, url: &str) -> Response {
+        let name = url
+            .split_once(':')
+            .map_or("", |(_, name)| name)
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let body = match name.as_str() {
+            "" | "about" => self.index(),
+            "blank" => String::new(),
+            "bookmarks" => self.bookmarks(),
+            "config" => self.config(),
+            "history" => self.history(),
+            "pageinfo" => self.page_info(url),
+            "version" => self.version(),
+            _ => page(
+                "Not found",
+                &format!("<p>There is no page called {}.</p>", escape(url)),
+            ),
+        };
+
+        html_response(url, body)
+    }
+
+    fn index(&self) -> String {
+        let items: String = PAGES
+            .iter()
+            .map(|name| format!("<li><a href=\"about:{name}\">about:{name}</a></li>"))
+            .collect();
+        page("About pages", &format!("<ul>{items}</ul>"))
+    }
+
+    fn history(&self) -> String {
+        let visits = self.history.visits();
+        if visits.is_empty() {
+            return page("History", "<p>Nothing visited yet.</p>");
+        }
+
+        let items: String = visits
+            .iter()
+            .rev()
+            .map(|visit| {
+                format!(
+                    "<li>{} <a href=\"{url}\">{url}</a></li>",
+                    httpdate::fmt_http_date(visit.at),
+                    url = escape(&visit.url)
+                )
+            })
+            .collect();
+        page("History", &format!("<ul>{items}</ul>"))
+    }
+
+    fn bookmarks(&self) -> String {
+        let path = bookmarks_path();
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+
+        // One bookmark per line: the URL, then optionally a title
+        let items: String = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (url, title) = line.split_once(char::is_whitespace).unwrap_or((line, line));
+                format!(
+                    "<li><a href=\"{}\">{}</a></li>",
+                    escape(url),
+                    escape(title.trim())
+                )
+            })
+            .collect();
+
+        if items.is_empty() {
+            page(
+                "Bookmarks",
+                &format!(
+                    "<p>No bookmarks yet. Add them to {}, one URL per line followed by an optional title.</p>",
+                    escape(&path.display().to_string())
+                ),
+            )
+        } else {
+            page("Bookmarks", &format!("<ul>{items}</ul>"))
+        }
+    }
+
+    fn config(&self) -> String {
+        let effective = toml::to_string_pretty(&self.config.redacted())
+            .unwrap_or_else(|err| format!("# could not serialize config: {err}"));
+        page(
+            "Configuration",
+            &format!(
+                "<p>Loaded from {}</p><pre>{}</pre>",
+                escape(&Config::path().display().to_string()),
+                escape(&effective)
+            ),
+        )
+    }
+
+    /// Details about the page at the `url` query parameter
+    fn page_info(&self, url: &str) -> String {
+        let Some(target) = Url::parse(url)
+            .ok()
+            .and_then(|u| {
+                u.query_pairs()
+                    .find(|(k, _)| k == "url")
+                    .map(|(_, v)| v.into_owned())
+            })
+            .and_then(|target| Url::parse(&target).ok())
+        else {
+            return page("Page info", "<p>No page given.</p>");
+        };
+
+        let mut body = format!(
+            "<p>URL: {url}</p><p><a href=\"{url}\">Back to the page</a></p><h2>Certificates</h2>",
+            url = escape(target.as_str())
+        );
+
+        if target.scheme() != "https" {
+            body.push_str("<p>This page wasn't loaded over HTTPS.</p>");
+            return page("Page info", &body);
+        }
+        let host = target.host_str().unwrap_or_default();
+        if let Some(accepted) = self.tls.exception(host) {
+            body.push_str(&format!(
+                "<p><strong>{} has an exception for the certificate with fingerprint {}.</strong></p>",
+                escape(host),
+                escape(&accepted)
+            ));
+        }
+
+        match self.tls.inspection(host) {
+            Some(inspection) => {
+                body.push_str(&match &inspection.problem {
+                    Some(problem) => format!("<p>{}</p>", escape(&problem.explanation())),
+                    None => String::from("<p>The certificate chain is trusted.</p>"),
+                });
+                body.push_str(&chain(&inspection.chain));
+            }
+            None => body
+                .push_str("<p>No connection to this host has been made since retumi started.</p>"),
+        }
+
+        page("Page info", &body)
+    }
+
+    fn version(&self) -> String {
+        page(
+            "Version",
+            &format!(
+                "<p>retumi {}</p><p>User agent: {}</p>",
+                env!("CARGO_PKG_VERSION"),
+                escape(&self.config.http.user_agent)
+            ),
+        )
+    }
+}
+
+/// The page shown instead of one whose certificate we couldn't verify
+pub fn certificate_error(
+    url: &str,
+    err: &RetumiError,
+    inspection: Option<&Inspection>,
+) -> Response {
+    let mut body = format!(
+        "<p>{} wasn't loaded because its certificate couldn't be verified.</p>",
+        escape(url)
+    );
+
+    let problem = inspection.and_then(|i| i.problem.as_ref());
+    body.push_str(&match problem {
+        Some(problem) => format!("<p><strong>{}</strong></p>", escape(&problem.explanation())),
+        // Our own check passed, so the system's trust store must differ from ours
+        None => format!("<p><strong>{}</strong></p>", escape(&err.to_string())),
+    });
+    if problem == Some(&CertProblem::SelfSigned) {
+        body.push_str(
+            "<p>If this is your own development server, press t to trust it from now on.</p>",
+        );
+    }
+
+    if let Some(inspection) = inspection {
+        body.push_str("<h2>Certificates presented</h2>");
+        body.push_str(&chain(&inspection.chain));
+    }
+
+    html_response(url, page("Certificate problem", &body))
+}
+
+fn chain(chain: &[CertInfo]) -> String {
+    chain
+        .iter()
+        .enumerate()
+        .map(|(i, cert)| {
+            format!(
+                "<h3>{}. {}</h3><ul><li>Issued by: {}</li><li>Valid from {} until {}</li><li>Names: {}</li><li>SHA-256: {}</li></ul>",
+                i + 1,
+                escape(&cert.subject),
+                escape(&cert.issuer),
+                escape(&cert.not_before),
+                escape(&cert.not_after),
+                escape(&cert.names.join(", ")),
+                cert.fingerprint
+            )
+        })
+        .collect()
+}
+
+fn html_response(url: &str, body: String) -> Response {
+    Response {
+        url: url.to_string(),
+        content_type: Some(String::from("text/html; charset=utf-8")),
+        body: body.into_bytes(),
+        redirects: Vec::new(),
+        truncated: false,
+    }
+}
+
+fn bookmarks_path() -> PathBuf {
+    config::data_dir().join("bookmarks")
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html><html><head><title>{title}</title></head><body><h1>{title}</h1>{body}</body></html>")
+}
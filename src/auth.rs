@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use reqwest::header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config;
+use crate::error::RetumiError;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+// Keep passwords out of logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// One challenge from a `WWW-Authenticate` header
+#[derive(Clone, Debug)]
+pub struct Challenge {
+    /// The lowercased scheme, e.g. `basic` or `digest`
+    pub scheme: String,
+    params: HashMap<String, String>,
+}
+
+impl Challenge {
+    pub fn realm(&self) -> &str {
+        self.params.get("realm").map_or("", String::as_str)
+    }
+
+    /// A Digest challenge saying the nonce we answered has expired, rather
+    /// than that the credentials are wrong
+    pub fn is_stale(&self) -> bool {
+        self.scheme == "digest"
+            && self
+                .params
+                .get("stale")
+                .is_some_and(|stale| stale.eq_ignore_ascii_case("true"))
+    }
+
+    fn is_supported(&self) -> bool {
+        match self.scheme.as_str() {
+            "basic" => true,
+            "digest" => self.params.contains_key("nonce") && self.hash().is_some(),
+            _ => false,
+        }
+    }
+
+    fn hash(&self) -> Option<fn(&str) -> String> {
+        let algorithm = self
+            .params
+            .get("algorithm")
+            .map_or_else(|| String::from("md5"), |a| a.to_ascii_lowercase());
+        match algorithm.trim_end_matches("-sess") {
+            "md5" => Some(hex::<Md5>),
+            "sha-256" => Some(hex::<Sha256>),
+            _ => None,
+        }
+    }
+
+    /// The `Authorization` header answering this challenge for a GET of `url`
+    pub fn authorization(
+        &self,
+        credentials: &Credentials,
+        url: &Url,
+    ) -> Result<HeaderValue, RetumiError> {
+        let value = match self.scheme.as_str() {
+            "digest" => self.digest(credentials, url, &cnonce()),
+            _ => {
+                let pair = format!("{}:{}", credentials.username, credentials.password);
+                format!("Basic {}", STANDARD.encode(pair))
+            }
+        };
+        HeaderValue::from_str(&value).map_err(|_| {
+            RetumiError::AuthError(String::from(
+                "credentials contain characters that can't be sent",
+            ))
+        })
+    }
+
+    /// RFC 7616, with `qop=auth` when the server offers it
+    fn digest(&self, credentials: &Credentials, url: &Url, cnonce: &str) -> String {
+        let hash = self.hash().unwrap_or(hex::<Md5>);
+        let param = |name: &str| self.params.get(name).map_or("", String::as_str);
+        let nonce = param("nonce");
+        let uri = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let nc = "00000001";
+
+        let mut ha1 = hash(&format!(
+            "{}:{}:{}",
+            credentials.username,
+            self.realm(),
+            credentials.password
+        ));
+        if param("algorithm").to_ascii_lowercase().ends_with("-sess") {
+            ha1 = hash(&format!("{ha1}:{nonce}:{cnonce}"));
+        }
+        let ha2 = hash(&format!("GET:{uri}"));
+
+        let qop_auth = param("qop").split(',').any(|q| q.trim() == "auth");
+        let response = if qop_auth {
+            hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"))
+        } else {
+            hash(&format!("{ha1}:{nonce}:{ha2}"))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{nonce}\", uri=\"{uri}\", response=\"{response}\"",
+            quote(&credentials.username),
+            quote(self.realm())
+        );
+        if let Some(algorithm) = self.params.get("algorithm") {
+            header.push_str(&format!(", algorithm={algorithm}"));
+        }
+        if qop_auth {
+            header.push_str(&format!(", qop=auth, nc={nc}, cnonce=\"{cnonce}\""));
+        }
+        if let Some(opaque) = self.params.get("opaque") {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+}
+
+fn hex<D: Digest>(data: &str) -> String {
+    D::digest(data.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Doesn't need to be secret, just unlikely to repeat
+fn cnonce() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    hex::<Sha256>(&format!("{}:{}", now.as_nanos(), std::process::id()))[..16].to_string()
+}
+
+/// The challenges we know how to answer, in the order the server gave them
+pub fn challenges(headers: &HeaderMap) -> Vec<Challenge> {
+    headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(parse_challenges)
+        .filter(Challenge::is_supported)
+        .collect()
+}
+
+/// A header may hold several challenges, e.g. `Basic realm="a", Digest realm="b", nonce="c"`
+fn parse_challenges(header: &str) -> Vec<Challenge> {
+    let mut challenges: Vec<Challenge> = Vec::new();
+    let mut rest = header;
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            break;
+        }
+
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '=')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            // A stray '=', e.g. the padding of a token68
+            rest = &rest[1..];
+            continue;
+        }
+        let token = rest[..end].to_ascii_lowercase();
+        let after = rest[end..].trim_start();
+
+        match (after.strip_prefix('='), challenges.last_mut()) {
+            (Some(value), Some(challenge)) => {
+                let (value, after) = parse_value(value.trim_start());
+                challenge.params.insert(token, value);
+                rest = after;
+            }
+            _ => {
+                challenges.push(Challenge {
+                    scheme: token,
+                    params: HashMap::new(),
+                });
+                rest = &rest[end..];
+            }
+        }
+    }
+
+    challenges
+}
+
+/// Parse a token or quoted string, returning it and whatever follows
+fn parse_value(input: &str) -> (String, &str) {
+    let Some(quoted) = input.strip_prefix('"') else {
+        let end = input.find([',', ' ', '\t']).unwrap_or(input.len());
+        return (input[..end].to_string(), &input[end..]);
+    };
+
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            '"' => return (value, &quoted[i + 1..]),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+/// Credentials for each protection space, i.e. an origin and a realm.
+/// Remembered ones are written to disk unencrypted, readable only by the user.
+pub struct CredentialStore {
+    path: PathBuf,
+    session: HashMap<String, Credentials>,
+    saved: HashMap<String, Credentials>,
+}
+
+impl CredentialStore {
+    pub fn load() -> Self {
+        let path = config::data_dir().join("credentials.json");
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            session: HashMap::new(),
+            saved,
+        }
+    }
+
+    fn key(url: &Url, realm: &str) -> String {
+        format!("{} {realm}", url.origin().ascii_serialization())
+    }
+
+    /// The first challenge we have credentials for
+    pub fn find(&self, url: &Url, challenges: &[Challenge]) -> Option<(Challenge, Credentials)> {
+        challenges.iter().find_map(|challenge| {
+            let key = Self::key(url, challenge.realm());
+            self.session
+                .get(&key)
+                .or_else(|| self.saved.get(&key))
+                .map(|credentials| (challenge.clone(), credentials.clone()))
+        })
+    }
+
+    pub fn insert(
+        &mut self,
+        url: &Url,
+        realm: &str,
+        credentials: Credentials,
+        remember: bool,
+    ) -> Result<(), RetumiError> {
+        let key = Self::key(url, realm);
+        self.session.insert(key.clone(), credentials.clone());
+        if remember {
+            self.saved.insert(key, credentials);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Drop credentials that the server rejected
+    pub fn forget(&mut self, url: &Url, realm: &str) -> Result<(), RetumiError> {
+        let key = Self::key(url, realm);
+        self.session.remove(&key);
+        if self.saved.remove(&key).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), RetumiError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let file = options.open(&self.path)?;
+        serde_json::to_writer_pretty(file, &self.saved)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example in RFC 7616 §3.9.1
+    const RFC_CHALLENGES: [&str; 2] = [
+        "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, \
+         nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+         opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+        "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=MD5, \
+         nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+         opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+    ];
+    const RFC_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn mufasa() -> Credentials {
+        Credentials {
+            username: String::from("Mufasa"),
+            password: String::from("Circle of Life"),
+        }
+    }
+
+    fn rfc_digest(challenge: &str) -> String {
+        let challenge = parse_challenges(challenge).remove(0);
+        let url = Url::parse("http://www.example.org/dir/index.html").unwrap();
+        challenge.digest(&mufasa(), &url, RFC_CNONCE)
+    }
+
+    #[test]
+    fn values_are_tokens_or_quoted_strings() {
+        assert_eq!(parse_value("abc, def"), (String::from("abc"), ", def"));
+        assert_eq!(parse_value("abc"), (String::from("abc"), ""));
+        assert_eq!(
+            parse_value("\"a, \\\"b\\\" \\\\c\" rest"),
+            (String::from("a, \"b\" \\c"), " rest")
+        );
+        // Unterminated quotes run to the end
+        assert_eq!(parse_value("\"open"), (String::from("open"), ""));
+    }
+
+    #[test]
+    fn several_challenges_in_one_header() {
+        let challenges = parse_challenges(
+            "Basic realm=\"simple\", Newauth realm=\"apps\", type=1, \
+             title=\"Login to \\\"apps\\\"\", Digest realm=\"x\", nonce=abc",
+        );
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["basic", "newauth", "digest"]);
+        assert_eq!(challenges[0].realm(), "simple");
+        assert_eq!(challenges[1].params["type"], "1");
+        assert_eq!(challenges[1].params["title"], "Login to \"apps\"");
+        assert_eq!(challenges[2].params["nonce"], "abc");
+    }
+
+    #[test]
+    fn token68_and_unsupported_challenges() {
+        let challenges = parse_challenges("Negotiate YIIB7wYGKwYBBQUCoIIB4z==, Basic realm=r");
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["negotiate", "basic"]);
+        assert_eq!(challenges.last().unwrap().realm(), "r");
+
+        let mut headers = HeaderMap::new();
+        for value in [
+            "Negotiate abc",
+            "Digest realm=\"r\", nonce=\"n\", algorithm=SHA-512-256",
+            "Digest realm=\"r\"",
+            "Basic realm=\"r\"",
+        ] {
+            headers.append(WWW_AUTHENTICATE, HeaderValue::from_static(value));
+        }
+        let supported: Vec<_> = challenges(&headers).into_iter().map(|c| c.scheme).collect();
+        assert_eq!(supported, ["basic"]);
+    }
+
+    #[test]
+    fn challenges_across_headers_keep_their_order() {
+        let mut headers = HeaderMap::new();
+        for value in RFC_CHALLENGES {
+            headers.append(WWW_AUTHENTICATE, HeaderValue::from_str(value).unwrap());
+        }
+        let algorithms: Vec<_> = challenges(&headers)
+            .iter()
+            .map(|c| c.params["algorithm"].clone())
+            .collect();
+        assert_eq!(algorithms, ["SHA-256", "MD5"]);
+    }
+
+    #[test]
+    fn digest_sha256_matches_the_rfc() {
+        assert_eq!(
+            rfc_digest(RFC_CHALLENGES[0]),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", uri=\"/dir/index.html\", \
+             response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+             algorithm=SHA-256, qop=auth, nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+    }
+
+    #[test]
+    fn digest_md5_matches_the_rfc() {
+        assert!(
+            rfc_digest(RFC_CHALLENGES[1]).contains("response=\"8ca523f5e9506fed4657c9700eebdbec\"")
+        );
+    }
+
+    #[test]
+    fn digest_without_qop() {
+        // RFC 2069 style, where the response covers only the nonce and the request
+        let challenge = "Digest realm=\"testrealm@host.com\", \
+                         nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\"";
+        let header = rfc_digest(challenge);
+        let ha1 = hex::<Md5>("Mufasa:testrealm@host.com:Circle of Life");
+        let ha2 = hex::<Md5>("GET:/dir/index.html");
+        let expected = hex::<Md5>(&format!("{ha1}:dcd98b7102dd2f0e8b11d0f600bfb0c093:{ha2}"));
+        assert!(header.contains(&format!("response=\"{expected}\"")));
+        assert!(!header.contains("qop") && !header.contains("cnonce"));
+    }
+
+    #[test]
+    fn stale_nonces() {
+        let stale = parse_challenges("Digest realm=\"r\", nonce=\"n\", stale=TRUE").remove(0);
+        assert!(stale.is_stale());
+        let fresh = parse_challenges("Digest realm=\"r\", nonce=\"n\", stale=false").remove(0);
+        assert!(!fresh.is_stale());
+    }
+}
@@ -1,31 +1,53 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use crossbeam::channel::{Receiver, Sender};
+use encoding_rs::Encoding;
 use html2text::config::Config;
+use html2text::markup5ever_rcdom::Handle;
 use html2text::render::{RichAnnotation, RichDecorator, TextDecorator};
 use html2text::{Colour, RcDom};
+use image::{DynamicImage, RgbaImage};
+use regex::Regex;
+use reqwest::Url;
 
-use tuirealm::props::{Style, TextSpan};
-use tuirealm::ratatui::style::Modifier;
+use tuirealm::props::{Color, Style, TextSpan};
+use unicode_width::UnicodeWidthStr;
 
-use crate::doc;
+use crate::charset::{self, Confidence};
+use crate::content::{self, ContentKind, JsonView};
+use crate::css::{self, ElementStyle, Media, Stylesheet, TextAlign, Viewport};
+use crate::doc::{self, StyleSource};
 use crate::error::RetumiError;
+use crate::event::{Response, Subresource, SubresourceKind};
+use crate::find::{self, Match};
+use crate::gemtext;
+use crate::gopher;
+use crate::graphics::{self, CellSize, ImageMode, Picture, Protocol};
 use crate::js;
 use crate::js::{EngineContext, JsMessage, WorkerMsg};
+use crate::net::Hop;
+use crate::render::{self, ImageRow, Line};
+use crate::table::{self, Table};
+use crate::theme::Theme;
 
 #[derive(Clone, Debug)]
 struct RetumiRenderer {
     decorator: RichDecorator,
-    selected_link: Option<usize>,
-    link_count: usize,
-    selected_url: Option<String>,
+    /// How many links have been started, shared with the subblock decorators
+    links: Rc<Cell<usize>>,
 }
 
 impl RetumiRenderer {
     fn new() -> Self {
         Self {
             decorator: RichDecorator::new(),
-            selected_link: None,
-            link_count: 0,
-            selected_url: None,
+            links: Rc::default(),
         }
     }
 }
@@ -34,15 +56,13 @@ impl TextDecorator for RetumiRenderer {
     type Annotation = RichAnnotation;
 
     fn decorate_link_start(&mut self, url: &str) -> (String, Self::Annotation) {
-        self.link_count += 1;
-        if self.selected_link == Some(self.link_count) {
-            let style = RichAnnotation::ActiveLink(url.to_string());
-            let (str, _) = self.decorator.decorate_link_start(url);
-            self.selected_url = Some(url.to_string());
-            (str, style)
-        } else {
-            self.decorator.decorate_link_start(url)
+        if css::style_index(url).is_some() {
+            return self.decorator.decorate_link_start(url);
         }
+        // Number each link, so that neighbouring links to the same place stay apart
+        let n = self.links.get();
+        self.links.set(n + 1);
+        self.decorator.decorate_link_start(&format!("{n} {url}"))
     }
 
     fn decorate_link_end(&mut self) -> String {
@@ -89,7 +109,13 @@ impl TextDecorator for RetumiRenderer {
     }
 
     fn decorate_image(&mut self, src: &str, title: &str) -> (String, Self::Annotation) {
-        self.decorator.decorate_image(src, title)
+        let (_, annotation) = self.decorator.decorate_image(src, title);
+        // Stand in for the image, so that it's clear something is there
+        let label = match title.trim() {
+            "" => "image",
+            title => title,
+        };
+        (format!("[{label}]"), annotation)
     }
 
     fn decorate_preformat_cont(&self) -> Self::Annotation {
@@ -113,136 +139,1239 @@ impl TextDecorator for RetumiRenderer {
     }
 
     fn decorate_strikeout_start(&self) -> (String, Self::Annotation) {
-        self.decorate_code_start()
+        self.decorator.decorate_strikeout_start()
     }
 }
 
-fn to_style(tags: &[RichAnnotation]) -> Option<Style> {
-    let mut style = Style::default()
-        .fg(tuirealm::props::Color::White)
-        .bg(tuirealm::props::Color::Black);
-    let mut applied = false;
+/// The URLs of the links a run of text is in, including the ones marking styles
+fn link_urls(tags: &[RichAnnotation]) -> impl Iterator<Item = &str> {
+    tags.iter().filter_map(|ann| match ann {
+        RichAnnotation::Link(url) | RichAnnotation::ActiveLink(url) => Some(url.as_str()),
+        _ => None,
+    })
+}
 
-    for ann in tags {
-        match *ann {
-            RichAnnotation::Default => {}
-            RichAnnotation::Link(_) => {
-                applied = true;
-                style = style.add_modifier(Modifier::UNDERLINED);
-            }
-            RichAnnotation::ActiveLink(_) => {
-                applied = true;
-                style = style
-                    .bg(tuirealm::props::Color::Blue)
-                    .add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
-            }
-            RichAnnotation::Strong => {
-                applied = true;
-                style = style
-                    .add_modifier(Modifier::BOLD)
-                    .fg(tuirealm::props::Color::Green);
-            }
-            RichAnnotation::BgColour(Colour { r, g, b }) => {
-                applied = true;
-                style = style.bg(tuirealm::props::Color::Rgb(r, g, b));
-            }
-            _ => {}
+/// The link a run of text belongs to: the number of its element, and its URL
+fn link_url(tags: &[RichAnnotation]) -> Option<(usize, &str)> {
+    link_urls(tags)
+        .filter(|url| css::style_index(url).is_none())
+        .find_map(|link| {
+            let (n, url) = link.split_once(' ')?;
+            Some((n.parse().ok()?, url))
+        })
+}
+
+fn to_style(tags: &[RichAnnotation], active: bool, theme: &Theme) -> Style {
+    tags.iter().fold(theme.text, |style, ann| match ann {
+        RichAnnotation::Default => style,
+        RichAnnotation::Link(url) if css::style_index(url).is_some() => style,
+        RichAnnotation::Link(_) | RichAnnotation::ActiveLink(_) if active => {
+            style.patch(theme.active_link)
         }
-    }
+        RichAnnotation::Link(_) | RichAnnotation::ActiveLink(_) => style.patch(theme.link),
+        RichAnnotation::Image(_) => style.patch(theme.image),
+        RichAnnotation::Emphasis => style.patch(theme.emphasis),
+        RichAnnotation::Strong => style.patch(theme.strong),
+        RichAnnotation::Strikeout => style.patch(theme.strikeout),
+        RichAnnotation::Code => style.patch(theme.code),
+        RichAnnotation::Preformat(_) => style.patch(theme.preformat),
+        RichAnnotation::Colour(Colour { r, g, b }) => style.fg(Color::Rgb(*r, *g, *b)),
+        RichAnnotation::BgColour(Colour { r, g, b }) => style.bg(Color::Rgb(*r, *g, *b)),
+    })
+}
 
-    if applied {
-        Some(style)
-    } else {
-        None
-    }
+enum View {
+    Html,
+    Json(JsonView),
+    /// Drawn as a tree, in the colours of the current theme
+    Xml(String),
+    Static(Vec<TextSpan>),
+    Resource {
+        kind: ContentKind,
+        response: Response,
+        saved: Option<PathBuf>,
+    },
+}
+
+/// A `<meta http-equiv="refresh">` that navigates to `url` once `at` has passed
+#[derive(Clone, Debug)]
+pub struct Refresh {
+    pub url: String,
+    pub at: Instant,
+}
+
+/// Shared between the page, which arms and fires it, and the status bar
+pub type PendingRefresh = Arc<Mutex<Option<Refresh>>>;
+
+/// Until the page has been drawn we don't know how wide it is
+const DEFAULT_WIDTH: usize = 80;
+/// html2text gives up on anything narrower than its deepest nesting
+const MIN_WIDTH: usize = 20;
+/// Put in the document to find where the element a fragment names ends up
+const ANCHOR_MARKER: &str = "\u{E000}";
+
+pub enum Activation {
+    Follow(String),
+    Redraw(Vec<Line>),
+    Nothing,
 }
 
 pub struct Browser {
+    /// The document as scripts see it
     dom: RcDom,
-    pub current_link: Option<usize>,
+    /// A copy of `dom` to lay out, styled, and with its tables and pictures
+    /// swapped for markers
+    styled: RcDom,
+    /// The stylesheets and images that have arrived for the page, by URL
+    linked: Vec<(String, String)>,
+    images: Vec<(String, DynamicImage)>,
+    view: View,
+    base_url: Option<Url>,
+    redirects: Vec<Hop>,
+    show_redirects: bool,
+    refresh: Option<Refresh>,
+    /// Columns available to the page
+    width: usize,
+    max_width: Option<usize>,
+    /// Targets of the links on the page, in document order
+    links: Vec<String>,
+    current_link: Option<usize>,
+    search: Option<Regex>,
+    matches: Vec<Match>,
+    current_match: usize,
+    /// The page as last laid out, which searches look through
+    laid_out: Vec<Line>,
+    /// The `#fragment` of the page's URL
+    fragment: Option<String>,
+    /// Where the element named by the fragment was rendered
+    anchor_line: Option<usize>,
+    /// The page's CSS, for the elements it marked
+    styles: Vec<ElementStyle>,
+    /// The page's tables, which are laid out where their markers are rendered
+    tables: Vec<Table>,
+    /// The page's images, likewise
+    pictures: Vec<Picture>,
+    /// `None` if images are off
+    protocol: Option<Protocol>,
+    show_images: bool,
+    cell: CellSize,
+    /// Images scaled to fit the cells they take, by image, columns and rows
+    graphics: HashMap<(usize, usize, usize), RgbaImage>,
+    media: Media,
+    theme: Theme,
+    js: EngineContext,
     config: Config<RetumiRenderer>,
     msg_rx: Receiver<JsMessage>,
     worker_tx: Sender<WorkerMsg>,
+    download_dir: PathBuf,
 }
 
 impl Browser {
-    pub fn new(msg_rx: Receiver<JsMessage>, worker_tx: Sender<WorkerMsg>) -> Self {
+    pub fn new(
+        msg_rx: Receiver<JsMessage>,
+        worker_tx: Sender<WorkerMsg>,
+        download_dir: PathBuf,
+        max_width: Option<usize>,
+        media: Media,
+        theme: Theme,
+        images: ImageMode,
+    ) -> Self {
         Self {
             dom: RcDom::default(),
+            styled: RcDom::default(),
+            linked: Vec::new(),
+            images: Vec::new(),
+            view: View::Html,
+            base_url: None,
+            redirects: Vec::new(),
+            show_redirects: false,
+            refresh: None,
+            width: DEFAULT_WIDTH,
+            max_width,
+            links: Vec::new(),
             current_link: None,
+            search: None,
+            matches: Vec::new(),
+            current_match: 0,
+            laid_out: Vec::new(),
+            fragment: None,
+            anchor_line: None,
+            styles: Vec::new(),
+            tables: Vec::new(),
+            pictures: Vec::new(),
+            protocol: images.protocol(),
+            show_images: true,
+            cell: CellSize::query(),
+            graphics: HashMap::new(),
+            media,
+            theme,
+            js: EngineContext::new(),
             config: html2text::config::with_decorator(RetumiRenderer::new()),
             msg_rx,
             worker_tx,
+            download_dir,
         }
     }
 
-    pub fn render_contents(&mut self, contents: &str) -> Result<Vec<TextSpan>, RetumiError> {
-        let mut dom = self.config.parse_html(std::io::Cursor::new(contents))?;
+    pub fn render_contents(&mut self, response: &Response) -> Result<Vec<Line>, RetumiError> {
+        let kind = ContentKind::of(response);
+        tracing::info!("rendering {} as {kind:?}", response.url);
+        self.base_url = Url::parse(&response.url).ok();
+        self.fragment = fragment(self.base_url.as_ref());
+        self.redirects = response.redirects.clone();
+        self.show_redirects = false;
+        self.refresh = None;
+        self.search = None;
+        self.pictures.clear();
+
+        self.view = match kind {
+            ContentKind::Html => return self.render_html(response),
+            ContentKind::Gemini => {
+                let html = gemtext::to_html(&content::decode_text(response));
+                let dom = self.parse(html.as_bytes(), encoding_rs::UTF_8)?;
+                return self.show_dom(dom);
+            }
+            ContentKind::GopherMenu => {
+                let html = gopher::menu_to_html(&content::decode_text(response), &response.url);
+                let dom = self.parse(html.as_bytes(), encoding_rs::UTF_8)?;
+                return self.show_dom(dom);
+            }
+            ContentKind::Text => {
+                View::Static(content::render_text(&content::decode_text(response)))
+            }
+            ContentKind::Json => {
+                let text = content::decode_text(response);
+                match serde_json::from_str(&text) {
+                    Ok(value) => View::Json(JsonView::new(value)),
+                    Err(err) => {
+                        tracing::warn!("invalid JSON, showing as text: {err}");
+                        View::Static(content::render_text(&text))
+                    }
+                }
+            }
+            ContentKind::Xml => {
+                let text = content::decode_text(response);
+                match content::render_xml(&text, &self.theme) {
+                    Ok(_) => View::Xml(text),
+                    Err(err) => {
+                        tracing::warn!("invalid XML, showing as text: {err}");
+                        View::Static(content::render_text(&text))
+                    }
+                }
+            }
+            ContentKind::Image | ContentKind::Binary => View::Resource {
+                kind,
+                response: response.clone(),
+                saved: None,
+            },
+        };
+
+        self.render()
+    }
+
+    fn render_html(&mut self, response: &Response) -> Result<Vec<Line>, RetumiError> {
+        let (encoding, confidence) =
+            charset::sniff(response.content_type.as_deref(), &response.body);
+        let mut dom = self.parse(&response.body, encoding)?;
 
-        let mut context = EngineContext::new();
+        // The prescan only looks at the start of the document, so the parser
+        // may find a <meta> that disagrees with our guess: if so, start over.
+        if confidence == Confidence::Tentative {
+            let declared = doc::meta_charset(&dom).and_then(|l| charset::from_meta_label(&l));
+            if let Some(declared) = declared.filter(|d| *d != encoding) {
+                tracing::info!(
+                    "re-parsing as {} instead of {}",
+                    declared.name(),
+                    encoding.name()
+                );
+                dom = self.parse(&response.body, declared)?;
+            }
+        }
+
+        if let Some((delay, url)) = doc::meta_refresh(&dom) {
+            let url = url.map_or_else(|| response.url.clone(), |url| self.resolve(&url));
+            self.refresh = Some(Refresh {
+                url,
+                at: Instant::now() + Duration::from_secs(delay),
+            });
+        }
+
+        self.js = EngineContext::new();
+        js::exec(
+            &mut dom,
+            &mut self.js,
+            self.msg_rx.clone(),
+            self.worker_tx.clone(),
+            js::page_loaded(&response.url),
+        );
         let scripts = doc::extract_scripts(&dom);
         for script in scripts {
             js::exec(
                 &mut dom,
-                &mut context,
+                &mut self.js,
                 self.msg_rx.clone(),
                 self.worker_tx.clone(),
                 doc::contents(&script),
             );
         }
 
+        self.show_dom(dom)
+    }
+
+    /// Show `dom`, styled by its own CSS and without images until what it
+    /// links to arrives
+    fn show_dom(&mut self, dom: RcDom) -> Result<Vec<Line>, RetumiError> {
         self.dom = dom;
-        // Reset the rendering params so that we start with a clean page
+        self.linked.clear();
+        self.images.clear();
+        self.graphics.clear();
+        self.restyle();
+        self.view = View::Html;
         self.current_link = None;
-        self.config.decorator = RetumiRenderer::new();
         self.render()
     }
 
+    /// Make a new copy of the document to lay out, so that styling it and
+    /// taking out its tables and pictures leaves the one scripts use alone
+    fn restyle(&mut self) {
+        let styled = doc::copy(&self.dom);
+        let sheet = self.stylesheet(&styled);
+        self.styles = css::apply(&styled, &sheet);
+        let pictures = graphics::extract(&styled, &self.images, |src| self.resolve(src));
+        self.pictures = pictures;
+        self.tables = table::extract(&styled);
+        self.styled = styled;
+    }
+
+    /// What the page still needs fetching: the stylesheets it links to for
+    /// our media, then its images unless they're off
+    pub fn subresources(&self) -> Vec<Subresource> {
+        if !matches!(self.view, View::Html) {
+            return Vec::new();
+        }
+        let images = match self.protocol {
+            Some(_) => graphics::sources(&self.dom, |src| self.resolve(src)),
+            None => Vec::new(),
+        };
+        doc::stylesheets(&self.dom)
+            .into_iter()
+            .filter(|(_, query)| {
+                query
+                    .as_ref()
+                    .is_none_or(|q| css::media_matches(q, self.viewport()))
+            })
+            .filter_map(|(source, _)| match source {
+                StyleSource::Linked(href) => Some(Subresource {
+                    kind: SubresourceKind::Stylesheet,
+                    url: self.resolve(&href),
+                }),
+                StyleSource::Inline(_) => None,
+            })
+            .chain(images.into_iter().map(|url| Subresource {
+                kind: SubresourceKind::Image,
+                url,
+            }))
+            .collect()
+    }
+
+    /// Take in something the page needed, returning the page laid out again
+    /// if it changed
+    pub fn add_subresource(
+        &mut self,
+        subresource: &Subresource,
+        response: &Response,
+    ) -> Result<Option<Vec<Line>>, RetumiError> {
+        if !matches!(self.view, View::Html) {
+            return Ok(None);
+        }
+        match subresource.kind {
+            SubresourceKind::Stylesheet => {
+                // Keyed by what the page asked for, whatever redirects it took
+                let css = content::decode_text(response);
+                self.linked.push((subresource.url.clone(), css));
+            }
+            SubresourceKind::Image => {
+                let Some(image) = graphics::decode(&subresource.url, &response.body) else {
+                    return Ok(None);
+                };
+                self.images.push((subresource.url.clone(), image));
+            }
+        }
+        self.restyle();
+        self.render().map(Some)
+    }
+
+    /// The document's own CSS and the linked stylesheets, for our media
+    fn stylesheet(&self, dom: &RcDom) -> Stylesheet {
+        let mut sheet = Stylesheet::default();
+        for (source, query) in doc::stylesheets(dom) {
+            if query.is_some_and(|q| !css::media_matches(&q, self.viewport())) {
+                continue;
+            }
+            let css = match source {
+                StyleSource::Inline(css) => css,
+                StyleSource::Linked(href) => {
+                    let url = self.resolve(&href);
+                    match self.linked.iter().find(|(u, _)| *u == url) {
+                        Some((_, css)) => css.clone(),
+                        None => continue,
+                    }
+                }
+            };
+            sheet.extend(Stylesheet::parse(&css, self.viewport()));
+        }
+        sheet
+    }
+
+    fn parse(&self, body: &[u8], encoding: &'static Encoding) -> Result<RcDom, RetumiError> {
+        let text = charset::decode(body, encoding);
+        Ok(self.config.parse_html(std::io::Cursor::new(text))?)
+    }
+
     pub fn get_active_link(&mut self) -> Option<String> {
-        self.config.decorator.selected_url.clone()
+        match self.view {
+            View::Html => self.current_link.and_then(|i| self.link_url(i)),
+            _ => None,
+        }
     }
 
-    pub fn cycle_link(&mut self) -> Result<Vec<TextSpan>, RetumiError> {
-        if let Some(link) = self.current_link {
-            if link == self.config.decorator.link_count {
-                self.current_link = Some(1);
-            } else {
-                self.current_link = Some(link + 1);
+    /// Where the link at `index` in the document goes
+    pub fn link_url(&self, index: usize) -> Option<String> {
+        self.links.get(index).map(|href| self.resolve(href))
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        match self.base_url.as_ref().map(|base| base.join(href)) {
+            Some(Ok(url)) => url.to_string(),
+            _ => href.to_string(),
+        }
+    }
+
+    pub fn activate(&mut self) -> Result<Activation, RetumiError> {
+        if let Some(url) = self.get_active_link() {
+            return Ok(Activation::Follow(url));
+        }
+
+        match &mut self.view {
+            View::Json(json) => {
+                json.toggle();
+                Ok(Activation::Redraw(self.render()?))
             }
-        } else if self.config.decorator.link_count > 0 {
-            self.current_link = Some(1);
+            _ => Ok(Activation::Nothing),
+        }
+    }
+
+    /// Save the current resource to disk if it's one we can't display
+    pub fn save_resource(&mut self) -> Result<Option<Vec<Line>>, RetumiError> {
+        match &mut self.view {
+            View::Resource {
+                response, saved, ..
+            } => {
+                *saved = Some(content::save(response, &self.download_dir)?);
+                Ok(Some(self.render()?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The URL of the page being shown
+    pub fn url(&self) -> Option<&Url> {
+        self.base_url.as_ref()
+    }
+
+    /// The line showing the element the URL's fragment points at
+    pub fn anchor_line(&self) -> Option<usize> {
+        self.anchor_line
+    }
+
+    /// If `url` is just another part of the page we're showing, go there
+    /// without loading anything. Returns `None` if it's somewhere else.
+    pub fn navigate_within(&mut self, url: &str) -> Result<Option<Vec<Line>>, RetumiError> {
+        let (Some(current), Ok(target)) = (&self.base_url, Url::parse(url)) else {
+            return Ok(None);
+        };
+        let mut without_fragment = target.clone();
+        without_fragment.set_fragment(current.fragment());
+        if !matches!(self.view, View::Html)
+            || target.fragment().is_none()
+            || without_fragment != *current
+        {
+            return Ok(None);
+        }
+
+        let old_url = current.to_string();
+        let new_url = target.to_string();
+        self.fragment = fragment(Some(&target));
+        self.base_url = Some(target);
+        if old_url != new_url {
+            js::exec(
+                &mut self.dom,
+                &mut self.js,
+                self.msg_rx.clone(),
+                self.worker_tx.clone(),
+                js::hashchange(&old_url, &new_url),
+            );
+            // The handlers may have changed the document
+            self.restyle();
+        }
+        Ok(Some(self.render()?))
+    }
+
+    /// The meta refresh declared by the page we just rendered, if any
+    pub fn take_refresh(&mut self) -> Option<Refresh> {
+        self.refresh.take()
+    }
+
+    /// Show or hide the page's images, in favour of their alt text.
+    /// Returns `None` if the page has none.
+    pub fn toggle_images(&mut self) -> Result<Option<Vec<Line>>, RetumiError> {
+        self.show_images = !self.show_images;
+        tracing::info!(
+            "images {}",
+            if self.show_images { "shown" } else { "hidden" }
+        );
+        if self.pictures.is_empty() {
+            return Ok(None);
+        }
+        self.render().map(Some)
+    }
+
+    /// The escape sequence drawing `rows` of the picture `image` is part
+    /// of, if the terminal has a better way to show it than half blocks
+    pub fn graphic(&mut self, image: &ImageRow, rows: Range<usize>) -> Option<String> {
+        let protocol = self
+            .protocol
+            .filter(|p| *p != Protocol::Blocks && self.show_images)?;
+        let (_, source) = self.images.get(image.index)?;
+        let scaled = self
+            .graphics
+            .entry((image.index, image.columns, image.rows))
+            .or_insert_with(|| graphics::scale(source, image.columns, image.rows, self.cell));
+        graphics::encode(protocol, scaled, image.columns, image.rows, rows)
+    }
+
+    /// Show or hide the redirect chain above the page.
+    /// Returns `None` if the page wasn't redirected.
+    pub fn toggle_redirects(&mut self) -> Result<Option<Vec<Line>>, RetumiError> {
+        if self.redirects.is_empty() {
+            return Ok(None);
+        }
+
+        self.show_redirects = !self.show_redirects;
+        Ok(Some(self.render()?))
+    }
+
+    /// Lay the page out again for `width` columns.
+    /// Returns `None` if nothing changed.
+    pub fn resize(&mut self, width: usize) -> Result<Option<Vec<Line>>, RetumiError> {
+        if width == self.width {
+            return Ok(None);
+        }
+
+        let columns = self.layout_width();
+        self.width = width;
+        // The font may have changed with the size, and the pictures with it
+        self.cell = CellSize::query();
+        self.graphics.clear();
+        // Media queries on the width may apply differently now
+        if matches!(self.view, View::Html) && self.layout_width() != columns {
+            self.restyle();
+        }
+        Ok(Some(self.render()?))
+    }
+
+    /// Draw the page with `theme`. Returns `None` if nothing changed.
+    pub fn set_theme(&mut self, theme: Theme) -> Result<Option<Vec<Line>>, RetumiError> {
+        if theme == self.theme {
+            return Ok(None);
+        }
+
+        self.theme = theme;
+        Ok(Some(self.render()?))
+    }
+
+    /// The position of the selected link in the document
+    pub fn current_link(&self) -> Option<usize> {
+        self.current_link
+    }
+
+    pub fn link_count(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn select_link(&mut self, index: usize) -> Result<Vec<Line>, RetumiError> {
+        if index < self.links.len() {
+            self.current_link = Some(index);
+        }
+        self.render()
+    }
+
+    pub fn cycle_link(&mut self) -> Result<Vec<Line>, RetumiError> {
+        if let View::Json(json) = &mut self.view {
+            json.cycle();
+            return self.render();
+        }
+
+        if !self.links.is_empty() {
+            self.current_link = Some(self.current_link.map_or(0, |i| (i + 1) % self.links.len()));
         }
 
         self.render()
     }
 
-    fn render(&mut self) -> Result<Vec<TextSpan>, RetumiError> {
+    fn render(&mut self) -> Result<Vec<Line>, RetumiError> {
+        self.laid_out = self.layout()?;
+        self.find_matches();
+        Ok(self.highlighted())
+    }
+
+    /// Look for `search` in the page, starting from the first match at or
+    /// after `from_line`. `None` clears the search.
+    pub fn find(&mut self, search: Option<Regex>, from_line: usize) -> Vec<Line> {
+        self.search = search;
+        self.find_matches();
+        self.current_match = self
+            .matches
+            .iter()
+            .position(|m| m.line >= from_line)
+            .unwrap_or(0);
+        self.highlighted()
+    }
+
+    /// Move to the next match, or the previous one if `forward` is false
+    pub fn find_next(&mut self, forward: bool) -> Vec<Line> {
+        let count = self.matches.len();
+        if count > 0 {
+            self.current_match = match forward {
+                true => (self.current_match + 1) % count,
+                false => (self.current_match + count - 1) % count,
+            };
+        }
+        self.highlighted()
+    }
+
+    /// The line of the current match, its position and how many there are
+    pub fn current_match(&self) -> Option<(usize, usize, usize)> {
+        let found = self.matches.get(self.current_match)?;
+        Some((found.line, self.current_match, self.matches.len()))
+    }
+
+    fn find_matches(&mut self) {
+        self.matches = match &self.search {
+            Some(regex) => find::matches(regex, &self.laid_out),
+            None => Vec::new(),
+        };
+        if self.current_match >= self.matches.len() {
+            self.current_match = 0;
+        }
+    }
+
+    /// The page as last laid out, with the matches highlighted
+    fn highlighted(&self) -> Vec<Line> {
+        let mut lines = self.laid_out.clone();
+        for (i, found) in self.matches.iter().enumerate() {
+            let style = if i == self.current_match {
+                self.theme.find_current
+            } else {
+                self.theme.find_match
+            };
+            lines[found.line]
+                .highlight(found.start, found.end, |span| render::overlay(span, style));
+        }
+        lines
+    }
+
+    /// The page's lines before any search highlighting
+    fn layout(&mut self) -> Result<Vec<Line>, RetumiError> {
+        let mut lines = match (&self.base_url, self.show_redirects) {
+            (Some(url), true) => render::wrap(
+                content::render_redirects(url.as_str(), &self.redirects, &self.theme),
+                self.width,
+            ),
+            _ => Vec::new(),
+        };
+        let offset = lines.len();
+        self.anchor_line = None;
+        lines.extend(self.render_view()?);
+        self.anchor_line = self.anchor_line.map(|line| line + offset);
+        Ok(lines)
+    }
+
+    fn render_view(&mut self) -> Result<Vec<Line>, RetumiError> {
+        let spans = match &mut self.view {
+            View::Html => return self.render_dom(),
+            View::Json(json) => json.render(&self.theme),
+            // Checked when the page loaded
+            View::Xml(text) => content::render_xml(text, &self.theme).unwrap_or_default(),
+            View::Static(lines) => lines.clone(),
+            View::Resource {
+                kind: ContentKind::Image,
+                response,
+                saved,
+            } => content::render_image_info(response, saved.as_ref()),
+            View::Resource {
+                response, saved, ..
+            } => content::render_binary_info(response, saved.as_ref()),
+        };
+        Ok(render::wrap(spans, self.width))
+    }
+
+    /// Columns the page is laid out in, once held to `max_width`
+    fn layout_width(&self) -> usize {
+        self.max_width
+            .map_or(self.width, |max| self.width.min(max))
+            .max(MIN_WIDTH)
+    }
+
+    /// What the page's media queries are evaluated against
+    fn viewport(&self) -> Viewport {
+        Viewport {
+            media: self.media,
+            columns: self.layout_width(),
+        }
+    }
+
+    fn render_dom(&mut self) -> Result<Vec<Line>, RetumiError> {
         self.config.decorator = RetumiRenderer::new();
-        self.config.decorator.selected_link = self.current_link;
 
-        let tree = self.config.dom_to_render_tree(&self.dom)?;
-        let (rendered, dec) = self.config.render_to_lines_and_dec(tree, 120)?;
+        let anchor = self.fragment.as_deref().and_then(|f| {
+            doc::find_anchor(&self.styled.document, f).or_else(|| {
+                self.tables
+                    .iter()
+                    .find_map(|t| doc::find_anchor(&t.node, f))
+            })
+        });
+        let marker = anchor.map(|node| doc::prepend_text(&node, ANCHOR_MARKER));
+        let width = self.layout_width();
+        self.links.clear();
+        let dom = std::mem::take(&mut self.styled);
+        let rendered = self.render_fragment(&dom, width);
+        self.styled = dom;
+        if let Some(marker) = marker {
+            doc::detach(&marker);
+        }
+        let (mut lines, anchor_line) = rendered?;
+        self.anchor_line = anchor_line;
+
+        // Center the text when it's been held narrower than the page
+        let margin = self.width.saturating_sub(width) / 2;
+        for line in &mut lines {
+            line.indent(margin);
+        }
+
+        // An empty fragment, or "top" with nothing of that name, means the start
+        if self.anchor_line.is_none()
+            && self
+                .fragment
+                .as_deref()
+                .is_some_and(|f| f.is_empty() || f.eq_ignore_ascii_case("top"))
+        {
+            self.anchor_line = Some(0);
+        }
+
+        Ok(lines)
+    }
+
+    /// Lay out `dom` `width` columns wide, returning its lines and the one
+    /// the anchor is on
+    fn render_fragment(
+        &mut self,
+        dom: &RcDom,
+        width: usize,
+    ) -> Result<(Vec<Line>, Option<usize>), RetumiError> {
+        let tree = self.config.dom_to_render_tree(dom)?;
+        let (rendered, dec) = self.config.render_to_lines_and_dec(tree, width)?;
         self.config.decorator = dec;
 
-        let mut result = Vec::new();
-
-        for line in rendered {
-            let strings = line.tagged_strings();
-            for ts in strings {
-                if let Some(style) = to_style(&ts.tag) {
-                    let span = TextSpan {
-                        content: ts.s.clone(),
-                        fg: style.fg.unwrap(),
-                        bg: style.bg.unwrap(),
-                        modifiers: style.add_modifier,
-                    };
-                    result.push(span);
-                } else {
-                    result.push(TextSpan::new(ts.s.clone()));
+        let mut lines = Vec::new();
+        let mut anchor = None;
+        // A link that wraps continues on the next line, so this spans lines
+        let mut open_link: Option<usize> = None;
+
+        for tagged in &rendered {
+            let text: String = tagged.tagged_strings().map(|ts| ts.s.as_str()).collect();
+            if let Some(index) = graphics::marker_index(&text) {
+                let url = tagged.tagged_strings().find_map(|ts| link_url(&ts.tag));
+                let link = self.open(url, &mut open_link);
+                lines.extend(self.render_picture(index, width, link));
+                continue;
+            }
+            if let Some(index) = table::marker_index(&text) {
+                let (table, found) = self.render_table(index, width)?;
+                if found && anchor.is_none() {
+                    anchor = Some(lines.len());
+                }
+                lines.extend(table);
+                open_link = None;
+                continue;
+            }
+
+            let mut line = Line::default();
+            let mut align = None;
+
+            for ts in tagged.tagged_strings() {
+                let mut text = ts.s.clone();
+                if text.contains(ANCHOR_MARKER) {
+                    text = text.replace(ANCHOR_MARKER, "");
+                    anchor = Some(lines.len());
+                }
+
+                let link = self.open(link_url(&ts.tag), &mut open_link);
+
+                let active = link.is_some() && link == self.current_link;
+                let styles: Vec<&ElementStyle> = link_urls(&ts.tag)
+                    .filter_map(|url| self.styles.get(css::style_index(url)?))
+                    .collect();
+                // The line is aligned by whatever its text starts in
+                if align.is_none() && !text.trim().is_empty() {
+                    align = Some(styles.iter().rev().find_map(|s| s.text_align));
+                }
+
+                let mut style =
+                    styles
+                        .iter()
+                        .fold(to_style(&ts.tag, active, &self.theme), |style, element| {
+                            let style = match element.heading {
+                                Some(level) => style.patch(self.theme.heading(level)),
+                                None => style,
+                            };
+                            element.apply(style)
+                        });
+                // The selection has to stay visible whatever the page says
+                if active {
+                    style = style.patch(self.theme.active_link);
+                }
+                line.push(render::span(text, style), link);
+            }
+
+            let free = width.saturating_sub(line.text().trim_end().width());
+            let padding = match align.flatten() {
+                Some(TextAlign::Center) => free / 2,
+                Some(TextAlign::Right) => free,
+                _ => 0,
+            };
+            line.indent(padding);
+            lines.push(line);
+        }
+
+        Ok((lines, anchor))
+    }
+
+    /// The index of the link `url` marks, adding it to the page's links if
+    /// it's a different element from the `open` one
+    fn open(&mut self, url: Option<(usize, &str)>, open: &mut Option<usize>) -> Option<usize> {
+        let Some((n, url)) = url else {
+            *open = None;
+            return None;
+        };
+        if *open != Some(n) {
+            self.links.push(url.to_string());
+        }
+        *open = Some(n);
+        Some(self.links.len() - 1)
+    }
+
+    /// Draw picture `index` in half blocks at most `width` columns wide, or
+    /// its alt text if images are hidden
+    fn render_picture(&self, index: usize, width: usize, link: Option<usize>) -> Vec<Line> {
+        let Some(picture) = self.pictures.get(index) else {
+            return Vec::new();
+        };
+
+        if !self.show_images || self.protocol.is_none() {
+            let mut style = self.theme.image;
+            if link.is_some() && link == self.current_link {
+                style = style.patch(self.theme.active_link);
+            }
+            let label = match picture.alt.trim() {
+                "" => "image",
+                alt => alt,
+            };
+            let mut line = Line::default();
+            line.push(render::span(format!("[{label}]"), style), link);
+            return vec![line];
+        }
+
+        let Some((_, image)) = self.images.get(picture.image) else {
+            return Vec::new();
+        };
+        let (columns, rows) = graphics::size(image, width, self.cell);
+        graphics::half_blocks(image, columns, rows)
+            .into_iter()
+            .enumerate()
+            .map(|(row, spans)| {
+                let mut line = Line::default();
+                for span in spans {
+                    line.push(span, link);
                 }
+                if self.protocol != Some(Protocol::Blocks) {
+                    line.image = Some(ImageRow {
+                        index: picture.image,
+                        row,
+                        rows,
+                        column: 0,
+                        columns,
+                    });
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Lay out `node`'s children on their own, `width` columns wide
+    fn render_children(
+        &mut self,
+        node: &Handle,
+        width: usize,
+    ) -> Result<(Vec<Line>, Option<usize>), RetumiError> {
+        let dom = RcDom::default();
+        dom.document
+            .children
+            .borrow_mut()
+            .extend(node.children.borrow().iter().cloned());
+        let rendered = self.render_fragment(&dom, width);
+        // Dropping a node empties everything below it, so let go of the children first
+        dom.document.children.borrow_mut().clear();
+        rendered
+    }
+
+    /// Lay out table `index` `width` columns wide, and say whether the anchor
+    /// is in it. Its rows are stacked if its columns don't fit side by side.
+    fn render_table(
+        &mut self,
+        index: usize,
+        width: usize,
+    ) -> Result<(Vec<Line>, bool), RetumiError> {
+        let Some(table) = self.tables.get(index).cloned() else {
+            return Ok((Vec::new(), false));
+        };
+
+        let mut lines = Vec::new();
+        let mut found = false;
+        if let Some(caption) = &table.caption {
+            let (caption, anchor) = self.render_children(caption, width)?;
+            found |= anchor.is_some();
+            for mut line in caption {
+                line.indent(width.saturating_sub(line.text().trim_end().width()) / 2);
+                lines.push(line);
             }
         }
 
-        Ok(result)
+        let links = self.links.len();
+        let grid = match table.column_widths(width) {
+            Some(widths) => match self.render_cells(&table, &widths) {
+                Ok((contents, anchor)) => Some((
+                    table.draw(&widths, &contents, self.theme.table_border),
+                    anchor,
+                )),
+                Err(err) => {
+                    tracing::info!("stacking a table whose cells don't fit: {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+        let (body, anchor) = grid.unwrap_or_else(|| {
+            self.links.truncate(links);
+            self.stack_table(&table, width)
+        });
+        lines.extend(body);
+        Ok((lines, found || anchor))
+    }
+
+    /// The lines of each of `table`'s cells, with its columns `widths` wide
+    fn render_cells(
+        &mut self,
+        table: &Table,
+        widths: &[usize],
+    ) -> Result<(Vec<Vec<Line>>, bool), RetumiError> {
+        let header = self.theme.table_header;
+        let mut contents = vec![Vec::new(); table.cells.len()];
+        let mut found = false;
+        for (i, cell) in table.cells.iter().enumerate() {
+            let Some(node) = &cell.node else {
+                continue;
+            };
+            let (mut lines, anchor) = self.render_children(node, table.cell_width(cell, widths))?;
+            if cell.header {
+                for line in &mut lines {
+                    let end = line.width();
+                    line.highlight(0, end, |span| render::overlay(span, header));
+                }
+            }
+            found |= anchor.is_some();
+            contents[i] = lines;
+        }
+        Ok((contents, found))
+    }
+
+    /// Show each row of `table` as its cells one after another, labelled
+    /// with their column's header, for when the columns won't fit side by side
+    fn stack_table(&mut self, table: &Table, width: usize) -> (Vec<Line>, bool) {
+        let labels = table.labels();
+        let header = self.theme.table_header;
+        let rule = Line::from(render::span("─".repeat(width), self.theme.table_border));
+        let mut lines = Vec::new();
+        let mut found = false;
+
+        for row in table.header_rows()..table.rows() {
+            if !lines.is_empty() {
+                lines.push(rule.clone());
+            }
+            for (_, cell) in table.row(row) {
+                let Some(node) = &cell.node else {
+                    continue;
+                };
+                let label = labels.get(cell.column).cloned().flatten();
+                let indent = if label.is_some() { 2 } else { 0 };
+                if let Some(label) = label {
+                    lines.push(Line::from(render::span(label, header)));
+                }
+
+                let width = width.saturating_sub(indent).max(1);
+                let content = match self.render_children(node, width) {
+                    Ok((content, anchor)) => {
+                        found |= anchor.is_some();
+                        content
+                    }
+                    Err(err) => {
+                        tracing::warn!("showing a table cell as plain text: {err}");
+                        render::wrap(vec![TextSpan::new(doc::text_content(node))], width)
+                    }
+                };
+                for mut line in content {
+                    line.indent(indent);
+                    lines.push(line);
+                }
+            }
+        }
+        (lines, found)
+    }
+}
+
+/// The percent-decoded fragment of `url`
+fn fragment(url: Option<&Url>) -> Option<String> {
+    let fragment = url?.fragment()?;
+    Some(
+        percent_encoding::percent_decode_str(fragment)
+            .decode_utf8_lossy()
+            .into_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn browser() -> Browser {
+        let (msg_tx, msg_rx) = crossbeam::channel::unbounded();
+        let (worker_tx, worker_rx) = crossbeam::channel::unbounded();
+        // Scripts aren't what's being tested, so finish each one straight away
+        std::thread::spawn(move || {
+            for msg in worker_rx {
+                if let WorkerMsg::Execute(_) = msg {
+                    let _ = msg_tx.send(JsMessage::Done);
+                }
+            }
+        });
+        Browser::new(
+            msg_rx,
+            worker_tx,
+            PathBuf::new(),
+            None,
+            Media::Screen,
+            Theme::default(),
+            ImageMode::Blocks,
+        )
+    }
+
+    fn page(url: &str, body: &str) -> Response {
+        Response {
+            url: url.to_string(),
+            content_type: Some(String::from("text/html")),
+            body: body.as_bytes().to_vec(),
+            redirects: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn line_of(lines: &[Line], text: &str) -> Option<usize> {
+        lines.iter().position(|line| line.text().contains(text))
+    }
+
+    const LONG_PAGE: &str = "<p>Intro</p><p>a</p><p>b</p><p>c</p>\
+        <h2 id=\"section\">Section</h2><p>d</p>\
+        <p><a name=\"named\">Named</a></p><p>e</p>\
+        <p id=\"caf\u{e9}\">Accented</p>";
+
+    #[test]
+    fn fragment_is_percent_decoded() {
+        let url = Url::parse("http://example.com/#caf%C3%A9").unwrap();
+        assert_eq!(fragment(Some(&url)).as_deref(), Some("caf\u{e9}"));
+        let url = Url::parse("http://example.com/").unwrap();
+        assert_eq!(fragment(Some(&url)), None);
+    }
+
+    #[test]
+    fn anchors_are_found_by_id_or_name() {
+        let mut browser = browser();
+        for (fragment, text) in [
+            ("section", "Section"),
+            ("named", "Named"),
+            ("caf%C3%A9", "Accented"),
+        ] {
+            let url = format!("http://example.com/#{fragment}");
+            let lines = browser.render_contents(&page(&url, LONG_PAGE)).unwrap();
+            assert!(line_of(&lines, text).is_some());
+            assert_eq!(browser.anchor_line(), line_of(&lines, text), "#{fragment}");
+            // The marker used to find the line mustn't show
+            assert!(!lines.iter().any(|l| l.text().contains(ANCHOR_MARKER)));
+        }
+    }
+
+    #[test]
+    fn missing_anchors_and_top() {
+        let mut browser = browser();
+        browser
+            .render_contents(&page("http://example.com/#nowhere", LONG_PAGE))
+            .unwrap();
+        assert_eq!(browser.anchor_line(), None);
+
+        for url in ["http://example.com/#", "http://example.com/#TOP"] {
+            browser.render_contents(&page(url, LONG_PAGE)).unwrap();
+            assert_eq!(browser.anchor_line(), Some(0), "{url}");
+        }
+    }
+
+    #[test]
+    fn anchor_in_a_table_points_at_the_table() {
+        let mut browser = browser();
+        let body = "<p>Before</p><table><tr><td>x</td><td id=\"cell\">Cell</td></tr></table>";
+        let lines = browser
+            .render_contents(&page("http://example.com/#cell", body))
+            .unwrap();
+        let anchor = browser.anchor_line().unwrap();
+        assert!(anchor > line_of(&lines, "Before").unwrap());
+        assert!(anchor <= line_of(&lines, "Cell").unwrap());
+    }
+
+    #[test]
+    fn navigating_within_the_page() {
+        let mut browser = browser();
+        browser
+            .render_contents(&page("http://example.com/page", LONG_PAGE))
+            .unwrap();
+        assert_eq!(browser.anchor_line(), None);
+
+        let lines = browser
+            .navigate_within("http://example.com/page#named")
+            .unwrap()
+            .unwrap();
+        assert_eq!(browser.anchor_line(), line_of(&lines, "Named"));
+        assert_eq!(
+            browser.url().map(Url::as_str),
+            Some("http://example.com/page#named")
+        );
+
+        for elsewhere in [
+            "http://example.com/other#named",
+            "http://example.com/page?q=1#named",
+            "http://example.com/page",
+        ] {
+            assert!(browser.navigate_within(elsewhere).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn tables_too_wide_for_the_page_are_stacked() {
+        let mut browser = browser();
+        let html = "<table><tr><th>Name<th>Description\
+            <tr><td>Ada<td>an unbreakablewordthatislong</table>";
+        let lines = browser
+            .render_contents(&page("http://example.com/", html))
+            .unwrap();
+        assert!(line_of(&lines, "│ Ada").is_some());
+
+        let lines = browser.resize(20).unwrap().unwrap();
+        assert!(line_of(&lines, "│").is_none());
+        let name = line_of(&lines, "Name").unwrap();
+        assert_eq!(lines[name + 1].text().trim_end(), "  Ada");
+        let description = line_of(&lines, "Description").unwrap();
+        assert!(lines[description + 1].text().starts_with("  an"));
+    }
+
+    #[test]
+    fn linked_stylesheets_apply_once_they_arrive() {
+        let mut browser = browser();
+        let html = "<link rel=stylesheet href=screen.css>\
+            <link rel=stylesheet href=print.css media=print>\
+            <p class=gone>Hidden</p><p>Shown</p>";
+        let lines = browser
+            .render_contents(&page("http://example.com/dir/page", html))
+            .unwrap();
+        assert!(line_of(&lines, "Hidden").is_some());
+
+        let subresources = browser.subresources();
+        assert_eq!(
+            subresources,
+            [Subresource {
+                kind: SubresourceKind::Stylesheet,
+                url: String::from("http://example.com/dir/screen.css"),
+            }]
+        );
+
+        let mut sheet = page("http://example.com/moved.css", ".gone { display: none }");
+        sheet.content_type = Some(String::from("text/css"));
+        let lines = browser
+            .add_subresource(&subresources[0], &sheet)
+            .unwrap()
+            .unwrap();
+        assert!(line_of(&lines, "Hidden").is_none());
+        assert!(line_of(&lines, "Shown").is_some());
+    }
+
+    #[test]
+    fn images_are_drawn_once_they_arrive() {
+        fn png(size: u32) -> Response {
+            let mut body = Vec::new();
+            DynamicImage::new_rgb8(size, size)
+                .write_to(
+                    &mut std::io::Cursor::new(&mut body),
+                    image::ImageFormat::Png,
+                )
+                .unwrap();
+            let mut response = page("http://example.com/picture.png", "");
+            response.content_type = Some(String::from("image/png"));
+            response.body = body;
+            response
+        }
+
+        let mut browser = browser();
+        let html = "<img src=a.png alt=Cat><img src=a.png><img src=pixel.png>";
+        let lines = browser
+            .render_contents(&page("http://example.com/", html))
+            .unwrap();
+        assert!(!lines.iter().any(|line| line.text().contains('▀')));
+
+        let subresources = browser.subresources();
+        let urls: Vec<&str> = subresources.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            ["http://example.com/a.png", "http://example.com/pixel.png"]
+        );
+        assert!(subresources
+            .iter()
+            .all(|s| s.kind == SubresourceKind::Image));
+
+        // Too small to be worth showing
+        assert!(browser
+            .add_subresource(&subresources[1], &png(1))
+            .unwrap()
+            .is_none());
+        let lines = browser
+            .add_subresource(&subresources[0], &png(16))
+            .unwrap()
+            .unwrap();
+        assert!(lines.iter().any(|line| line.text().contains('▀')));
     }
 }
@@ -0,0 +1,591 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::net::Hop;
+
+// Keep roughly this many bytes of response bodies in memory before evicting
+const MEMORY_LIMIT: usize = 32 * 1024 * 1024;
+// And this many on disk, dropping the least recently used first
+const DISK_LIMIT: u64 = 256 * 1024 * 1024;
+// Upper bound for the Last-Modified freshness heuristic (RFC 9111 §4.2.2)
+const MAX_HEURISTIC_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub url: String,
+    /// Where the redirects from `url` led, if there were any
+    #[serde(default)]
+    pub final_url: Option<String>,
+    #[serde(default)]
+    pub redirects: Vec<Hop>,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub expires_at: Option<SystemTime>,
+    pub must_revalidate: bool,
+    /// The request headers named by the response's Vary, as they were sent
+    #[serde(default)]
+    pub vary: Vec<(String, Option<String>)>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        if self.must_revalidate {
+            return false;
+        }
+
+        match self.expires_at {
+            Some(expires) => now < expires,
+            None => false,
+        }
+    }
+
+    /// Headers for a conditional request that revalidates this entry. They
+    /// describe the final response, so an entry reached through redirects
+    /// is fetched again in full.
+    pub fn validators(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if !self.redirects.is_empty() {
+            return headers;
+        }
+        let values = [
+            (header::IF_NONE_MATCH, &self.etag),
+            (header::IF_MODIFIED_SINCE, &self.last_modified),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        headers
+    }
+
+    /// Whether a request with `request` headers would have got this response
+    pub fn matches(&self, request: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_value(request, name) == *value)
+    }
+
+    fn update_freshness(&mut self, headers: &HeaderMap, now: SystemTime) {
+        let directives = CacheControl::parse(headers);
+        self.must_revalidate = directives.no_cache;
+        self.expires_at = expiry(headers, &directives, self.last_modified.as_deref(), now);
+    }
+}
+
+#[derive(Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut result = Self::default();
+
+        for value in headers.get_all(header::CACHE_CONTROL) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+
+            for directive in value.split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                    None => (directive.as_str(), None),
+                };
+
+                match name {
+                    "no-store" => result.no_store = true,
+                    "no-cache" => result.no_cache = true,
+                    "max-age" => result.max_age = arg.and_then(|a| a.parse().ok()),
+                    _ => {}
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// The header names in Vary, or None for `Vary: *`, which matches no request
+fn vary_names(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    for value in headers.get_all(header::VARY) {
+        for name in value.to_str().unwrap_or_default().split(',') {
+            let name = name.trim().to_ascii_lowercase();
+            match name.as_str() {
+                "*" => return None,
+                "" => {}
+                _ => names.push(name),
+            }
+        }
+    }
+    Some(names)
+}
+
+fn request_value(request: &HeaderMap, name: &str) -> Option<String> {
+    request
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+fn header_string(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+fn expiry(
+    headers: &HeaderMap,
+    directives: &CacheControl,
+    last_modified: Option<&str>,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    // How long the response had already been in caches on the way here
+    let age = headers
+        .get(header::AGE)
+        .and_then(|a| a.to_str().ok())
+        .and_then(|a| a.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default();
+
+    // max-age takes precedence over Expires (RFC 9111 §5.3)
+    if let Some(max_age) = directives.max_age {
+        return Some(now + Duration::from_secs(max_age).saturating_sub(age));
+    }
+
+    if let Some(expires) = headers.get(header::EXPIRES) {
+        // An invalid Expires value (often "0") means "already expired"
+        return Some(
+            expires
+                .to_str()
+                .ok()
+                .and_then(|e| httpdate::parse_http_date(e).ok())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        );
+    }
+
+    let modified = httpdate::parse_http_date(last_modified?).ok()?;
+    let unchanged = now.duration_since(modified).ok()?;
+    Some(
+        now + (unchanged / 10)
+            .min(MAX_HEURISTIC_LIFETIME)
+            .saturating_sub(age),
+    )
+}
+
+struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    fn key(url: &str) -> String {
+        // FNV-1a, so that file names stay stable across builds
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in url.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{hash:016x}")
+    }
+
+    fn load(&self, url: &str) -> Option<CacheEntry> {
+        let key = Self::key(url);
+        let meta = std::fs::read(self.dir.join(format!("{key}.json"))).ok()?;
+        let mut entry: CacheEntry = serde_json::from_slice(&meta).ok()?;
+        // Guard against hash collisions
+        if entry.url != url {
+            return None;
+        }
+        let body = self.dir.join(format!("{key}.body"));
+        entry.body = std::fs::read(&body).ok()?;
+        // The modification time is what `prune` goes by
+        if let Ok(file) = std::fs::File::options().append(true).open(&body) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(entry)
+    }
+
+    fn save(&self, entry: &CacheEntry) -> Result<(), std::io::Error> {
+        let key = Self::key(&entry.url);
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(format!("{key}.body")), &entry.body)?;
+        std::fs::write(
+            self.dir.join(format!("{key}.json")),
+            serde_json::to_vec(entry)?,
+        )?;
+        self.prune()
+    }
+
+    /// Remove the least recently used entries until the bodies fit in `DISK_LIMIT`
+    fn prune(&self) -> Result<(), std::io::Error> {
+        let mut bodies = Vec::new();
+        for file in std::fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|e| e == "body") {
+                let meta = std::fs::metadata(&path)?;
+                bodies.push((meta.modified()?, meta.len(), path));
+            }
+        }
+
+        let mut total: u64 = bodies.iter().map(|(_, len, _)| len).sum();
+        bodies.sort();
+        for (_, len, path) in bodies {
+            if total <= DISK_LIMIT {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            let _ = std::fs::remove_file(path.with_extension("json"));
+            total -= len;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, url: &str) {
+        let key = Self::key(url);
+        let _ = std::fs::remove_file(self.dir.join(format!("{key}.json")));
+        let _ = std::fs::remove_file(self.dir.join(format!("{key}.body")));
+    }
+}
+
+pub struct HttpCache {
+    memory: HashMap<String, CacheEntry>,
+    order: VecDeque<String>,
+    memory_size: usize,
+    disk: Option<DiskStore>,
+}
+
+impl HttpCache {
+    pub fn new(disk_dir: Option<PathBuf>) -> Self {
+        Self {
+            memory: HashMap::new(),
+            order: VecDeque::new(),
+            memory_size: 0,
+            disk: disk_dir.map(|dir| DiskStore { dir }),
+        }
+    }
+
+    /// The entry for `url`, if it was stored for a request like `request`
+    pub fn get(&mut self, url: &str, request: &HeaderMap) -> Option<&CacheEntry> {
+        self.lookup(url).filter(|entry| entry.matches(request))
+    }
+
+    /// Store the response to a GET of `url` that was sent with `request`
+    /// headers and answered from `final_url` after following `redirects`
+    #[allow(clippy::too_many_arguments)]
+    pub fn store(
+        &mut self,
+        url: &str,
+        request: &HeaderMap,
+        final_url: &str,
+        redirects: &[Hop],
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) {
+        // Errors other than these say nothing about the stored copy, which
+        // is still worth having for the offline fallback
+        if matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE) {
+            self.remove(url);
+            return;
+        }
+        if status != StatusCode::OK {
+            return;
+        }
+
+        let directives = CacheControl::parse(headers);
+        let Some(vary) = vary_names(headers).filter(|_| !directives.no_store) else {
+            // This response replaces the stored one, but may not be kept itself
+            self.remove(url);
+            return;
+        };
+
+        let now = SystemTime::now();
+        let mut entry = CacheEntry {
+            url: url.to_string(),
+            final_url: Some(final_url).filter(|f| *f != url).map(String::from),
+            redirects: redirects.to_vec(),
+            status: status.as_u16(),
+            content_type: header_string(headers, header::CONTENT_TYPE),
+            etag: header_string(headers, header::ETAG),
+            last_modified: header_string(headers, header::LAST_MODIFIED),
+            expires_at: None,
+            must_revalidate: false,
+            vary: vary
+                .into_iter()
+                .map(|name| {
+                    let value = request_value(request, &name);
+                    (name, value)
+                })
+                .collect(),
+            body: body.to_vec(),
+        };
+        entry.update_freshness(headers, now);
+
+        self.persist(&entry);
+        self.insert_memory(entry);
+    }
+
+    /// Refresh a stored entry after the server confirmed it with a 304
+    pub fn revalidate(&mut self, url: &str, headers: &HeaderMap) -> Option<&CacheEntry> {
+        self.lookup(url)?;
+        let now = SystemTime::now();
+
+        let entry = self.memory.get_mut(url)?;
+        if let Some(etag) = header_string(headers, header::ETAG) {
+            entry.etag = Some(etag);
+        }
+        if let Some(modified) = header_string(headers, header::LAST_MODIFIED) {
+            entry.last_modified = Some(modified);
+        }
+        entry.update_freshness(headers, now);
+
+        if let Some(disk) = &self.disk {
+            if let Err(err) = disk.save(entry) {
+                tracing::warn!("could not update cache entry for {url}: {err}");
+            }
+        }
+
+        self.memory.get(url)
+    }
+
+    fn lookup(&mut self, url: &str) -> Option<&CacheEntry> {
+        if self.memory.contains_key(url) {
+            // Used just now, so it's the last to be evicted
+            if let Some(pos) = self.order.iter().position(|u| u == url) {
+                let used = self.order.remove(pos)?;
+                self.order.push_back(used);
+            }
+        } else {
+            let entry = self.disk.as_ref()?.load(url)?;
+            self.insert_memory(entry);
+        }
+
+        self.memory.get(url)
+    }
+
+    fn persist(&self, entry: &CacheEntry) {
+        if let Some(disk) = &self.disk {
+            if let Err(err) = disk.save(entry) {
+                tracing::warn!("could not write cache entry for {}: {err}", entry.url);
+            }
+        }
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some(old) = self.memory.remove(url) {
+            self.memory_size -= old.body.len();
+            self.order.retain(|u| u != url);
+        }
+        if let Some(disk) = &self.disk {
+            disk.remove(url);
+        }
+    }
+
+    fn insert_memory(&mut self, entry: CacheEntry) {
+        if let Some(old) = self.memory.remove(&entry.url) {
+            self.memory_size -= old.body.len();
+            self.order.retain(|u| *u != entry.url);
+        }
+
+        self.memory_size += entry.body.len();
+        self.order.push_back(entry.url.clone());
+        self.memory.insert(entry.url.clone(), entry);
+
+        while self.memory_size > MEMORY_LIMIT && self.order.len() > 1 {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(old) = self.memory.remove(&oldest) {
+                self.memory_size -= old.body.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn expires_in(pairs: &[(&str, &str)], last_modified: Option<&str>) -> Option<Duration> {
+        let headers = headers(pairs);
+        let now = SystemTime::now();
+        let expires = expiry(&headers, &CacheControl::parse(&headers), last_modified, now)?;
+        Some(expires.duration_since(now).unwrap_or_default())
+    }
+
+    fn store(cache: &mut HttpCache, url: &str, status: StatusCode, pairs: &[(&str, &str)]) {
+        cache.store(
+            url,
+            &HeaderMap::new(),
+            url,
+            &[],
+            status,
+            &headers(pairs),
+            b"body",
+        );
+    }
+
+    #[test]
+    fn cache_control_directives() {
+        let directives = CacheControl::parse(&headers(&[
+            ("cache-control", "No-Cache, max-age=\"60\""),
+            ("cache-control", "no-store"),
+        ]));
+        assert!(directives.no_cache);
+        assert!(directives.no_store);
+        assert_eq!(directives.max_age, Some(60));
+
+        let directives = CacheControl::parse(&headers(&[("cache-control", "max-age=soon")]));
+        assert!(!directives.no_cache && !directives.no_store);
+        assert_eq!(directives.max_age, None);
+    }
+
+    #[test]
+    fn max_age_wins_over_expires() {
+        let pairs = [
+            ("cache-control", "max-age=60"),
+            ("expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+        ];
+        assert_eq!(expires_in(&pairs, None), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn age_counts_against_freshness() {
+        let pairs = [("cache-control", "max-age=60"), ("age", "45")];
+        assert_eq!(expires_in(&pairs, None), Some(Duration::from_secs(15)));
+        let pairs = [("cache-control", "max-age=60"), ("age", "100")];
+        assert_eq!(expires_in(&pairs, None), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_expires_has_already_passed() {
+        let headers = headers(&[("expires", "0")]);
+        let expires = expiry(
+            &headers,
+            &CacheControl::parse(&headers),
+            None,
+            SystemTime::now(),
+        );
+        assert_eq!(expires, Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn heuristic_freshness_from_last_modified() {
+        let ten_hours_ago =
+            httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(36_000));
+        let lifetime = expires_in(&[], Some(&ten_hours_ago)).unwrap();
+        assert!(lifetime > Duration::from_secs(3_599) && lifetime < Duration::from_secs(3_601));
+
+        // Capped however long ago it changed
+        let long_ago = "Thu, 01 Jan 1970 00:00:00 GMT";
+        assert_eq!(
+            expires_in(&[], Some(long_ago)),
+            Some(MAX_HEURISTIC_LIFETIME)
+        );
+        assert_eq!(expires_in(&[], None), None);
+    }
+
+    #[test]
+    fn vary_star_matches_nothing() {
+        assert_eq!(vary_names(&headers(&[("vary", "Accept, *")])), None);
+        assert_eq!(
+            vary_names(&headers(&[("vary", "Accept-Language, ,Accept")])),
+            Some(vec![
+                String::from("accept-language"),
+                String::from("accept")
+            ])
+        );
+
+        let mut cache = HttpCache::new(None);
+        store(&mut cache, "http://a/", StatusCode::OK, &[("vary", "*")]);
+        assert!(cache.get("http://a/", &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn entries_match_the_varying_request_headers() {
+        let mut cache = HttpCache::new(None);
+        let english = headers(&[("accept-language", "en")]);
+        cache.store(
+            "http://a/",
+            &english,
+            "http://a/",
+            &[],
+            StatusCode::OK,
+            &headers(&[("vary", "Accept-Language")]),
+            b"hello",
+        );
+
+        assert!(cache.get("http://a/", &english).is_some());
+        let french = headers(&[("accept-language", "fr")]);
+        assert!(cache.get("http://a/", &french).is_none());
+        assert!(cache.get("http://a/", &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn gone_resources_and_no_store_drop_the_entry() {
+        for status in [StatusCode::NOT_FOUND, StatusCode::GONE] {
+            let mut cache = HttpCache::new(None);
+            store(&mut cache, "http://a/", StatusCode::OK, &[]);
+            store(&mut cache, "http://a/", status, &[]);
+            assert!(
+                cache.get("http://a/", &HeaderMap::new()).is_none(),
+                "{status}"
+            );
+        }
+
+        let mut cache = HttpCache::new(None);
+        store(&mut cache, "http://a/", StatusCode::OK, &[]);
+        store(
+            &mut cache,
+            "http://a/",
+            StatusCode::OK,
+            &[("cache-control", "no-store")],
+        );
+        assert!(cache.get("http://a/", &HeaderMap::new()).is_none());
+
+        // Other errors keep the stored copy for the offline fallback
+        store(&mut cache, "http://b/", StatusCode::OK, &[]);
+        store(
+            &mut cache,
+            "http://b/",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &[],
+        );
+        assert!(cache.get("http://b/", &HeaderMap::new()).is_some());
+    }
+
+    #[test]
+    fn lookups_keep_entries_from_eviction() {
+        let mut cache = HttpCache::new(None);
+        for url in ["http://a/", "http://b/"] {
+            store(&mut cache, url, StatusCode::OK, &[]);
+        }
+        cache.get("http://a/", &HeaderMap::new());
+        assert_eq!(cache.order, ["http://b/", "http://a/"]);
+    }
+}
@@ -0,0 +1,283 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+// How much of the document to prescan for a <meta> declaration (per the HTML spec)
+const PRESCAN_LIMIT: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confidence {
+    /// The encoding came from a BOM or the HTTP headers and must not be overridden.
+    Certain,
+    /// The encoding was guessed, so a <meta> found while parsing may replace it.
+    Tentative,
+}
+
+/// Pick the encoding for an HTML document following the order of the
+/// HTML spec's encoding sniffing algorithm: BOM, transport layer, <meta>
+/// prescan, and finally a statistical guess.
+pub fn sniff(content_type: Option<&str>, body: &[u8]) -> (&'static Encoding, Confidence) {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return (encoding, Confidence::Certain);
+    }
+
+    if let Some(encoding) = content_type.and_then(from_content_type) {
+        return (encoding, Confidence::Certain);
+    }
+
+    if let Some(encoding) = prescan(&body[..body.len().min(PRESCAN_LIMIT)]) {
+        return (encoding, Confidence::Tentative);
+    }
+
+    (guess(body), Confidence::Tentative)
+}
+
+/// Decode text which isn't HTML, so only the BOM and headers are meaningful.
+pub fn sniff_text(content_type: Option<&str>, body: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+
+    content_type
+        .and_then(from_content_type)
+        .unwrap_or_else(|| guess(body))
+}
+
+pub fn decode(body: &[u8], encoding: &'static Encoding) -> String {
+    let (text, actual, had_errors) = encoding.decode(body);
+    if had_errors {
+        tracing::warn!("malformed input while decoding as {}", actual.name());
+    }
+    text.into_owned()
+}
+
+pub fn from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+/// Resolve a label found in a <meta> tag, applying the substitutions the
+/// spec requires for encodings that can't be declared from inside the document.
+pub fn from_meta_label(label: &str) -> Option<&'static Encoding> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())?;
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        Some(UTF_8)
+    } else if encoding == X_USER_DEFINED {
+        Some(WINDOWS_1252)
+    } else {
+        Some(encoding)
+    }
+}
+
+fn guess(body: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(body).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(body, true);
+    detector.guess(None, true)
+}
+
+/// Extract the charset from the value of a `content` attribute on a
+/// `<meta http-equiv="content-type">` tag.
+pub fn charset_from_meta_content(content: &str) -> Option<&str> {
+    let lower = content.to_ascii_lowercase();
+    let start = lower.find("charset")? + "charset".len();
+    let rest = content[start..].trim_start();
+    let rest = rest.strip_prefix('=')?.trim_start();
+
+    let value = match rest.chars().next()? {
+        quote @ ('"' | '\'') => rest[1..].split(quote).next()?,
+        _ => rest
+            .split(|c: char| c == ';' || c.is_ascii_whitespace())
+            .next()?,
+    };
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// A simplified version of the spec's prescan: good enough for the
+// <meta charset> and <meta http-equiv> forms that appear in practice.
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let text = String::from_utf8_lossy(bytes);
+    let lower = text.to_ascii_lowercase();
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<meta") {
+        let start = pos + offset + "<meta".len();
+        let end = lower[start..].find('>').map_or(lower.len(), |e| start + e);
+        pos = end;
+
+        let attrs = parse_attributes(&text[start..end]);
+        let get = |name: &str| {
+            attrs
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+
+        if let Some(label) = get("charset") {
+            if let Some(encoding) = from_meta_label(label) {
+                return Some(encoding);
+            }
+        }
+
+        let is_content_type =
+            get("http-equiv").is_some_and(|v| v.eq_ignore_ascii_case("content-type"));
+        if is_content_type {
+            if let Some(encoding) = get("content")
+                .and_then(charset_from_meta_content)
+                .and_then(from_meta_label)
+            {
+                return Some(encoding);
+            }
+        }
+    }
+
+    None
+}
+
+fn skip_while(chars: &mut Peekable<CharIndices>, pred: impl Fn(char) -> bool) {
+    while chars.next_if(|&(_, c)| pred(c)).is_some() {}
+}
+
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut chars = tag.char_indices().peekable();
+
+    loop {
+        skip_while(&mut chars, |c| c.is_whitespace() || c == '/');
+        let Some(&(name_start, _)) = chars.peek() else {
+            break;
+        };
+
+        let mut name_end = tag.len();
+        while let Some(&(i, c)) = chars.peek() {
+            if c == '=' || c.is_whitespace() || c == '/' {
+                name_end = i;
+                break;
+            }
+            chars.next();
+        }
+        let name = tag[name_start..name_end].to_ascii_lowercase();
+
+        skip_while(&mut chars, char::is_whitespace);
+        if chars.next_if(|&(_, c)| c == '=').is_none() {
+            attrs.push((name, String::new()));
+            continue;
+        }
+        skip_while(&mut chars, char::is_whitespace);
+
+        let mut value = String::new();
+        match chars.peek() {
+            Some(&(_, quote @ ('"' | '\''))) => {
+                chars.next();
+                for (_, c) in chars.by_ref() {
+                    if c == quote {
+                        break;
+                    }
+                    value.push(c);
+                }
+            }
+            _ => {
+                while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                    value.push(c);
+                }
+            }
+        }
+
+        attrs.push((name, value));
+    }
+
+    attrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1251};
+
+    #[test]
+    fn prescan_finds_meta_charset() {
+        let html = b"<!doctype html><html><head><meta charset=\"windows-1251\"><title>";
+        assert_eq!(prescan(html), Some(WINDOWS_1251));
+    }
+
+    #[test]
+    fn prescan_finds_http_equiv() {
+        let html = b"<head><META HTTP-EQUIV='Content-Type' CONTENT='text/html; charset=Shift_JIS'>";
+        assert_eq!(prescan(html), Some(SHIFT_JIS));
+    }
+
+    #[test]
+    fn prescan_skips_unknown_labels() {
+        let html = b"<meta charset=bogus><meta charset=koi8-r>";
+        assert_eq!(prescan(html).map(Encoding::name), Some("KOI8-R"));
+    }
+
+    #[test]
+    fn prescan_ignores_other_meta_tags() {
+        let html = b"<meta name=description content=\"charset=shift_jis\">";
+        assert_eq!(prescan(html), None);
+    }
+
+    #[test]
+    fn meta_cannot_declare_utf16() {
+        assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(UTF_8));
+        assert_eq!(
+            prescan(b"<meta charset=x-user-defined>"),
+            Some(WINDOWS_1252)
+        );
+    }
+
+    #[test]
+    fn sniff_prefers_bom_then_headers_then_meta() {
+        let html = b"<meta charset=windows-1251>";
+        let mut with_bom = b"\xEF\xBB\xBF".to_vec();
+        with_bom.extend_from_slice(html);
+
+        assert_eq!(
+            sniff(Some("text/html; charset=shift_jis"), &with_bom),
+            (UTF_8, Confidence::Certain)
+        );
+        assert_eq!(
+            sniff(Some("text/html; charset=shift_jis"), html),
+            (SHIFT_JIS, Confidence::Certain)
+        );
+        assert_eq!(
+            sniff(Some("text/html"), html),
+            (WINDOWS_1251, Confidence::Tentative)
+        );
+    }
+
+    #[test]
+    fn sniff_only_prescans_the_start() {
+        let mut html = vec![b' '; PRESCAN_LIMIT];
+        html.extend_from_slice(b"<meta charset=windows-1251>");
+        assert_eq!(sniff(None, &html), (UTF_8, Confidence::Tentative));
+    }
+
+    #[test]
+    fn charset_from_content_attribute() {
+        assert_eq!(
+            charset_from_meta_content("text/html; charset=\"euc-jp\""),
+            Some("euc-jp")
+        );
+        assert_eq!(
+            charset_from_meta_content("text/html;charset = utf-8; x=y"),
+            Some("utf-8")
+        );
+        assert_eq!(charset_from_meta_content("text/html; charset="), None);
+        assert_eq!(charset_from_meta_content("text/html"), None);
+    }
+}
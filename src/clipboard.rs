@@ -0,0 +1,15 @@
+use std::io::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::error::RetumiError;
+
+/// Put `text` on the clipboard with the OSC 52 escape sequence, which
+/// most terminals understand and which also works over SSH
+pub fn copy(text: &str) -> Result<(), RetumiError> {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()?;
+    Ok(())
+}
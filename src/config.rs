@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::css::Media;
+use crate::error::RetumiError;
+use crate::graphics::ImageMode;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub cache: CacheConfig,
+    pub downloads: DownloadConfig,
+    pub browser: BrowserConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    pub accept_language: String,
+    /// Seconds to wait for a connection to be established
+    pub connect_timeout: u64,
+    /// Seconds to wait between reads of the response body
+    pub read_timeout: u64,
+    pub max_redirects: usize,
+    /// Any proxy URL understood by reqwest, e.g. `http://host:3128` or `socks5h://host:1080`
+    pub proxy: Option<String>,
+    /// PEM file of extra root certificates to trust, e.g. an internal CA
+    pub ca_file: Option<PathBuf>,
+    /// Extra headers sent to a domain and its subdomains, keyed by domain
+    pub headers: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!("retumi/{}", env!("CARGO_PKG_VERSION")),
+            accept_language: String::from("en-US,en;q=0.8"),
+            connect_timeout: 10,
+            read_timeout: 30,
+            max_redirects: 10,
+            proxy: None,
+            ca_file: None,
+            headers: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Keep cached responses on disk so that they survive restarts
+    pub disk: bool,
+    pub dir: Option<PathBuf>,
+}
+
+impl CacheConfig {
+    /// `RETUMI_CACHE_DIR` turns the disk cache on in that directory, whatever the file says
+    pub fn disk_dir(&self) -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("RETUMI_CACHE_DIR") {
+            return Some(PathBuf::from(dir));
+        }
+        if !self.disk {
+            return None;
+        }
+
+        self.dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|d| d.join("retumi")))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    pub dir: Option<PathBuf>,
+}
+
+impl DownloadConfig {
+    /// `RETUMI_DOWNLOAD_DIR` takes precedence over the file
+    pub fn dir(&self) -> PathBuf {
+        std::env::var_os("RETUMI_DOWNLOAD_DIR")
+            .map(PathBuf::from)
+            .or_else(|| self.dir.clone())
+            .or_else(dirs::download_dir)
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrowserConfig {
+    /// Follow `<meta http-equiv="refresh">` once its countdown runs out
+    pub meta_refresh: bool,
+    /// Widest the text of a page may get, in columns. Narrower pages are centered.
+    pub max_width: Option<usize>,
+    /// Style pages as if for `screen` or for `print`
+    pub media: Media,
+    /// `dark`, `light`, `high-contrast`, or the name of a file in the themes directory
+    pub theme: String,
+    /// `auto`, `kitty`, `iterm2`, `sixel`, `blocks` for half-block art, or `off`
+    pub images: ImageMode,
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            meta_refresh: true,
+            max_width: None,
+            media: Media::default(),
+            theme: String::from("dark"),
+            images: ImageMode::default(),
+        }
+    }
+}
+
+const REDACTED: &str = "redacted";
+
+fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [
+        "auth", "cookie", "key", "secret", "token", "session", "password",
+    ]
+    .iter()
+    .any(|word| name.contains(word))
+}
+
+/// Directory for the config file and themes
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .map(|d| d.join("retumi"))
+        .unwrap_or_else(|| PathBuf::from(".retumi"))
+}
+
+/// Directory for what retumi records as it's used, such as bookmarks and trusted hosts
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|d| d.join("retumi"))
+        .unwrap_or_else(|| PathBuf::from(".retumi"))
+}
+
+impl Config {
+    /// A copy that's safe to show: proxy passwords and header values that
+    /// look like credentials are masked
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        if let Some(proxy) = &mut config.http.proxy {
+            if let Ok(mut url) = reqwest::Url::parse(proxy) {
+                if url.password().is_some() && url.set_password(Some(REDACTED)).is_ok() {
+                    *proxy = url.to_string();
+                }
+            }
+        }
+        for headers in config.http.headers.values_mut() {
+            for (name, value) in headers.iter_mut() {
+                if is_secret_header(name) {
+                    *value = String::from(REDACTED);
+                }
+            }
+        }
+        config
+    }
+
+    pub fn path() -> PathBuf {
+        std::env::var_os("RETUMI_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| config_dir().join("config.toml"))
+    }
+
+    pub fn load() -> Result<Self, RetumiError> {
+        let path = Self::path();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                tracing::info!("no config file at {}, using defaults", path.display());
+                return Ok(Self::default());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        toml::from_str(&contents)
+            .map_err(|err| RetumiError::ConfigError(format!("{}: {err}", path.display())))
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tuirealm::props::{Color, TextModifiers, TextSpan};
+
+use crate::charset;
+use crate::download;
+use crate::error::RetumiError;
+use crate::event::Response;
+use crate::gopher;
+use crate::net::Hop;
+use crate::render;
+use crate::theme::Theme;
+
+// Containers nested deeper than this start out folded
+const JSON_UNFOLDED_DEPTH: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentKind {
+    Html,
+    Gemini,
+    GopherMenu,
+    Text,
+    Json,
+    Xml,
+    Image,
+    Binary,
+}
+
+impl ContentKind {
+    pub fn of(response: &Response) -> Self {
+        let Some(mime) = response.mime_type() else {
+            // Without a declared type, only treat obviously binary data as such
+            let head = &response.body[..response.body.len().min(512)];
+            return if head.contains(&0) {
+                ContentKind::Binary
+            } else {
+                ContentKind::Html
+            };
+        };
+
+        Self::from_mime(&mime)
+    }
+
+    pub fn from_mime(mime: &str) -> Self {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "text/html" | "application/xhtml+xml" => ContentKind::Html,
+            "text/gemini" => ContentKind::Gemini,
+            gopher::MENU_MIME => ContentKind::GopherMenu,
+            "application/json" | "text/json" => ContentKind::Json,
+            "application/xml" | "text/xml" => ContentKind::Xml,
+            "application/javascript" | "application/ecmascript" => ContentKind::Text,
+            m if m.ends_with("+json") => ContentKind::Json,
+            m if m.ends_with("+xml") => ContentKind::Xml,
+            m if m.starts_with("text/") => ContentKind::Text,
+            m if m.starts_with("image/") => ContentKind::Image,
+            _ => ContentKind::Binary,
+        }
+    }
+}
+
+fn span(content: String, fg: Color, modifiers: TextModifiers) -> TextSpan {
+    TextSpan {
+        content,
+        fg,
+        bg: Color::Reset,
+        modifiers,
+    }
+}
+
+pub fn decode_text(response: &Response) -> String {
+    let encoding = charset::sniff_text(response.content_type.as_deref(), &response.body);
+    charset::decode(&response.body, encoding)
+}
+
+pub fn render_text(text: &str) -> Vec<TextSpan> {
+    text.lines()
+        .map(|line| TextSpan::new(line.replace('\t', "    ")))
+        .collect()
+}
+
+pub struct JsonView {
+    value: Value,
+    folded: HashSet<usize>,
+    selected: Option<usize>,
+    visible: Vec<usize>,
+}
+
+impl JsonView {
+    pub fn new(value: Value) -> Self {
+        let mut folded = HashSet::new();
+        fold_deep(&value, 0, &mut 0, &mut folded);
+
+        Self {
+            value,
+            folded,
+            selected: None,
+            visible: vec![],
+        }
+    }
+
+    pub fn cycle(&mut self) {
+        self.selected = match self.selected {
+            Some(id) => self
+                .visible
+                .iter()
+                .find(|v| **v > id)
+                .or(self.visible.first())
+                .copied(),
+            None => self.visible.first().copied(),
+        };
+    }
+
+    pub fn toggle(&mut self) {
+        if let Some(id) = self.selected {
+            if !self.folded.remove(&id) {
+                self.folded.insert(id);
+            }
+        }
+    }
+
+    pub fn render(&mut self, theme: &Theme) -> Vec<TextSpan> {
+        let mut lines = vec![];
+        let mut visible = vec![];
+        let mut counter = 0;
+        self.walk(
+            &self.value,
+            None,
+            0,
+            false,
+            theme,
+            &mut counter,
+            &mut lines,
+            &mut visible,
+        );
+        self.visible = visible;
+        lines
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &self,
+        value: &Value,
+        key: Option<&str>,
+        depth: usize,
+        comma: bool,
+        theme: &Theme,
+        counter: &mut usize,
+        lines: &mut Vec<TextSpan>,
+        visible: &mut Vec<usize>,
+    ) {
+        let indent = "  ".repeat(depth);
+        let key = key
+            .map(|k| format!("{}: ", Value::String(k.to_string())))
+            .unwrap_or_default();
+        let comma = if comma { "," } else { "" };
+
+        let (open, close, children): (_, _, Vec<(Option<&str>, &Value)>) = match value {
+            Value::Array(items) => ("[", "]", items.iter().map(|v| (None, v)).collect()),
+            Value::Object(map) => (
+                "{",
+                "}",
+                map.iter().map(|(k, v)| (Some(k.as_str()), v)).collect(),
+            ),
+            scalar => {
+                let style = match scalar {
+                    Value::String(_) => theme.json_string,
+                    Value::Number(_) => theme.json_number,
+                    _ => theme.json_literal,
+                };
+                lines.push(render::span(format!("{indent}{key}{scalar}{comma}"), style));
+                return;
+            }
+        };
+
+        let id = *counter;
+        *counter += 1;
+
+        if children.is_empty() {
+            lines.push(render::span(
+                format!("{indent}{key}{open}{close}{comma}"),
+                theme.json_key,
+            ));
+            return;
+        }
+
+        visible.push(id);
+        let style = if self.selected == Some(id) {
+            theme
+                .json_key
+                .add_modifier(TextModifiers::REVERSED | TextModifiers::BOLD)
+        } else {
+            theme.json_key.add_modifier(TextModifiers::BOLD)
+        };
+
+        if self.folded.contains(&id) {
+            let noun = if open == "[" { "items" } else { "keys" };
+            lines.push(render::span(
+                format!(
+                    "{indent}{key}{open} … {} {noun} {close}{comma}",
+                    children.len()
+                ),
+                style,
+            ));
+            *counter += children
+                .iter()
+                .map(|(_, v)| count_containers(v))
+                .sum::<usize>();
+            return;
+        }
+
+        lines.push(render::span(format!("{indent}{key}{open}"), style));
+        let last = children.len() - 1;
+        for (i, (child_key, child)) in children.into_iter().enumerate() {
+            self.walk(
+                child,
+                child_key,
+                depth + 1,
+                i != last,
+                theme,
+                counter,
+                lines,
+                visible,
+            );
+        }
+        lines.push(TextSpan::new(format!("{indent}{close}{comma}")));
+    }
+}
+
+fn fold_deep(value: &Value, depth: usize, counter: &mut usize, folded: &mut HashSet<usize>) {
+    let children: Vec<&Value> = match value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(map) => map.values().collect(),
+        _ => return,
+    };
+
+    if depth >= JSON_UNFOLDED_DEPTH && !children.is_empty() {
+        folded.insert(*counter);
+    }
+    *counter += 1;
+
+    for child in children {
+        fold_deep(child, depth + 1, counter, folded);
+    }
+}
+
+fn count_containers(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(count_containers).sum::<usize>(),
+        Value::Object(map) => 1 + map.values().map(count_containers).sum::<usize>(),
+        _ => 0,
+    }
+}
+
+pub fn render_xml(text: &str, theme: &Theme) -> Result<Vec<TextSpan>, roxmltree::Error> {
+    let document = roxmltree::Document::parse(text)?;
+    let mut lines = vec![];
+
+    fn walker(
+        node: roxmltree::Node,
+        prefix: &str,
+        last: bool,
+        theme: &Theme,
+        lines: &mut Vec<TextSpan>,
+    ) {
+        let branch = if last { "└── " } else { "├── " };
+
+        if node.is_element() {
+            let mut tag = format!("<{}", node.tag_name().name());
+            for attr in node.attributes() {
+                tag.push_str(&format!(" {}=\"{}\"", attr.name(), attr.value()));
+            }
+            tag.push('>');
+            lines.push(render::span(
+                format!("{prefix}{branch}{tag}"),
+                theme.xml_tag,
+            ));
+        } else if let Some(text) = node.text() {
+            lines.push(TextSpan::new(format!("{prefix}{branch}{}", text.trim())));
+        }
+
+        let children: Vec<_> = node
+            .children()
+            .filter(|c| c.is_element() || c.text().is_some_and(|t| !t.trim().is_empty()))
+            .collect();
+        let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+        let count = children.len();
+        for (i, child) in children.into_iter().enumerate() {
+            walker(child, &prefix, i + 1 == count, theme, lines);
+        }
+    }
+
+    let root = document.root_element();
+    walker(root, "", true, theme, &mut lines);
+    Ok(lines)
+}
+
+pub fn render_image_info(response: &Response, saved: Option<&PathBuf>) -> Vec<TextSpan> {
+    let reader = image::ImageReader::new(std::io::Cursor::new(&response.body))
+        .with_guessed_format()
+        .ok();
+    let format = reader
+        .as_ref()
+        .and_then(|r| r.format())
+        .map(|f| format!("{f:?}"))
+        .unwrap_or_else(|| String::from("unknown"));
+    let dimensions = reader
+        .and_then(|r| r.into_dimensions().ok())
+        .map(|(w, h)| format!("{w} × {h} pixels"))
+        .unwrap_or_else(|| String::from("unknown"));
+
+    let mut lines = vec![
+        span(
+            String::from("Image"),
+            Color::Reset,
+            TextModifiers::BOLD | TextModifiers::UNDERLINED,
+        ),
+        TextSpan::new(String::new()),
+        TextSpan::new(format!("Format:     {format}")),
+        TextSpan::new(format!("Dimensions: {dimensions}")),
+    ];
+    lines.extend(resource_info(response, saved));
+    lines
+}
+
+pub fn render_binary_info(response: &Response, saved: Option<&PathBuf>) -> Vec<TextSpan> {
+    let mut lines = vec![
+        span(
+            String::from("This resource can't be displayed"),
+            Color::Reset,
+            TextModifiers::BOLD | TextModifiers::UNDERLINED,
+        ),
+        TextSpan::new(String::new()),
+    ];
+    lines.extend(resource_info(response, saved));
+    lines
+}
+
+/// The redirect chain that led to `url`, shown above the page
+pub fn render_redirects(url: &str, hops: &[Hop], theme: &Theme) -> Vec<TextSpan> {
+    let mut lines = vec![render::span(
+        format!("Redirected {} time(s), press r to hide", hops.len()),
+        theme.redirect.add_modifier(TextModifiers::BOLD),
+    )];
+    for hop in hops {
+        lines.push(TextSpan::new(format!("  {}  {}", hop.status, hop.url)));
+    }
+    lines.push(render::span(format!("  → {url}"), theme.redirect));
+    lines.push(TextSpan::new(String::new()));
+    lines
+}
+
+fn resource_info(response: &Response, saved: Option<&PathBuf>) -> Vec<TextSpan> {
+    let mime = response
+        .mime_type()
+        .unwrap_or_else(|| String::from("unknown"));
+    let mut lines = vec![
+        TextSpan::new(format!("Type:       {mime}")),
+        TextSpan::new(format!("Size:       {}", format_size(response.body.len()))),
+        TextSpan::new(format!("URL:        {}", response.url)),
+        TextSpan::new(String::new()),
+    ];
+
+    match saved {
+        Some(path) => lines.push(span(
+            format!("Saved to {}", path.display()),
+            Color::Green,
+            TextModifiers::empty(),
+        )),
+        None => lines.push(span(
+            format!("Press s to save as {}", file_name(&response.url)),
+            Color::Reset,
+            TextModifiers::ITALIC,
+        )),
+    }
+
+    lines
+}
+
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// The name to save `url` as: its last path segment, decoded, with anything
+/// that could take it out of the download directory replaced
+pub fn file_name(url: &str) -> String {
+    let segment = reqwest::Url::parse(url).ok().and_then(|u| {
+        u.path_segments()?.rev().find(|s| !s.is_empty()).map(|s| {
+            percent_encoding::percent_decode_str(s)
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+    });
+    let name: String = segment
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // No "..", hidden files, or names Windows would quietly change
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() {
+        String::from("download")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Write a response body into `dir` without clobbering existing files.
+pub fn save(response: &Response, dir: &Path) -> Result<PathBuf, RetumiError> {
+    let path = download::reserve(dir, &response.url, |_| false)?;
+    std::fs::write(&path, &response.body)?;
+    Ok(path)
+}
@@ -0,0 +1,953 @@
+use std::rc::Rc;
+
+use html2text::markup5ever_rcdom::{Handle, NodeData, RcDom};
+use html5ever::{local_name, namespace_url, ns, Attribute, QualName};
+use serde::{Deserialize, Serialize};
+use tuirealm::props::{Color, Style};
+use tuirealm::ratatui::style::Modifier;
+
+use crate::doc;
+
+/// Styled elements get their contents wrapped in a link to this, so that
+/// the renderer tells us which text they cover. Not a real link.
+pub const STYLE_HREF: &str = "x-retumi-style:";
+
+/// Media queries see each column as this many pixels wide
+const COLUMN_PX: f32 = 8.0;
+
+/// Which media type pages are styled for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Media {
+    #[default]
+    Screen,
+    Print,
+}
+
+/// What media queries are asked about: the media type, and how many columns
+/// wide the page is laid out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub media: Media,
+    pub columns: usize,
+}
+
+/// Whether a media query list, as in `@media` or a `media` attribute, applies
+pub fn media_matches(list: &str, viewport: Viewport) -> bool {
+    if list.trim().is_empty() {
+        return true;
+    }
+
+    list.split(',').any(|query| {
+        let query = query.trim().to_ascii_lowercase();
+        let (negated, query) = match query.strip_prefix("not ") {
+            Some(rest) => (true, rest),
+            None => (false, query.strip_prefix("only ").unwrap_or(&query)),
+        };
+        let matched = query.split(" and ").map(str::trim).all(|part| {
+            match part.strip_prefix('(').and_then(|p| p.strip_suffix(')')) {
+                Some(feature) => feature_matches(feature, viewport.columns),
+                None => matches!(
+                    (part, viewport.media),
+                    ("all", _) | ("screen", Media::Screen) | ("print", Media::Print)
+                ),
+            }
+        });
+        matched != negated
+    })
+}
+
+fn feature_matches(feature: &str, columns: usize) -> bool {
+    let Some((name, value)) = feature.split_once(':') else {
+        // Boolean features: a terminal has colour, but nothing else we know of
+        return feature.trim() == "color";
+    };
+    let value = value.trim();
+    let px = value
+        .strip_suffix("px")
+        .and_then(|v| v.parse::<f32>().ok())
+        .or_else(|| {
+            let em = value
+                .strip_suffix("rem")
+                .or_else(|| value.strip_suffix("em"))?;
+            em.parse::<f32>().ok().map(|em| em * 16.0)
+        });
+
+    let width = columns as f32 * COLUMN_PX;
+    match (name.trim(), px) {
+        ("min-width", Some(px)) => width >= px,
+        ("max-width", Some(px)) => width <= px,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Display {
+    None,
+    Block,
+    Inline,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// The properties we understand, as declared for one element.
+/// Inherited properties come from the styles of enclosing elements
+/// being applied first, since their text is nested the same way.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ElementStyle {
+    pub color: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub display: Option<Display>,
+    pub white_space: Option<WhiteSpace>,
+    pub text_align: Option<TextAlign>,
+    /// Headings are styled by the theme, underneath their CSS
+    pub heading: Option<usize>,
+}
+
+impl ElementStyle {
+    fn set(&mut self, property: &str, value: &str) {
+        let value = value.trim().to_ascii_lowercase();
+        match property {
+            "color" => self.color = parse_color(&value).or(self.color),
+            "background-color" => self.background = parse_color(&value).or(self.background),
+            // Of the shorthand, only the color matters to us
+            "background" => {
+                if let Some(color) = value.split_whitespace().find_map(parse_color) {
+                    self.background = Some(color);
+                }
+            }
+            "font-weight" => {
+                self.bold = match value.as_str() {
+                    "bold" | "bolder" => Some(true),
+                    "normal" | "lighter" => Some(false),
+                    weight => weight.parse::<u32>().ok().map(|w| w >= 600),
+                }
+                .or(self.bold)
+            }
+            "font-style" => {
+                self.italic = match value.as_str() {
+                    "italic" | "oblique" => Some(true),
+                    "normal" => Some(false),
+                    _ => self.italic,
+                }
+            }
+            "text-decoration" | "text-decoration-line" => {
+                if value.split_whitespace().any(|v| v == "none") {
+                    self.underline = Some(false);
+                    self.strikethrough = Some(false);
+                }
+                if value.contains("underline") {
+                    self.underline = Some(true);
+                }
+                if value.contains("line-through") {
+                    self.strikethrough = Some(true);
+                }
+            }
+            "display" => {
+                self.display = Some(match value.as_str() {
+                    "none" => Display::None,
+                    "block" | "flex" | "grid" | "flow-root" => Display::Block,
+                    "inline" | "inline-block" | "inline-flex" => Display::Inline,
+                    _ => Display::Other,
+                })
+            }
+            "white-space" => {
+                self.white_space = Some(match value.as_str() {
+                    "pre" | "pre-wrap" | "break-spaces" => WhiteSpace::Pre,
+                    _ => WhiteSpace::Normal,
+                })
+            }
+            "text-align" => {
+                self.text_align = match value.as_str() {
+                    "center" => Some(TextAlign::Center),
+                    "right" | "end" => Some(TextAlign::Right),
+                    "left" | "start" | "justify" => Some(TextAlign::Left),
+                    _ => self.text_align,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether anything here changes how text looks, rather than layout
+    fn is_visual(&self) -> bool {
+        self.color.is_some()
+            || self.background.is_some()
+            || self.bold.is_some()
+            || self.italic.is_some()
+            || self.underline.is_some()
+            || self.strikethrough.is_some()
+            || self.text_align.is_some()
+            || self.heading.is_some()
+    }
+
+    /// `style` with these properties on top
+    pub fn apply(&self, mut style: Style) -> Style {
+        if let Some(color) = self.color {
+            style = style.fg(color);
+        }
+        if let Some(color) = self.background {
+            style = style.bg(color);
+        }
+        for (flag, modifier) in [
+            (self.bold, Modifier::BOLD),
+            (self.italic, Modifier::ITALIC),
+            (self.underline, Modifier::UNDERLINED),
+            (self.strikethrough, Modifier::CROSSED_OUT),
+        ] {
+            style = match flag {
+                Some(true) => style.add_modifier(modifier),
+                Some(false) => style.remove_modifier(modifier),
+                None => style,
+            };
+        }
+        style
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Declaration {
+    property: String,
+    value: String,
+    important: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    /// Attribute names, and the value they must have if any
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    /// Returns `None` for anything we don't support, such as pseudo-classes
+    fn parse(text: &str) -> Option<Self> {
+        let mut compound = Compound::default();
+        let mut rest = text;
+        let name_end = |s: &str| {
+            s.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                .unwrap_or(s.len())
+        };
+
+        if let Some(after) = rest.strip_prefix('*') {
+            rest = after;
+        } else {
+            let end = name_end(rest);
+            if end > 0 {
+                compound.tag = Some(rest[..end].to_ascii_lowercase());
+                rest = &rest[end..];
+            }
+        }
+
+        while let Some(c) = rest.chars().next() {
+            match c {
+                '.' | '#' => {
+                    let end = name_end(&rest[1..]) + 1;
+                    let name = rest[1..end].to_string();
+                    if name.is_empty() {
+                        return None;
+                    }
+                    if c == '.' {
+                        compound.classes.push(name);
+                    } else {
+                        compound.id = Some(name);
+                    }
+                    rest = &rest[end..];
+                }
+                '[' => {
+                    let mut quote = None;
+                    let (end, _) = rest.char_indices().find(|&(_, c)| match quote {
+                        Some(q) => {
+                            if c == q {
+                                quote = None;
+                            }
+                            false
+                        }
+                        None if c == '"' || c == '\'' => {
+                            quote = Some(c);
+                            false
+                        }
+                        None => c == ']',
+                    })?;
+                    let inner = &rest[1..end];
+                    let attr = match inner.split_once('=') {
+                        Some((name, value)) => (
+                            name.trim().to_ascii_lowercase(),
+                            Some(value.trim().trim_matches(['"', '\'']).to_string()),
+                        ),
+                        None => (inner.trim().to_ascii_lowercase(), None),
+                    };
+                    // Other operators like ~= and ^= aren't supported
+                    if attr
+                        .0
+                        .ends_with(|c: char| !(c.is_alphanumeric() || c == '-'))
+                    {
+                        return None;
+                    }
+                    compound.attrs.push(attr);
+                    rest = &rest[end + 1..];
+                }
+                _ => return None,
+            }
+        }
+        Some(compound)
+    }
+
+    fn matches(&self, node: &Handle) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
+        };
+        let attrs = attrs.borrow();
+        let attr = |wanted: &str| {
+            attrs
+                .iter()
+                .find(|a| a.name.local.eq_ignore_ascii_case(wanted))
+                .map(|a| &*a.value)
+        };
+
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| name.local.eq_ignore_ascii_case(tag))
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| attr("id") == Some(id.as_str()))
+            && self.classes.iter().all(|class| {
+                attr("class").is_some_and(|c| c.split_whitespace().any(|c| c == class))
+            })
+            && self.attrs.iter().all(|(name, value)| match value {
+                Some(value) => attr(name) == Some(value.as_str()),
+                None => attr(name).is_some(),
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Selector {
+    /// Compound selectors from left to right, each with how it relates to
+    /// the one before it
+    parts: Vec<(Combinator, Compound)>,
+}
+
+impl Selector {
+    fn parse(text: &str) -> Option<Self> {
+        let mut parts = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        for token in tokenize(text) {
+            if token == ">" {
+                combinator = Combinator::Child;
+                continue;
+            }
+            parts.push((combinator, Compound::parse(token)?));
+            combinator = Combinator::Descendant;
+        }
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(Self { parts })
+        }
+    }
+
+    /// Counts of ids, classes and attributes, and tags
+    fn specificity(&self) -> (usize, usize, usize) {
+        self.parts.iter().fold((0, 0, 0), |(a, b, c), (_, part)| {
+            (
+                a + part.id.is_some() as usize,
+                b + part.classes.len() + part.attrs.len(),
+                c + part.tag.is_some() as usize,
+            )
+        })
+    }
+
+    fn matches(&self, node: &Handle) -> bool {
+        matches_at(&self.parts, self.parts.len() - 1, node)
+    }
+}
+
+/// Split a selector into compound selectors and `>`s, leaving whatever is
+/// in brackets alone so that attribute values can have spaces and `>`s
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut bracketed = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), _) => {
+                if c == q {
+                    quote = None;
+                }
+                continue;
+            }
+            (None, '"' | '\'') if bracketed => quote = Some(c),
+            (None, '[') => bracketed = true,
+            (None, ']') => bracketed = false,
+            _ => {}
+        }
+
+        if !bracketed && (c.is_whitespace() || c == '>') {
+            if let Some(start) = start.take() {
+                tokens.push(&text[start..i]);
+            }
+            if c == '>' {
+                tokens.push(">");
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+fn matches_at(parts: &[(Combinator, Compound)], i: usize, node: &Handle) -> bool {
+    let (combinator, compound) = &parts[i];
+    if !compound.matches(node) {
+        return false;
+    }
+    if i == 0 {
+        return true;
+    }
+
+    match combinator {
+        Combinator::Child => doc::parent(node).is_some_and(|p| matches_at(parts, i - 1, &p)),
+        Combinator::Descendant => {
+            let mut ancestor = doc::parent(node);
+            while let Some(node) = ancestor {
+                if matches_at(parts, i - 1, &node) {
+                    return true;
+                }
+                ancestor = doc::parent(&node);
+            }
+            false
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    selectors: Vec<Selector>,
+    declarations: Vec<Declaration>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stylesheet {
+    rules: Vec<Rule>,
+}
+
+impl Stylesheet {
+    /// Parse `css`, keeping only the `@media` blocks that apply to `viewport`
+    pub fn parse(css: &str, viewport: Viewport) -> Self {
+        let mut rules = Vec::new();
+        parse_rules(&strip_comments(css), viewport, &mut rules);
+        Self { rules }
+    }
+
+    pub fn extend(&mut self, other: Stylesheet) {
+        self.rules.extend(other.rules);
+    }
+
+    /// The declarations that win for `node`, including its `style` attribute
+    fn cascade(&self, node: &Handle) -> ElementStyle {
+        // Importance, then whether it's inline, then specificity, then source order
+        let mut matched: Vec<((bool, bool, (usize, usize, usize), usize), &Declaration)> =
+            Vec::new();
+        for (order, rule) in self.rules.iter().enumerate() {
+            let specificity = rule
+                .selectors
+                .iter()
+                .filter(|s| s.matches(node))
+                .map(Selector::specificity)
+                .max();
+            if let Some(specificity) = specificity {
+                for declaration in &rule.declarations {
+                    matched.push((
+                        (declaration.important, false, specificity, order),
+                        declaration,
+                    ));
+                }
+            }
+        }
+
+        let inline = match &node.data {
+            NodeData::Element { attrs, .. } => attrs
+                .borrow()
+                .iter()
+                .find(|a| a.name.local == local_name!("style"))
+                .map(|a| parse_declarations(&a.value)),
+            _ => None,
+        }
+        .unwrap_or_default();
+        for declaration in &inline {
+            matched.push(((declaration.important, true, (0, 0, 0), 0), declaration));
+        }
+
+        matched.sort_by_key(|(key, _)| *key);
+        let mut style = ElementStyle::default();
+        for (_, declaration) in matched {
+            style.set(&declaration.property, &declaration.value);
+        }
+        style
+    }
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        result.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .split_once("*/")
+            .map_or("", |(_, after)| after);
+    }
+    result.push_str(rest);
+    result
+}
+
+fn parse_rules(css: &str, viewport: Viewport, rules: &mut Vec<Rule>) {
+    let mut rest = css;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if rest.starts_with('@') {
+            let (prelude, block, after) = split_at_rule(rest);
+            if let (Some(query), Some(block)) = (prelude.strip_prefix("@media"), block) {
+                if media_matches(query, viewport) {
+                    parse_rules(block, viewport, rules);
+                }
+            }
+            rest = after;
+            continue;
+        }
+
+        let Some(open) = rest.find('{') else {
+            break;
+        };
+        let Some(close) = rest[open..].find('}').map(|c| c + open) else {
+            break;
+        };
+        let selectors: Option<Vec<Selector>> =
+            rest[..open].split(',').map(Selector::parse).collect();
+        // As in browsers, one selector we don't understand drops the whole rule
+        if let Some(selectors) = selectors {
+            rules.push(Rule {
+                selectors,
+                declarations: parse_declarations(&rest[open + 1..close]),
+            });
+        }
+        rest = &rest[close + 1..];
+    }
+}
+
+/// Split an at-rule into its prelude, its block if it has one, and whatever follows
+fn split_at_rule(css: &str) -> (&str, Option<&str>, &str) {
+    let Some(end) = css.find([';', '{']) else {
+        return (css, None, "");
+    };
+    let prelude = css[..end].trim();
+    if css.as_bytes()[end] == b';' {
+        return (prelude, None, &css[end + 1..]);
+    }
+
+    let mut depth = 0;
+    for (i, c) in css[end..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let close = end + i;
+                    return (prelude, Some(&css[end + 1..close]), &css[close + 1..]);
+                }
+            }
+            _ => {}
+        }
+    }
+    (prelude, Some(&css[end + 1..]), "")
+}
+
+fn parse_declarations(block: &str) -> Vec<Declaration> {
+    block
+        .split(';')
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            let value = value.trim();
+            let (value, important) = match value.strip_suffix("!important") {
+                Some(value) => (value.trim_end(), true),
+                None => (value, false),
+            };
+            Some(Declaration {
+                property: property.trim().to_ascii_lowercase(),
+                value: value.to_string(),
+                important,
+            })
+        })
+        .collect()
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let digit = |i: usize, len: usize| u8::from_str_radix(hex.get(i..i + len)?, 16).ok();
+        return match hex.len() {
+            3 | 4 => Some(Color::Rgb(
+                digit(0, 1)? * 17,
+                digit(1, 1)? * 17,
+                digit(2, 1)? * 17,
+            )),
+            6 | 8 => Some(Color::Rgb(digit(0, 2)?, digit(2, 2)?, digit(4, 2)?)),
+            _ => None,
+        };
+    }
+
+    if let Some(args) = value
+        .strip_prefix("rgba(")
+        .or_else(|| value.strip_prefix("rgb("))
+        .and_then(|v| v.strip_suffix(')'))
+    {
+        let mut channels = args
+            .split([',', ' ', '/'])
+            .filter(|c| !c.is_empty())
+            .map(|c| match c.strip_suffix('%') {
+                Some(percent) => percent.parse::<f32>().ok().map(|p| p * 2.55),
+                None => c.parse::<f32>().ok(),
+            })
+            .map(|c| c.map(|c| c.clamp(0.0, 255.0).round() as u8));
+        return Some(Color::Rgb(
+            channels.next()??,
+            channels.next()??,
+            channels.next()??,
+        ));
+    }
+
+    let (r, g, b) = match value {
+        "black" => (0, 0, 0),
+        "silver" => (192, 192, 192),
+        "gray" | "grey" => (128, 128, 128),
+        "white" => (255, 255, 255),
+        "maroon" => (128, 0, 0),
+        "red" => (255, 0, 0),
+        "purple" => (128, 0, 128),
+        "fuchsia" | "magenta" => (255, 0, 255),
+        "green" => (0, 128, 0),
+        "lime" => (0, 255, 0),
+        "olive" => (128, 128, 0),
+        "yellow" => (255, 255, 0),
+        "navy" => (0, 0, 128),
+        "blue" => (0, 0, 255),
+        "teal" => (0, 128, 128),
+        "aqua" | "cyan" => (0, 255, 255),
+        "orange" => (255, 165, 0),
+        "brown" => (165, 42, 42),
+        "pink" => (255, 192, 203),
+        "darkgray" | "darkgrey" => (169, 169, 169),
+        "lightgray" | "lightgrey" => (211, 211, 211),
+        "darkred" => (139, 0, 0),
+        "darkgreen" => (0, 100, 0),
+        "darkblue" => (0, 0, 139),
+        _ => return None,
+    };
+    Some(Color::Rgb(r, g, b))
+}
+
+/// Work out the style of every element and change the document to match:
+/// hidden elements are removed, display and white-space are expressed with
+/// tags the renderer knows, and visual styles are marked for the renderer.
+/// Returns the visual styles, indexed by the numbers in the marks.
+/// Only for a copy made to be laid out, as scripts would lose track of it.
+pub fn apply(dom: &RcDom, sheet: &Stylesheet) -> Vec<ElementStyle> {
+    let mut elements = Vec::new();
+    collect_elements(&dom.document, &mut elements);
+    // Match everything before changing the tree under the selectors
+    let styled: Vec<(Handle, ElementStyle)> = elements
+        .into_iter()
+        .map(|node| {
+            let mut style = sheet.cascade(&node);
+            style.heading = heading_level(&node);
+            (node, style)
+        })
+        .filter(|(_, style)| *style != ElementStyle::default())
+        .collect();
+
+    let mut styles = Vec::new();
+    for (node, style) in styled {
+        if style.display == Some(Display::None) {
+            doc::detach(&node);
+            continue;
+        }
+
+        let node = match retag_for(&node, &style) {
+            Some(name) => retag(&node, name),
+            None => node,
+        };
+        if style.is_visual() {
+            wrap_children(&node, &format!("{STYLE_HREF}{}", styles.len()));
+            styles.push(style);
+        }
+    }
+    styles
+}
+
+/// Which style a link made by [`apply`] marks, or `None` for real links
+pub fn style_index(url: &str) -> Option<usize> {
+    url.strip_prefix(STYLE_HREF)?.parse().ok()
+}
+
+fn heading_level(node: &Handle) -> Option<usize> {
+    let NodeData::Element { name, .. } = &node.data else {
+        return None;
+    };
+    match name.local {
+        local_name!("h1") => Some(1),
+        local_name!("h2") => Some(2),
+        local_name!("h3") => Some(3),
+        local_name!("h4") => Some(4),
+        local_name!("h5") => Some(5),
+        local_name!("h6") => Some(6),
+        _ => None,
+    }
+}
+
+fn collect_elements(node: &Handle, elements: &mut Vec<Handle>) {
+    if let NodeData::Element { name, .. } = &node.data {
+        if matches!(
+            name.local,
+            local_name!("head") | local_name!("script") | local_name!("style")
+        ) {
+            return;
+        }
+        elements.push(node.clone());
+    }
+    for child in node.children.borrow().iter() {
+        collect_elements(child, elements);
+    }
+}
+
+const BLOCKS: [&str; 12] = [
+    "address", "article", "aside", "div", "footer", "header", "li", "main", "nav", "p", "section",
+    "td",
+];
+
+/// The tag that gives the renderer the layout `style` asks for, if it
+/// differs from the element's own
+fn retag_for(node: &Handle, style: &ElementStyle) -> Option<&'static str> {
+    let NodeData::Element { name, .. } = &node.data else {
+        return None;
+    };
+    let block = BLOCKS.contains(&&*name.local);
+
+    match (style.white_space, style.display) {
+        (Some(WhiteSpace::Pre), _) if block || style.display == Some(Display::Block) => Some("pre"),
+        (_, Some(Display::Block)) if !block && name.local != local_name!("pre") => Some("div"),
+        (_, Some(Display::Inline)) if block && name.local != local_name!("td") => Some("span"),
+        _ => None,
+    }
+}
+
+/// Replace `node` with an element called `name` with the same attributes and children
+fn retag(node: &Handle, name: &str) -> Handle {
+    let NodeData::Element { attrs, .. } = &node.data else {
+        return node.clone();
+    };
+    let replacement = doc::element(name, attrs.borrow().clone());
+    for child in node.children.take() {
+        child.parent.set(Some(Rc::downgrade(&replacement)));
+        replacement.children.borrow_mut().push(child);
+    }
+
+    doc::replace(node, &replacement);
+    replacement
+}
+
+/// Move all of `node`'s children into a new style mark for `href`
+fn wrap_children(node: &Handle, href: &str) {
+    let href = Attribute {
+        name: QualName::new(None, ns!(), local_name!("href")),
+        value: href.into(),
+    };
+    let wrapper = doc::element("a", vec![href]);
+    for child in node.children.take() {
+        child.parent.set(Some(Rc::downgrade(&wrapper)));
+        wrapper.children.borrow_mut().push(child);
+    }
+    wrapper.parent.set(Some(Rc::downgrade(node)));
+    node.children.borrow_mut().push(wrapper);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+
+    const RED: Option<Color> = Some(Color::Rgb(255, 0, 0));
+    const GREEN: Option<Color> = Some(Color::Rgb(0, 128, 0));
+    const SCREEN: Viewport = Viewport {
+        media: Media::Screen,
+        columns: 80,
+    };
+    const PRINT: Viewport = Viewport {
+        media: Media::Print,
+        columns: 80,
+    };
+    const BLUE: Option<Color> = Some(Color::Rgb(0, 0, 255));
+
+    fn parse(html: &str) -> RcDom {
+        html5ever::parse_document(RcDom::default(), Default::default()).one(html)
+    }
+
+    fn color(css: &str, html: &str) -> Option<Color> {
+        let dom = parse(html);
+        let node = doc::find_anchor(&dom.document, "target").unwrap();
+        Stylesheet::parse(css, SCREEN).cascade(&node).color
+    }
+
+    fn specificity(selector: &str) -> (usize, usize, usize) {
+        Selector::parse(selector).unwrap().specificity()
+    }
+
+    #[test]
+    fn specificity_counts() {
+        assert_eq!(specificity("p"), (0, 0, 1));
+        assert_eq!(specificity("#a .b p"), (1, 1, 1));
+        assert_eq!(specificity("a[href].x.y"), (0, 3, 1));
+        assert_eq!(specificity("* > *"), (0, 0, 0));
+    }
+
+    #[test]
+    fn more_specific_rules_win() {
+        let html = "<p id=target class=c>Text</p>";
+        assert_eq!(
+            color(
+                "#target { color: green } .c { color: blue } p { color: red }",
+                html
+            ),
+            GREEN
+        );
+        assert_eq!(color(".c { color: blue } p { color: red }", html), BLUE);
+        // Then the later rule
+        assert_eq!(color("p { color: red } p { color: blue }", html), BLUE);
+        assert_eq!(
+            color("p.c, #target { color: red } .c { color: blue }", html),
+            RED
+        );
+    }
+
+    #[test]
+    fn important_and_inline_declarations() {
+        let html = "<p id=target style=\"color: blue\">Text</p>";
+        assert_eq!(color("#target { color: red }", html), BLUE);
+        assert_eq!(color("p { color: red !important }", html), RED);
+        let html = "<p id=target style=\"color: blue !important\">Text</p>";
+        assert_eq!(color("p { color: red !important }", html), BLUE);
+    }
+
+    #[test]
+    fn child_and_descendant_combinators() {
+        let direct = "<div><p id=target>Text</p></div>";
+        let nested = "<div><span><p id=target>Text</p></span></div>";
+        assert_eq!(color("div > p { color: red }", direct), RED);
+        assert_eq!(color("div > p { color: red }", nested), None);
+        assert_eq!(color("div>p { color: red }", direct), RED);
+        assert_eq!(color("div p { color: red }", nested), RED);
+        assert_eq!(color("div > span > p { color: red }", nested), RED);
+        assert_eq!(color("span div p { color: red }", nested), None);
+    }
+
+    #[test]
+    fn attribute_values_keep_their_spaces_and_brackets() {
+        assert_eq!(
+            tokenize("ul>li  a[title='a > b'] > b"),
+            ["ul", ">", "li", "a[title='a > b']", ">", "b"]
+        );
+
+        let html = "<a id=target title=\"x > [y]\">Text</a>";
+        assert_eq!(color("a[title=\"x > [y]\"] { color: red }", html), RED);
+        assert_eq!(color("a[title=\"x\"] { color: red }", html), None);
+        // Operators other than = drop the rule
+        assert_eq!(color("a[title^=\"x\"] { color: red }", html), None);
+    }
+
+    #[test]
+    fn media_queries() {
+        assert!(media_matches("", SCREEN));
+        assert!(media_matches("all", PRINT));
+        assert!(media_matches("screen, print", PRINT));
+        assert!(!media_matches("print", SCREEN));
+        assert!(media_matches("not print", SCREEN));
+        assert!(media_matches("only screen and (min-width: 600px)", SCREEN));
+        assert!(!media_matches("screen and (min-width: 800px)", SCREEN));
+        assert!(media_matches("(max-width: 40em)", SCREEN));
+        assert!(media_matches("(color)", SCREEN));
+        assert!(!media_matches("(hover: hover)", SCREEN));
+    }
+
+    #[test]
+    fn width_queries_follow_the_columns() {
+        let narrow = Viewport {
+            media: Media::Screen,
+            columns: 40,
+        };
+        assert!(media_matches("(max-width: 320px)", narrow));
+        assert!(!media_matches("(min-width: 321px)", narrow));
+        assert!(!media_matches("(max-width: 320px)", SCREEN));
+        assert!(media_matches("(min-width: 40em)", SCREEN));
+    }
+
+    #[test]
+    fn media_blocks_apply_only_when_they_match() {
+        let html = "<p id=target>Text</p>";
+        let css = "p { color: red } @media print { p { color: blue } } \
+                   @media screen { @media (min-width: 100px) { p { color: green } } }";
+        assert_eq!(color(css, html), GREEN);
+        let css = "@import url(x.css); p { color: red } @media print { p { color: blue } }";
+        assert_eq!(color(css, html), RED);
+    }
+
+    #[test]
+    fn applying_styles_changes_only_the_copy() {
+        let dom = parse("<p>Shown</p><p class=hidden>Hidden</p><span id=target>Span</span>");
+        let sheet = Stylesheet::parse(
+            ".hidden { display: none } span { display: block; color: red }",
+            SCREEN,
+        );
+        let copy = doc::copy(&dom);
+        let styles = apply(&copy, &sheet);
+
+        assert_eq!(styles.len(), 1);
+        assert_eq!(doc::text_content(&copy.document), "Shown Span");
+        assert_eq!(doc::text_content(&dom.document), "Shown Hidden Span");
+        let span = doc::find_anchor(&dom.document, "target").unwrap();
+        assert!(
+            matches!(&span.data, NodeData::Element { name, .. } if name.local == local_name!("span"))
+        );
+        assert_eq!(span.children.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,107 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+use crate::error::RetumiError;
+use crate::event::Response;
+
+// Plenty of data: URLs in the wild leave off the padding
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Decode a `data:[<mediatype>][;base64],<data>` URL (RFC 2397)
+pub fn parse(url: &str) -> Result<Response, RetumiError> {
+    let invalid = |reason: &str| RetumiError::UrlError(format!("{reason} in data: URL"));
+
+    let rest = url
+        .get(..5)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("data:"))
+        .map(|_| &url[5..])
+        .ok_or_else(|| invalid("missing scheme"))?;
+    let (header, data) = rest
+        .split_once(',')
+        .ok_or_else(|| invalid("missing comma"))?;
+    // The fragment belongs to the URL, not the data
+    let data = data.split('#').next().unwrap_or_default();
+
+    let header = header.trim();
+    let base64 = header.to_ascii_lowercase().ends_with(";base64");
+    let media_type = if base64 {
+        &header[..header.len() - 7]
+    } else {
+        header
+    };
+
+    let mut body: Vec<u8> = percent_encoding::percent_decode_str(data).collect();
+    if base64 {
+        body.retain(|b| !b.is_ascii_whitespace());
+        body = BASE64
+            .decode(&body)
+            .map_err(|err| invalid(&format!("bad base64 ({err})")))?;
+    }
+
+    let content_type = match media_type.trim() {
+        "" => String::from("text/plain;charset=US-ASCII"),
+        // A bare ";charset=..." keeps the default type
+        params if params.starts_with(';') => format!("text/plain{params}"),
+        media_type => media_type.to_string(),
+    };
+
+    Ok(Response {
+        url: url.to_string(),
+        content_type: Some(content_type),
+        body,
+        redirects: Vec::new(),
+        truncated: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_by_default() {
+        let response = parse("data:,Hello%2C%20World%21").unwrap();
+        assert_eq!(
+            response.content_type.as_deref(),
+            Some("text/plain;charset=US-ASCII")
+        );
+        assert_eq!(response.body, b"Hello, World!");
+    }
+
+    #[test]
+    fn charset_without_type() {
+        let response = parse("data:;charset=utf-8,caf%C3%A9").unwrap();
+        assert_eq!(
+            response.content_type.as_deref(),
+            Some("text/plain;charset=utf-8")
+        );
+        assert_eq!(response.body, "café".as_bytes());
+    }
+
+    #[test]
+    fn base64_with_whitespace_and_no_padding() {
+        let response = parse("DATA:text/html;BASE64,PGI+aGk8\n L2I+").unwrap();
+        assert_eq!(response.content_type.as_deref(), Some("text/html"));
+        assert_eq!(response.body, b"<b>hi</b>");
+
+        assert_eq!(parse("data:;base64,aGk").unwrap().body, b"hi");
+    }
+
+    #[test]
+    fn fragment_is_not_data() {
+        let response = parse("data:text/plain,abc#def").unwrap();
+        assert_eq!(response.body, b"abc");
+        assert_eq!(response.url, "data:text/plain,abc#def");
+    }
+
+    #[test]
+    fn malformed_urls() {
+        assert!(parse("data:text/plain").is_err());
+        assert!(parse("http://example.com/,x").is_err());
+        assert!(parse("data:;base64,****").is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use html2text::markup5ever_rcdom::{Handle, NodeData, RcDom};
-use html5ever::local_name;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use html2text::markup5ever_rcdom::{Handle, Node, NodeData, RcDom};
+use html5ever::{local_name, namespace_url, ns, Attribute, LocalName, QualName};
+
+use crate::charset;
 
 pub fn extract_scripts(dom: &RcDom) -> Vec<Handle> {
     let mut result = vec![];
@@ -36,3 +41,368 @@ pub fn contents(script: &Handle) -> String {
 
     String::new()
 }
+
+/// Where a page's CSS comes from
+pub enum StyleSource {
+    Inline(String),
+    /// The unresolved `href` of a `<link rel="stylesheet">`
+    Linked(String),
+}
+
+/// Every `<style>` and stylesheet `<link>`, in document order, along with
+/// its `media` attribute
+pub fn stylesheets(dom: &RcDom) -> Vec<(StyleSource, Option<String>)> {
+    fn walker(node: &Handle, result: &mut Vec<(StyleSource, Option<String>)>) {
+        if let NodeData::Element { name, attrs, .. } = &node.data {
+            let attrs = attrs.borrow();
+            let media = attr(&attrs, "media");
+            if name.local == local_name!("style") {
+                let mut css = String::new();
+                for child in node.children.borrow().iter() {
+                    if let NodeData::Text { contents } = &child.data {
+                        css.push_str(&contents.borrow());
+                    }
+                }
+                result.push((StyleSource::Inline(css), media));
+                return;
+            }
+
+            let is_stylesheet = attr(&attrs, "rel").is_some_and(|rel| {
+                let rel = rel.to_ascii_lowercase();
+                // Alternate stylesheets are only used when picked by the user
+                rel.split_whitespace().any(|r| r == "stylesheet")
+                    && !rel.split_whitespace().any(|r| r == "alternate")
+            });
+            if name.local == local_name!("link") && is_stylesheet {
+                if let Some(href) = attr(&attrs, "href") {
+                    result.push((StyleSource::Linked(href), media));
+                }
+            }
+        }
+
+        for child in node.children.borrow().iter() {
+            walker(child, result);
+        }
+    }
+
+    let mut result = vec![];
+    walker(&dom.document, &mut result);
+    result
+}
+
+fn attr(attrs: &[html5ever::Attribute], name: &str) -> Option<String> {
+    attrs
+        .iter()
+        .find(|a| a.name.local.eq_ignore_ascii_case(name))
+        .map(|a| String::from(&a.value))
+}
+
+/// Every `<img>` under `root`, in document order
+pub fn images(root: &Handle) -> Vec<Handle> {
+    fn walker(node: &Handle, result: &mut Vec<Handle>) {
+        if let NodeData::Element { name, .. } = &node.data {
+            if name.local == local_name!("img") {
+                result.push(node.clone());
+            }
+        }
+        for child in node.children.borrow().iter() {
+            walker(child, result);
+        }
+    }
+
+    let mut result = vec![];
+    walker(root, &mut result);
+    result
+}
+
+/// Find the charset declared by a `<meta>` tag anywhere in the document.
+pub fn meta_charset(dom: &RcDom) -> Option<String> {
+    fn walker(node: &Handle) -> Option<String> {
+        if let NodeData::Element { name, attrs, .. } = &node.data {
+            if name.local == local_name!("meta") {
+                let attrs = attrs.borrow();
+                if let Some(charset) = attr(&attrs, "charset") {
+                    return Some(charset);
+                }
+
+                let is_content_type = attr(&attrs, "http-equiv")
+                    .is_some_and(|v| v.eq_ignore_ascii_case("content-type"));
+                if is_content_type {
+                    if let Some(content) = attr(&attrs, "content") {
+                        if let Some(charset) = charset::charset_from_meta_content(&content) {
+                            return Some(charset.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        node.children.borrow().iter().find_map(walker)
+    }
+
+    walker(&dom.document)
+}
+
+/// Find the first `<meta http-equiv="refresh">`, returning its delay in
+/// seconds and the URL to go to, if it names one rather than reloading.
+pub fn meta_refresh(dom: &RcDom) -> Option<(u64, Option<String>)> {
+    fn walker(node: &Handle) -> Option<(u64, Option<String>)> {
+        if let NodeData::Element { name, attrs, .. } = &node.data {
+            if name.local == local_name!("meta") {
+                let attrs = attrs.borrow();
+                let is_refresh =
+                    attr(&attrs, "http-equiv").is_some_and(|v| v.eq_ignore_ascii_case("refresh"));
+                if is_refresh {
+                    if let Some(refresh) = attr(&attrs, "content").and_then(|c| parse_refresh(&c)) {
+                        return Some(refresh);
+                    }
+                }
+            }
+        }
+
+        node.children.borrow().iter().find_map(walker)
+    }
+
+    walker(&dom.document)
+}
+
+/// Parse the content of a refresh declaration, e.g. `5; url='/next'`
+fn parse_refresh(content: &str) -> Option<(u64, Option<String>)> {
+    let content = content.trim_start();
+    let digits = content.len()
+        - content
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    // Browsers accept fractional delays but ignore the fraction
+    let delay = content[..digits].parse().ok()?;
+
+    let rest = content[digits..]
+        .trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+        .trim_start();
+    let rest = rest.strip_prefix([';', ',']).unwrap_or(rest).trim_start();
+    let rest = match rest.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url") => {
+            let after = rest[3..].trim_start();
+            after.strip_prefix('=').map_or(rest, str::trim_start)
+        }
+        _ => rest,
+    };
+
+    let url = match rest.chars().next() {
+        Some(quote @ ('\'' | '"')) => rest[1..].split(quote).next().unwrap_or_default(),
+        _ => rest,
+    }
+    .trim();
+
+    Some((delay, Some(url.to_string()).filter(|u| !u.is_empty())))
+}
+
+/// Escape text for inclusion in HTML we generate ourselves
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Find the element a URL fragment points at: the one with that `id`,
+/// or failing that an `<a>` with that `name`
+pub fn find_anchor(root: &Handle, fragment: &str) -> Option<Handle> {
+    fn walker(node: &Handle, matches: &dyn Fn(&Handle) -> bool) -> Option<Handle> {
+        if matches(node) {
+            return Some(node.clone());
+        }
+        node.children
+            .borrow()
+            .iter()
+            .find_map(|child| walker(child, matches))
+    }
+
+    let has_id = |node: &Handle| match &node.data {
+        NodeData::Element { attrs, .. } => {
+            attr(&attrs.borrow(), "id").is_some_and(|id| id == fragment)
+        }
+        _ => false,
+    };
+    let has_name = |node: &Handle| match &node.data {
+        NodeData::Element { name, attrs, .. } if name.local == local_name!("a") => {
+            attr(&attrs.borrow(), "name").is_some_and(|name| name == fragment)
+        }
+        _ => false,
+    };
+
+    walker(root, &has_id).or_else(|| walker(root, &has_name))
+}
+
+/// Put a text node at the start of `node`, returning it so it can be removed again
+pub fn prepend_text(node: &Handle, contents: &str) -> Handle {
+    let child = text(contents);
+    child.parent.set(Some(Rc::downgrade(node)));
+    node.children.borrow_mut().insert(0, child.clone());
+    child
+}
+
+pub fn detach(node: &Handle) {
+    if let Some(parent) = node.parent.take().and_then(|p| p.upgrade()) {
+        parent
+            .children
+            .borrow_mut()
+            .retain(|child| !Rc::ptr_eq(child, node));
+    }
+}
+
+pub fn parent(node: &Handle) -> Option<Handle> {
+    let weak = node.parent.take();
+    let parent = weak.as_ref().and_then(|p| p.upgrade());
+    node.parent.set(weak);
+    parent
+}
+
+/// Put `replacement` where `node` is in its parent
+pub fn replace(node: &Handle, replacement: &Handle) {
+    if let Some(parent) = parent(node) {
+        replacement.parent.set(Some(Rc::downgrade(&parent)));
+        for child in parent.children.borrow_mut().iter_mut() {
+            if Rc::ptr_eq(child, node) {
+                *child = replacement.clone();
+            }
+        }
+    }
+}
+
+/// A copy of `dom` that shares no nodes with it
+pub fn copy(dom: &RcDom) -> RcDom {
+    let copy = RcDom::default();
+    append_copies(&dom.document, &copy.document);
+    copy
+}
+
+fn append_copies(from: &Handle, to: &Handle) {
+    for child in from.children.borrow().iter() {
+        let data = match &child.data {
+            NodeData::Document => NodeData::Document,
+            NodeData::Doctype {
+                name,
+                public_id,
+                system_id,
+            } => NodeData::Doctype {
+                name: name.clone(),
+                public_id: public_id.clone(),
+                system_id: system_id.clone(),
+            },
+            NodeData::Text { contents } => NodeData::Text {
+                contents: contents.clone(),
+            },
+            NodeData::Comment { contents } => NodeData::Comment {
+                contents: contents.clone(),
+            },
+            NodeData::Element {
+                name,
+                attrs,
+                template_contents,
+                mathml_annotation_xml_integration_point,
+            } => NodeData::Element {
+                name: name.clone(),
+                attrs: attrs.clone(),
+                template_contents: RefCell::new(template_contents.borrow().as_ref().map(
+                    |contents| {
+                        let copy = Node::new(NodeData::Document);
+                        append_copies(contents, &copy);
+                        copy
+                    },
+                )),
+                mathml_annotation_xml_integration_point: *mathml_annotation_xml_integration_point,
+            },
+            NodeData::ProcessingInstruction { target, contents } => {
+                NodeData::ProcessingInstruction {
+                    target: target.clone(),
+                    contents: contents.clone(),
+                }
+            }
+        };
+        let copy = Node::new(data);
+        copy.parent.set(Some(Rc::downgrade(to)));
+        to.children.borrow_mut().push(copy.clone());
+        append_copies(child, &copy);
+    }
+}
+
+pub fn element(name: &str, attrs: Vec<Attribute>) -> Handle {
+    Node::new(NodeData::Element {
+        name: QualName::new(None, ns!(html), LocalName::from(name)),
+        attrs: RefCell::new(attrs),
+        template_contents: RefCell::new(None),
+        mathml_annotation_xml_integration_point: false,
+    })
+}
+
+pub fn text(contents: &str) -> Handle {
+    Node::new(NodeData::Text {
+        contents: RefCell::new(contents.into()),
+    })
+}
+
+/// All the text inside `node`, with runs of whitespace collapsed to a space
+pub fn text_content(node: &Handle) -> String {
+    fn walker(node: &Handle, result: &mut String) {
+        if let NodeData::Text { contents } = &node.data {
+            result.push_str(&contents.borrow());
+            result.push(' ');
+        }
+        for child in node.children.borrow().iter() {
+            walker(child, result);
+        }
+    }
+
+    let mut result = String::new();
+    walker(node, &mut result);
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The value of `node`'s attribute `name`, if it's an element with one
+pub fn attribute(node: &Handle, name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attr(&attrs.borrow(), name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+
+    fn parse(html: &str) -> RcDom {
+        html5ever::parse_document(RcDom::default(), Default::default()).one(html)
+    }
+
+    fn text_of(node: Option<Handle>) -> Option<String> {
+        node.as_ref().map(text_content)
+    }
+
+    #[test]
+    fn anchor_by_id_wins_over_name() {
+        let dom = parse("<a name=\"x\">By name</a><p id=\"x\">By id</p>");
+        assert_eq!(
+            text_of(find_anchor(&dom.document, "x")).as_deref(),
+            Some("By id")
+        );
+    }
+
+    #[test]
+    fn only_links_are_named_anchors() {
+        let dom = parse("<p name=\"x\">Paragraph</p><a name=\"x\">Link</a>");
+        assert_eq!(
+            text_of(find_anchor(&dom.document, "x")).as_deref(),
+            Some("Link")
+        );
+    }
+
+    #[test]
+    fn anchors_match_exactly() {
+        let dom = parse("<p id=\"Section\">Text</p>");
+        assert!(find_anchor(&dom.document, "section").is_none());
+        assert!(find_anchor(&dom.document, "Sec").is_none());
+        assert!(find_anchor(&dom.document, "Section").is_some());
+    }
+}
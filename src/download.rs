@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crossbeam::channel::Sender;
+use reqwest::header::{self, HeaderMap, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+
+use crate::content;
+use crate::error::RetumiError;
+use crate::net::Session;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadState {
+    InProgress,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub id: usize,
+    pub url: String,
+    pub path: PathBuf,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub state: DownloadState,
+    pub started: Instant,
+}
+
+impl Download {
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.url.clone())
+    }
+}
+
+/// Shared between the download tasks and the downloads panel
+pub type DownloadList = Arc<Mutex<Vec<Download>>>;
+
+/// Reserve a destination in `dir` by creating it empty, so that neither a
+/// finished file nor another download can end up with the same name.
+/// A name that's already taken is reused only if `reusable` accepts it.
+pub fn reserve(
+    dir: &Path,
+    url: &str,
+    reusable: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, std::io::Error> {
+    std::fs::create_dir_all(dir)?;
+    let name = content::file_name(url);
+    let mut path = dir.join(&name);
+    let mut n = 1;
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                if reusable(&path) {
+                    return Ok(path);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+        path = dir.join(format!("{name}.{n}"));
+        n += 1;
+    }
+}
+
+/// Whether `path` is the reservation of a transfer that was interrupted,
+/// with the data received so far in its `.part` file
+fn is_interrupted(path: &Path) -> bool {
+    let empty = std::fs::metadata(path).is_ok_and(|m| m.len() == 0);
+    empty && part_path(path).exists()
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Where the ETag or Last-Modified of what's in the `.part` file is kept,
+/// so that a resumed transfer only appends to the same version
+fn validator_path(path: &Path) -> PathBuf {
+    let mut validator = part_path(path).into_os_string();
+    validator.push(".validator");
+    PathBuf::from(validator)
+}
+
+/// What If-Range can check: a strong ETag, or else Last-Modified
+fn validator(headers: &HeaderMap) -> Option<&str> {
+    let etag = headers
+        .get(header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    etag.or_else(|| {
+        headers
+            .get(header::LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+    })
+}
+
+/// The first byte and the complete length in a Content-Range such as
+/// `bytes 100-199/200` or `bytes */200`
+fn content_range(headers: &HeaderMap) -> (Option<u64>, Option<u64>) {
+    let Some(range) = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes "))
+    else {
+        return (None, None);
+    };
+    let (span, total) = range.split_once('/').unwrap_or((range, "*"));
+    let start = span
+        .split_once('-')
+        .and_then(|(start, _)| start.trim().parse().ok());
+    (start, total.trim().parse().ok())
+}
+
+pub struct DownloadManager {
+    session: Session,
+    dir: PathBuf,
+    list: DownloadList,
+    tasks: HashMap<usize, JoinHandle<()>>,
+    changed: Sender<()>,
+    next_id: usize,
+}
+
+impl DownloadManager {
+    pub fn new(session: Session, dir: PathBuf, list: DownloadList, changed: Sender<()>) -> Self {
+        Self {
+            session,
+            dir,
+            list,
+            tasks: HashMap::new(),
+            changed,
+            next_id: 0,
+        }
+    }
+
+    /// Download `url`, resuming a previous partial transfer if there is one
+    pub fn start(&mut self, url: String) -> PathBuf {
+        self.spawn(url, None)
+    }
+
+    /// Take over a response whose headers we've already received,
+    /// typically because it turned out not to be renderable.
+    pub fn adopt(&mut self, url: String, response: reqwest::Response) -> PathBuf {
+        self.spawn(url, Some(response))
+    }
+
+    pub fn cancel(&mut self, id: usize) {
+        if let Some(task) = self.tasks.remove(&id) {
+            task.abort();
+        }
+
+        update(&self.list, &self.changed, id, |d| {
+            if d.state == DownloadState::InProgress {
+                d.state = DownloadState::Cancelled;
+            }
+        });
+    }
+
+    fn spawn(&mut self, url: String, response: Option<reqwest::Response>) -> PathBuf {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.retain(|_, task| !task.is_finished());
+
+        // Holding the list while reserving keeps two downloads from resuming the same file
+        let mut list = self.list.lock().unwrap();
+        let reserved = reserve(&self.dir, &url, |path| {
+            let active = list
+                .iter()
+                .any(|d| d.path == path && d.state == DownloadState::InProgress);
+            !active && is_interrupted(path)
+        });
+        let (path, state) = match reserved {
+            Ok(path) => (path, DownloadState::InProgress),
+            Err(err) => (
+                self.dir.join(content::file_name(&url)),
+                DownloadState::Failed(err.to_string()),
+            ),
+        };
+        let failed = state != DownloadState::InProgress;
+        list.push(Download {
+            id,
+            url: url.clone(),
+            path: path.clone(),
+            received: 0,
+            total: None,
+            state,
+            started: Instant::now(),
+        });
+        drop(list);
+        let _ = self.changed.try_send(());
+        if failed {
+            return path;
+        }
+
+        let session = self.session.clone();
+        let list = self.list.clone();
+        let changed = self.changed.clone();
+        let dest = path.clone();
+
+        let task = tokio::spawn(async move {
+            let progress = |received, total| {
+                update(&list, &changed, id, |d| {
+                    d.received = received;
+                    d.total = total;
+                })
+            };
+
+            let result = transfer(&session, &url, &dest, response, progress).await;
+
+            update(&list, &changed, id, |d| {
+                d.state = match &result {
+                    Ok(()) => DownloadState::Finished,
+                    Err(err) => DownloadState::Failed(err.to_string()),
+                }
+            });
+        });
+
+        self.tasks.insert(id, task);
+        path
+    }
+}
+
+fn update(list: &DownloadList, changed: &Sender<()>, id: usize, f: impl FnOnce(&mut Download)) {
+    if let Some(download) = list.lock().unwrap().iter_mut().find(|d| d.id == id) {
+        f(download);
+    }
+    // The channel only needs to hold one wakeup for any number of updates
+    let _ = changed.try_send(());
+}
+
+async fn transfer(
+    session: &Session,
+    url: &str,
+    dest: &Path,
+    response: Option<reqwest::Response>,
+    progress: impl Fn(u64, Option<u64>),
+) -> Result<(), RetumiError> {
+    let part = part_path(dest);
+    let validator_file = validator_path(dest);
+    let offset = tokio::fs::metadata(&part).await.map_or(0, |m| m.len());
+    let mut response = match response {
+        Some(response) => response,
+        None => {
+            let mut headers = HeaderMap::new();
+            // Without a validator the server can't tell us the file changed, so start over
+            let saved = tokio::fs::read_to_string(&validator_file).await.ok();
+            if let Some(saved) = saved.filter(|_| offset > 0) {
+                tracing::info!("resuming download of {url} from byte {offset}");
+                headers.insert(RANGE, format!("bytes={offset}-").parse().unwrap());
+                if let Ok(value) = saved.trim().parse() {
+                    headers.insert(IF_RANGE, value);
+                }
+            }
+            session.send(url, &headers).await?.0
+        }
+    };
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // We asked for a range starting at the end of the file, so it's already complete
+        if content_range(response.headers()).1 == Some(offset) {
+            tokio::fs::rename(&part, dest).await?;
+            let _ = tokio::fs::remove_file(&validator_file).await;
+            return Ok(());
+        }
+        tracing::info!("{url} is no longer the size of what was received, starting over");
+        response = session.send(url, &HeaderMap::new()).await?.0;
+    }
+    let mut response = response.error_for_status()?;
+
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+    if resumed && content_range(response.headers()).0 != Some(offset) {
+        return Err(RetumiError::DownloadError(format!(
+            "{url} was resumed from the wrong place"
+        )));
+    }
+    if !resumed {
+        match validator(response.headers()) {
+            Some(value) => tokio::fs::write(&validator_file, value).await?,
+            None => {
+                let _ = tokio::fs::remove_file(&validator_file).await;
+            }
+        }
+    }
+    let mut received = if resumed { offset } else { 0 };
+    let total = response.content_length().map(|len| len + received);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)
+        .await?;
+
+    progress(received, total);
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress(received, total);
+    }
+    file.flush().await?;
+
+    tokio::fs::rename(&part, dest).await?;
+    let _ = tokio::fs::remove_file(&validator_file).await;
+    Ok(())
+}
+
+/// Open a finished download with the system's default application
+pub fn open(path: &Path) -> Result<(), RetumiError> {
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    // Explorer takes the path as an argument, where cmd's start would
+    // interpret characters such as & in it
+    #[cfg(target_os = "windows")]
+    let mut command = std::process::Command::new("explorer");
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let mut command = std::process::Command::new("xdg-open");
+
+    command
+        .arg(path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    Ok(())
+}
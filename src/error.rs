@@ -28,4 +28,22 @@ pub enum RetumiError {
     TerminalError(#[from] TerminalError),
     #[error("error while sending/receiving to/from channel")]
     ChannelError,
+    #[error("error in configuration: {0}")]
+    ConfigError(String),
+    #[error("invalid URL: {0}")]
+    UrlError(String),
+    #[error("error while setting up TLS: {0}")]
+    TlsError(String),
+    #[error("error while talking to a Gemini server: {0}")]
+    GeminiError(String),
+    #[error("error while talking to a Gopher server: {0}")]
+    GopherError(String),
+    #[error("error while authenticating: {0}")]
+    AuthError(String),
+    #[error("error while following redirects: {0}")]
+    RedirectError(String),
+    #[error("error while loading part of a page: {0}")]
+    SubresourceError(String),
+    #[error("error while downloading: {0}")]
+    DownloadError(String),
 }
@@ -1,41 +1,406 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::about::{self, About};
+use crate::auth::{self, Challenge, CredentialStore, Credentials};
+use crate::cache::{CacheEntry, HttpCache};
+use crate::config::Config;
+use crate::content::{self, ContentKind};
+use crate::data_url;
+use crate::download::{DownloadList, DownloadManager};
+use crate::error::RetumiError;
+use crate::gemini::GeminiClient;
+use crate::gopher::GopherClient;
+use crate::history::History;
+use crate::net::{Hop, Session, MAX_BODY};
+use crate::tls::{self, CertProblem};
 use crate::ui::Msg;
 
 use crossbeam::channel::{Receiver, Sender};
+use percent_encoding::NON_ALPHANUMERIC;
+use reqwest::header::{self, HeaderMap, AUTHORIZATION};
+use reqwest::{StatusCode, Url};
 use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::{JoinHandle, JoinSet};
 use tuirealm::listener::{ListenerResult, Poll};
 use tuirealm::Event;
 
+// Don't flood the UI with progress updates for every chunk
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// How many of a page's stylesheets and images are fetched at once
+const MAX_SUBRESOURCE_FETCHES: usize = 6;
+/// Stylesheets and images bigger than this are left out
+const MAX_SUBRESOURCE: usize = 8 * 1024 * 1024;
+
 #[derive(PartialEq, Eq, Clone, PartialOrd)]
 pub enum RetumiEvent {
     PageReady,
+    LoadProgress,
+    DownloadsChanged,
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub url: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    /// The redirects followed on the way to `url`, oldest first
+    pub redirects: Vec<Hop>,
+    /// The body was cut off at `net::MAX_BODY`
+    pub truncated: bool,
+}
+
+impl Response {
+    /// The MIME type essence, without parameters such as the charset
+    pub fn mime_type(&self) -> Option<String> {
+        let content_type = self.content_type.as_deref()?;
+        let essence = content_type.split(';').next()?.trim();
+        if essence.is_empty() {
+            None
+        } else {
+            Some(essence.to_ascii_lowercase())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubresourceKind {
+    Stylesheet,
+    Image,
+}
+
+/// Something the page being shown needs, fetched after it's on screen
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subresource {
+    pub kind: SubresourceKind,
+    pub url: String,
+}
+
+impl Subresource {
+    /// Whether `response` is what we asked for, rather than an error page
+    fn accepts(&self, response: &Response) -> bool {
+        response.mime_type().is_none_or(|mime| match self.kind {
+            SubresourceKind::Stylesheet => mime == "text/css",
+            SubresourceKind::Image => mime.starts_with("image/"),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Loaded {
+    Page(Response),
+    /// The resource can't be rendered, so it was handed to the download manager
+    Download(PathBuf),
+    /// The server needs some input from the user first
+    Prompt(Prompt),
+    Login(Login),
+    /// An error page for a certificate we couldn't verify. `host` is set
+    /// when the user may add an exception for it.
+    Untrusted {
+        page: Response,
+        host: Option<String>,
+    },
+    Failed(String),
+    /// Something the page on screen asked for
+    Subresource(Subresource, Response),
+}
+
+impl From<Option<Response>> for Loaded {
+    fn from(response: Option<Response>) -> Self {
+        response.map_or_else(
+            || Loaded::Failed(String::from("could not load URL")),
+            Loaded::Page,
+        )
+    }
+}
+
+impl From<RetumiError> for Loaded {
+    fn from(err: RetumiError) -> Self {
+        tracing::warn!("{err}");
+        Loaded::Failed(err.to_string())
+    }
+}
+
+impl From<Result<Response, RetumiError>> for Loaded {
+    fn from(result: Result<Response, RetumiError>) -> Self {
+        result.map_or_else(Loaded::from, Loaded::Page)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prompt {
+    pub text: String,
+    /// The URL that asked for input
+    pub url: String,
+    /// Whether the input should be hidden as it's typed
+    pub sensitive: bool,
+}
+
+/// A server wants credentials before it will show `url`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Login {
+    pub url: String,
+    pub realm: String,
+    /// Whether the credentials we sent were rejected
+    pub rejected: bool,
+}
+
+impl Prompt {
+    /// The URL to load to submit `input`
+    pub fn answer(&self, input: &str) -> String {
+        let query = percent_encoding::utf8_percent_encode(input, NON_ALPHANUMERIC).to_string();
+        // Gopher searches send the query after a tab in the selector
+        if self.url.to_ascii_lowercase().starts_with("gopher:") {
+            return format!("{}%09{query}", self.url);
+        }
+        match Url::parse(&self.url) {
+            Ok(mut url) => {
+                url.set_query(Some(&query));
+                url.to_string()
+            }
+            Err(_) => format!("{}?{query}", self.url),
+        }
+    }
+}
+
+impl From<&CacheEntry> for Response {
+    fn from(entry: &CacheEntry) -> Self {
+        Self {
+            url: entry.final_url.clone().unwrap_or_else(|| entry.url.clone()),
+            content_type: entry.content_type.clone(),
+            body: entry.body.clone(),
+            redirects: entry.redirects.clone(),
+            truncated: false,
+        }
+    }
+}
+
+/// The load currently shown in the status bar, if any
+#[derive(Clone, Debug)]
+pub struct LoadState {
+    pub url: String,
+    pub received: u64,
+    pub total: Option<u64>,
+    pub started: Instant,
+}
+
+pub type LoadStatus = Arc<Mutex<Option<LoadState>>>;
+
+/// Requests from the UI side to the network task
+#[derive(Debug)]
+enum Command {
+    Load(u64, String),
+    Cancel(u64),
+    Download(String),
+    CancelDownload(usize),
+    Login(Login, Credentials, bool),
+    Trust(String),
+    /// Fetch what the page from a load needs
+    Subresources(u64, Vec<Subresource>),
+    Quit,
+}
+
+/// Reports from the network task back to the UI side
+enum Update {
+    Progress {
+        id: u64,
+        received: u64,
+        total: Option<u64>,
+    },
+    Done {
+        id: u64,
+        loaded: Loaded,
+    },
+    Fetched {
+        id: u64,
+        subresource: Subresource,
+        response: Response,
+    },
 }
 
 pub struct HttpClient {
     rx: Receiver<Msg>,
-    tok_tx: tokio::sync::mpsc::Sender<Msg>,
+    tok_tx: tokio::sync::mpsc::Sender<Command>,
+    updates_rx: Receiver<Update>,
+    page_tx: Sender<Loaded>,
+    /// What the page on screen asks for once it's shown
+    subresources_rx: Receiver<Vec<Subresource>>,
+    downloads_rx: Receiver<()>,
+    status: LoadStatus,
+    history: History,
+    current: Option<u64>,
+    /// The load whose page is on screen, which subresources are fetched for
+    shown: Option<u64>,
+    next_id: u64,
     runtime: Runtime,
 }
 
 impl HttpClient {
-    pub fn new(rx: Receiver<Msg>, tx: Sender<Option<String>>) -> Self {
+    pub fn new(
+        rx: Receiver<Msg>,
+        page_tx: Sender<Loaded>,
+        subresources_rx: Receiver<Vec<Subresource>>,
+        session: Session,
+        config: &Config,
+        downloads: DownloadList,
+        status: LoadStatus,
+    ) -> Self {
         let (tok_tx, mut tok_rx) = tokio::sync::mpsc::channel(16);
+        let (updates_tx, updates_rx) = crossbeam::channel::unbounded();
+        let (downloads_tx, downloads_rx) = crossbeam::channel::bounded(1);
+        let history = History::default();
 
         {
+            let cache = Arc::new(Mutex::new(HttpCache::new(config.cache.disk_dir())));
+            let downloads = Arc::new(Mutex::new(DownloadManager::new(
+                session.clone(),
+                config.downloads.dir(),
+                downloads,
+                downloads_tx,
+            )));
+            let credentials = Arc::new(Mutex::new(CredentialStore::load()));
+            let about = Arc::new(About::new(
+                config.clone(),
+                history.clone(),
+                session.tls().clone(),
+            ));
+            let gemini = Arc::new(GeminiClient::new(&config.http, session.tls().clone()));
+            let gopher = Arc::new(GopherClient::new(&config.http));
+
             tokio::spawn(async move {
+                let mut loads: HashMap<u64, JoinHandle<()>> = HashMap::new();
                 loop {
-                    let msg = tok_rx.recv().await.unwrap();
-                    match msg {
-                        Msg::UrlSubmit(url) => {
-                            let contents = reqwest::get(url).await;
-                            let contents = match contents {
-                                Ok(c) => c.text().await.ok(),
-                                Err(_) => None,
+                    let cmd = tok_rx.recv().await.unwrap();
+                    match cmd {
+                        Command::Load(id, url) => {
+                            loads.retain(|_, task| !task.is_finished());
+
+                            let session = session.clone();
+                            let cache = cache.clone();
+                            let credentials = credentials.clone();
+                            let downloads = downloads.clone();
+                            let about = about.clone();
+                            let gemini = gemini.clone();
+                            let gopher = gopher.clone();
+                            let updates = updates_tx.clone();
+                            let task = tokio::spawn(async move {
+                                let mut last_report = Instant::now();
+                                let progress = |received, total| {
+                                    if last_report.elapsed() >= PROGRESS_INTERVAL {
+                                        last_report = Instant::now();
+                                        let _ = updates.send(Update::Progress {
+                                            id,
+                                            received,
+                                            total,
+                                        });
+                                    }
+                                };
+
+                                let loaded = match scheme(&url).as_str() {
+                                    "about" => Loaded::Page(about.page(&url)),
+                                    "data" => data_url::parse(&url).into(),
+                                    "gemini" => {
+                                        gemini.fetch(&url).await.unwrap_or_else(Loaded::from)
+                                    }
+                                    "gopher" => {
+                                        gopher.fetch(&url).await.unwrap_or_else(Loaded::from)
+                                    }
+                                    _ => {
+                                        // The fragment is ours alone: it isn't sent or cached
+                                        let (resource, fragment) = match url.split_once('#') {
+                                            Some((resource, fragment)) => {
+                                                (resource, Some(fragment))
+                                            }
+                                            None => (url.as_str(), None),
+                                        };
+                                        let loaded = fetch(
+                                            &session,
+                                            &cache,
+                                            &credentials,
+                                            &downloads,
+                                            progress,
+                                            resource,
+                                        )
+                                        .await;
+                                        with_fragment(loaded, fragment)
+                                    }
+                                };
+                                let _ = updates.send(Update::Done { id, loaded });
+                            });
+                            loads.insert(id, task);
+                        }
+                        Command::Subresources(id, subresources) => {
+                            loads.retain(|_, task| !task.is_finished());
+
+                            let session = session.clone();
+                            let cache = cache.clone();
+                            let updates = updates_tx.clone();
+                            // Dropping the set when the load is cancelled aborts every fetch
+                            let task = tokio::spawn(async move {
+                                let limit = Arc::new(Semaphore::new(MAX_SUBRESOURCE_FETCHES));
+                                let mut fetches = JoinSet::new();
+                                for subresource in subresources {
+                                    let session = session.clone();
+                                    let cache = cache.clone();
+                                    let updates = updates.clone();
+                                    let limit = limit.clone();
+                                    fetches.spawn(async move {
+                                        let Ok(_permit) = limit.acquire().await else {
+                                            return;
+                                        };
+                                        let url = &subresource.url;
+                                        match fetch_subresource(&session, &cache, url).await {
+                                            Ok(response) if subresource.accepts(&response) => {
+                                                let _ = updates.send(Update::Fetched {
+                                                    id,
+                                                    subresource,
+                                                    response,
+                                                });
+                                            }
+                                            Ok(_) => tracing::warn!("{url} is of the wrong type"),
+                                            Err(err) => {
+                                                tracing::warn!("could not load {url}: {err}")
+                                            }
+                                        }
+                                    });
+                                }
+                                while fetches.join_next().await.is_some() {}
+                            });
+                            loads.insert(id, task);
+                        }
+                        Command::Cancel(id) => {
+                            if let Some(task) = loads.remove(&id) {
+                                tracing::info!("cancelling request {id}");
+                                task.abort();
+                            }
+                        }
+                        Command::Download(url) => {
+                            downloads.lock().unwrap().start(url);
+                        }
+                        Command::CancelDownload(id) => downloads.lock().unwrap().cancel(id),
+                        Command::Login(login, user, remember) => {
+                            let Ok(url) = Url::parse(&login.url) else {
+                                continue;
                             };
-                            // TODO: send an error type back for helpful error messages
-                            tx.send(contents).unwrap();
+                            let stored = credentials.lock().unwrap().insert(
+                                &url,
+                                &login.realm,
+                                user,
+                                remember,
+                            );
+                            if let Err(err) = stored {
+                                tracing::warn!("could not save credentials: {err}");
+                            }
                         }
-                        Msg::Quit => break,
-                        _ => {}
+                        Command::Trust(host) => {
+                            if let Err(err) = session.tls().add_exception(&host) {
+                                tracing::warn!("could not save certificate exception: {err}");
+                            }
+                        }
+                        Command::Quit => break,
                     }
                 }
             });
@@ -48,29 +413,447 @@ impl HttpClient {
         Self {
             rx,
             tok_tx,
+            updates_rx,
+            page_tx,
+            subresources_rx,
+            downloads_rx,
+            status,
+            history,
+            current: None,
+            shown: None,
+            next_id: 0,
             runtime,
         }
     }
 
-    pub async fn start_page_load(&self, url: String) {
+    fn send(&self, cmd: Command) {
         let tx = self.tok_tx.clone();
-        tx.send(Msg::UrlSubmit(url)).await.unwrap();
+        self.runtime.block_on(async move {
+            tx.send(cmd)
+                .await
+                .expect("could not send message to I/O thread");
+        });
+    }
+
+    fn start_page_load(&mut self, url: String) {
+        // Whatever was loading before is now stale
+        self.cancel_page_load();
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.current = Some(id);
+        *self.status.lock().unwrap() = Some(LoadState {
+            url: url.clone(),
+            received: 0,
+            total: None,
+            started: Instant::now(),
+        });
+
+        self.send(Command::Load(id, url));
+    }
+
+    fn cancel_page_load(&mut self) {
+        if let Some(id) = self.current.take() {
+            self.send(Command::Cancel(id));
+        }
+        *self.status.lock().unwrap() = None;
+    }
+
+    fn handle_update(&mut self, update: Update) -> Option<Event<RetumiEvent>> {
+        match update {
+            Update::Progress {
+                id,
+                received,
+                total,
+            } if self.current == Some(id) => {
+                if let Some(state) = self.status.lock().unwrap().as_mut() {
+                    state.received = received;
+                    state.total = total;
+                }
+                Some(Event::User(RetumiEvent::LoadProgress))
+            }
+            Update::Done { id, loaded } if self.current == Some(id) => {
+                self.current = None;
+                if let Loaded::Page(response) = &loaded {
+                    if !response.url.starts_with("about:") {
+                        self.history.record(&response.url);
+                    }
+                }
+                if let Loaded::Page(_) | Loaded::Untrusted { .. } = &loaded {
+                    // The page it replaces doesn't need anything more
+                    if let Some(previous) = self.shown.replace(id) {
+                        self.send(Command::Cancel(previous));
+                    }
+                }
+                *self.status.lock().unwrap() = None;
+                self.page_tx.send(loaded).ok()?;
+                Some(Event::User(RetumiEvent::PageReady))
+            }
+            Update::Done { id, .. } => {
+                tracing::debug!("discarding stale response for request {id}");
+                None
+            }
+            Update::Fetched {
+                id,
+                subresource,
+                response,
+            } if self.shown == Some(id) => {
+                self.page_tx
+                    .send(Loaded::Subresource(subresource, response))
+                    .ok()?;
+                Some(Event::User(RetumiEvent::PageReady))
+            }
+            Update::Progress { .. } | Update::Fetched { .. } => None,
+        }
     }
 }
 
+/// Carry the fragment of the URL we were asked for over to the page we got,
+/// unless a redirect gave it one of its own
+fn with_fragment(loaded: Loaded, fragment: Option<&str>) -> Loaded {
+    match (loaded, fragment) {
+        (Loaded::Page(mut response), Some(fragment)) if !response.url.contains('#') => {
+            response.url = format!("{}#{fragment}", response.url);
+            Loaded::Page(response)
+        }
+        (loaded, _) => loaded,
+    }
+}
+
+fn scheme(url: &str) -> String {
+    url.split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_download(headers: &HeaderMap) -> bool {
+    let attachment = headers
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|cd| cd.to_str().ok())
+        .is_some_and(|cd| cd.trim().to_ascii_lowercase().starts_with("attachment"));
+    let binary = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(|ct| ct.split(';').next())
+        .is_some_and(|mime| ContentKind::from_mime(mime) == ContentKind::Binary);
+
+    attachment || binary
+}
+
+// Every lock on the cache and download manager is released before the next
+// await point, so that concurrent loads don't block each other.
+async fn fetch(
+    session: &Session,
+    cache: &Mutex<HttpCache>,
+    credentials: &Mutex<CredentialStore>,
+    downloads: &Mutex<DownloadManager>,
+    mut progress: impl FnMut(u64, Option<u64>),
+    url: &str,
+) -> Loaded {
+    // What the request is sent with, to match stored responses that Vary on it
+    let mut request = session.headers(url);
+    let mut validators = HeaderMap::new();
+    {
+        let mut cache = cache.lock().unwrap();
+        match cache.get(url, &request) {
+            Some(entry) if entry.is_fresh(std::time::SystemTime::now()) => {
+                return Loaded::Page(Response::from(entry));
+            }
+            Some(entry) => validators = entry.validators(),
+            None => {}
+        }
+    }
+
+    let (mut response, mut redirects) = match session.send(url, &validators).await {
+        Ok(sent) => sent,
+        // Never fall back to the cache here: the server may be an impostor
+        Err(err) if tls::is_certificate_error(&err) => return untrusted(session, url, err),
+        Err(err) => {
+            // Offline mode: fall back to whatever we have stored, however stale
+            tracing::warn!("request for {url} failed, trying cache: {err}");
+            return match cache.lock().unwrap().get(url, &request) {
+                Some(entry) => Loaded::Page(Response::from(entry)),
+                None => Loaded::from(err),
+            };
+        }
+    };
+
+    if response.status() == StatusCode::UNAUTHORIZED {
+        let target = response.url().clone();
+        let challenges = auth::challenges(response.headers());
+        let stored = credentials.lock().unwrap().find(&target, &challenges);
+
+        if let Some((challenge, user)) = stored {
+            // Unless we were redirected, the retry is for the URL the validators describe
+            if !redirects.is_empty() {
+                validators.clear();
+            }
+            let sent = send_with_credentials(
+                session,
+                &validators,
+                &mut request,
+                &target,
+                &challenge,
+                &user,
+            )
+            .await;
+            match sent {
+                Ok((retried, hops)) => {
+                    response = retried;
+                    redirects.extend(hops);
+                }
+                Err(err) => return Loaded::from(err),
+            }
+
+            // An expired nonce says nothing about the credentials, so answer the new one
+            let stale = auth::challenges(response.headers())
+                .into_iter()
+                .find(|fresh| fresh.is_stale() && fresh.realm() == challenge.realm());
+            if let Some(fresh) = stale.filter(|_| response.status() == StatusCode::UNAUTHORIZED) {
+                if !redirects.is_empty() {
+                    validators.clear();
+                }
+                let sent = send_with_credentials(
+                    session,
+                    &validators,
+                    &mut request,
+                    &target,
+                    &fresh,
+                    &user,
+                )
+                .await;
+                match sent {
+                    Ok((retried, hops)) => {
+                        response = retried;
+                        redirects.extend(hops);
+                    }
+                    Err(err) => return Loaded::from(err),
+                }
+            }
+
+            if response.status() == StatusCode::UNAUTHORIZED {
+                let forgotten = credentials
+                    .lock()
+                    .unwrap()
+                    .forget(&target, challenge.realm());
+                if let Err(err) = forgotten {
+                    tracing::warn!("could not forget credentials: {err}");
+                }
+                return Loaded::Login(Login {
+                    url: target.to_string(),
+                    realm: challenge.realm().to_string(),
+                    rejected: true,
+                });
+            }
+        } else if let Some(challenge) = challenges.first() {
+            return Loaded::Login(Login {
+                url: target.to_string(),
+                realm: challenge.realm().to_string(),
+                rejected: false,
+            });
+        }
+        // Otherwise it's a scheme we can't answer, so show whatever the server sent
+    }
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return cache
+            .lock()
+            .unwrap()
+            .revalidate(url, response.headers())
+            .map(|entry| Response {
+                redirects,
+                ..Response::from(entry)
+            })
+            .into();
+    }
+
+    if response.status().is_success() && is_download(response.headers()) {
+        let path = downloads
+            .lock()
+            .unwrap()
+            .adopt(response.url().to_string(), response);
+        return Loaded::Download(path);
+    }
+
+    let status = response.status();
+    let headers = response.headers().clone();
+    let final_url = response.url().to_string();
+    let total = response.content_length();
+
+    let mut body = Vec::new();
+    let mut truncated = false;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                let room = MAX_BODY - body.len();
+                if chunk.len() > room {
+                    tracing::warn!("{url} is larger than {MAX_BODY} bytes, cutting it off");
+                    body.extend_from_slice(&chunk[..room]);
+                    truncated = true;
+                    break;
+                }
+                body.extend_from_slice(&chunk);
+                progress(body.len() as u64, total);
+            }
+            Ok(None) => break,
+            Err(err) => {
+                tracing::warn!("error while reading {url}: {err}");
+                return Loaded::Failed(err.to_string());
+            }
+        }
+    }
+    // Only part of the page, which mustn't be served later as if it were all of it
+    if !truncated {
+        cache.lock().unwrap().store(
+            url, &request, &final_url, &redirects, status, &headers, &body,
+        );
+    }
+
+    Loaded::Page(Response {
+        url: final_url,
+        content_type: headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map(String::from),
+        body,
+        redirects,
+        truncated,
+    })
+}
+
+/// Fetch a stylesheet or image for a page. Unlike `fetch`, this never asks
+/// for credentials or hands the response to the download manager, and it
+/// gives up on anything bigger than `MAX_SUBRESOURCE`.
+async fn fetch_subresource(
+    session: &Session,
+    cache: &Mutex<HttpCache>,
+    url: &str,
+) -> Result<Response, RetumiError> {
+    if scheme(url) == "data" {
+        return data_url::parse(url);
+    }
+
+    let request = session.headers(url);
+    let mut validators = HeaderMap::new();
+    {
+        let mut cache = cache.lock().unwrap();
+        match cache.get(url, &request) {
+            Some(entry) if entry.is_fresh(std::time::SystemTime::now()) => {
+                return Ok(Response::from(entry));
+            }
+            Some(entry) => validators = entry.validators(),
+            None => {}
+        }
+    }
+
+    let (response, redirects) = session.send(url, &validators).await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return cache
+            .lock()
+            .unwrap()
+            .revalidate(url, response.headers())
+            .map(Response::from)
+            .ok_or_else(|| RetumiError::SubresourceError(format!("{url} is no longer cached")));
+    }
+    let mut response = response.error_for_status()?;
+
+    let too_big = || {
+        RetumiError::SubresourceError(format!(
+            "{url} is over {}",
+            content::format_size(MAX_SUBRESOURCE)
+        ))
+    };
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_SUBRESOURCE as u64)
+    {
+        return Err(too_big());
+    }
+    let status = response.status();
+    let headers = response.headers().clone();
+    let final_url = response.url().to_string();
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_SUBRESOURCE {
+            return Err(too_big());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    cache.lock().unwrap().store(
+        url, &request, &final_url, &redirects, status, &headers, &body,
+    );
+
+    Ok(Response {
+        url: final_url,
+        content_type: headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map(String::from),
+        body,
+        redirects,
+        truncated: false,
+    })
+}
+
+fn untrusted(session: &Session, url: &str, err: RetumiError) -> Loaded {
+    tracing::warn!("certificate error for {url}: {err}");
+    let Ok(parsed) = Url::parse(url) else {
+        return Loaded::from(err);
+    };
+
+    // The verifier recorded what the server sent while the handshake failed
+    let inspection = parsed
+        .host_str()
+        .and_then(|host| session.tls().inspection(host));
+    let self_signed = inspection
+        .as_ref()
+        .is_some_and(|i| i.problem == Some(CertProblem::SelfSigned));
+
+    Loaded::Untrusted {
+        page: about::certificate_error(url, &err, inspection.as_ref()),
+        host: parsed.host_str().filter(|_| self_signed).map(String::from),
+    }
+}
+
+/// Retry `url` with `credentials`, recording the Authorization header in `request`
+async fn send_with_credentials(
+    session: &Session,
+    validators: &HeaderMap,
+    request: &mut HeaderMap,
+    url: &Url,
+    challenge: &Challenge,
+    credentials: &Credentials,
+) -> Result<(reqwest::Response, Vec<Hop>), RetumiError> {
+    let authorization = challenge.authorization(credentials, url)?;
+    request.insert(AUTHORIZATION, authorization.clone());
+    let mut headers = validators.clone();
+    headers.insert(AUTHORIZATION, authorization);
+    session.send(url.as_str(), &headers).await
+}
+
 impl Drop for HttpClient {
     fn drop(&mut self) {
-        let tx = self.tok_tx.clone();
-        self.runtime.block_on(async move {
-            tx.send(Msg::Quit)
-                .await
-                .expect("could not send termination message to I/O thread");
-        });
+        self.send(Command::Quit);
     }
 }
 
 impl Poll<RetumiEvent> for HttpClient {
     fn poll(&mut self) -> ListenerResult<Option<Event<RetumiEvent>>> {
+        if self.downloads_rx.try_recv().is_ok() {
+            return Ok(Some(Event::User(RetumiEvent::DownloadsChanged)));
+        }
+
+        if let Ok(update) = self.updates_rx.try_recv() {
+            return Ok(self.handle_update(update));
+        }
+
+        if let Ok(subresources) = self.subresources_rx.try_recv() {
+            if let Some(id) = self.shown {
+                self.send(Command::Subresources(id, subresources));
+            }
+            return Ok(None);
+        }
+
         if !self.rx.is_empty() {
             let msg = self
                 .rx
@@ -78,10 +861,31 @@ impl Poll<RetumiEvent> for HttpClient {
                 .map_err(|_| tuirealm::ListenerError::PollFailed)?;
             match msg {
                 Msg::UrlSubmit(url) => {
-                    self.runtime.block_on(async {
-                        self.start_page_load(url).await;
-                    });
-                    Ok(Some(Event::User(RetumiEvent::PageReady)))
+                    self.start_page_load(url);
+                    Ok(Some(Event::User(RetumiEvent::LoadProgress)))
+                }
+                Msg::CancelLoad => {
+                    self.cancel_page_load();
+                    Ok(Some(Event::User(RetumiEvent::LoadProgress)))
+                }
+                Msg::Download(url) => {
+                    self.send(Command::Download(url));
+                    Ok(None)
+                }
+                Msg::CancelDownload(id) => {
+                    self.send(Command::CancelDownload(id));
+                    Ok(None)
+                }
+                Msg::TrustHost(host, url) => {
+                    self.send(Command::Trust(host));
+                    self.start_page_load(url);
+                    Ok(Some(Event::User(RetumiEvent::LoadProgress)))
+                }
+                Msg::LoginSubmit(login, credentials, remember) => {
+                    let url = login.url.clone();
+                    self.send(Command::Login(login, credentials, remember));
+                    self.start_page_load(url);
+                    Ok(Some(Event::User(RetumiEvent::LoadProgress)))
                 }
                 _ => Ok(None),
             }
@@ -0,0 +1,131 @@
+use regex::{Regex, RegexBuilder};
+use unicode_width::UnicodeWidthStr;
+
+use crate::render::Line;
+
+/// What the find bar is looking for
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    pub query: String,
+    pub case_sensitive: bool,
+    pub regex: bool,
+}
+
+impl Search {
+    /// Returns `None` while there's nothing to look for
+    pub fn compile(&self) -> Result<Option<Regex>, regex::Error> {
+        if self.query.is_empty() {
+            return Ok(None);
+        }
+
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+            .map(Some)
+    }
+}
+
+/// A match of a search, in columns of a rendered line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn matches(regex: &Regex, lines: &[Line]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for (n, line) in lines.iter().enumerate() {
+        let text = line.text();
+        for found in regex.find_iter(&text).filter(|m| !m.is_empty()) {
+            let start = text[..found.start()].width();
+            matches.push(Match {
+                line: n,
+                start,
+                end: start + found.as_str().width(),
+            });
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuirealm::props::TextSpan;
+
+    fn lines(texts: &[&str]) -> Vec<Line> {
+        texts
+            .iter()
+            .map(|text| Line::from(TextSpan::new(*text)))
+            .collect()
+    }
+
+    fn search(query: &str, case_sensitive: bool, regex: bool) -> Option<Regex> {
+        Search {
+            query: query.to_string(),
+            case_sensitive,
+            regex,
+        }
+        .compile()
+        .unwrap()
+    }
+
+    fn found(regex: &Regex, texts: &[&str]) -> Vec<(usize, usize, usize)> {
+        matches(regex, &lines(texts))
+            .into_iter()
+            .map(|m| (m.line, m.start, m.end))
+            .collect()
+    }
+
+    #[test]
+    fn empty_query_finds_nothing() {
+        assert!(search("", false, false).is_none());
+        assert!(search("", true, true).is_none());
+    }
+
+    #[test]
+    fn case_sensitivity_toggle() {
+        let texts = ["Rust and rust", "RUST"];
+        let insensitive = search("rust", false, false).unwrap();
+        assert_eq!(
+            found(&insensitive, &texts),
+            [(0, 0, 4), (0, 9, 13), (1, 0, 4)]
+        );
+        let sensitive = search("rust", true, false).unwrap();
+        assert_eq!(found(&sensitive, &texts), [(0, 9, 13)]);
+    }
+
+    #[test]
+    fn regex_toggle() {
+        let texts = ["a.c abc a+c"];
+        let literal = search("a.c", false, false).unwrap();
+        assert_eq!(found(&literal, &texts), [(0, 0, 3)]);
+        let regex = search("a.c", false, true).unwrap();
+        assert_eq!(found(&regex, &texts), [(0, 0, 3), (0, 4, 7), (0, 8, 11)]);
+
+        // Only in regex mode can a pattern be malformed
+        assert!(search("a(", false, false).is_some());
+        assert!(Search {
+            query: String::from("a("),
+            case_sensitive: false,
+            regex: true,
+        }
+        .compile()
+        .is_err());
+    }
+
+    #[test]
+    fn matches_are_in_columns_and_never_empty() {
+        let texts = ["日本語 text"];
+        let text = search("text", false, false).unwrap();
+        assert_eq!(found(&text, &texts), [(0, 7, 11)]);
+        let empty = search("x*", false, true).unwrap();
+        assert_eq!(found(&empty, &texts), [(0, 9, 10)]);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::{StatusCode, Url};
+use rustls::pki_types::ServerName;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{self, HttpConfig};
+use crate::error::RetumiError;
+use crate::event::{Loaded, Prompt, Response};
+use crate::net::{self, Hop, MAX_BODY};
+use crate::tls::{fingerprint, TlsSettings};
+
+const DEFAULT_PORT: u16 = 1965;
+// Two status digits, a space, at most 1024 bytes of meta and the CRLF
+const MAX_HEADER_LEN: usize = 1029;
+
+/// Certificate fingerprints pinned the first time we saw each host,
+/// since capsules overwhelmingly use self-signed certificates
+struct KnownHosts {
+    path: PathBuf,
+    hosts: HashMap<String, String>,
+}
+
+impl KnownHosts {
+    fn load(path: PathBuf) -> Self {
+        let hosts = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(host, fingerprint)| (host.to_string(), fingerprint.trim().to_string()))
+            .collect();
+        Self { path, hosts }
+    }
+
+    fn check(&mut self, host: &str, fingerprint: &str) -> Result<(), RetumiError> {
+        match self.hosts.get(host) {
+            Some(known) if known == fingerprint => Ok(()),
+            Some(_) => Err(RetumiError::GeminiError(format!(
+                "the certificate for {host} has changed since the last visit. \
+                 If this is expected, remove its line from {}",
+                self.path.display()
+            ))),
+            None => {
+                tracing::info!("trusting {host} on first use: {fingerprint}");
+                self.hosts.insert(host.to_string(), fingerprint.to_string());
+                self.save()
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), RetumiError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .hosts
+            .iter()
+            .map(|(host, fingerprint)| format!("{host} {fingerprint}\n"))
+            .collect();
+        Ok(std::fs::write(&self.path, contents)?)
+    }
+}
+
+pub struct GeminiClient {
+    known_hosts: Mutex<KnownHosts>,
+    tls: TlsSettings,
+    connect_timeout: Duration,
+    max_redirects: usize,
+}
+
+impl GeminiClient {
+    pub fn new(config: &HttpConfig, tls: TlsSettings) -> Self {
+        Self {
+            known_hosts: Mutex::new(KnownHosts::load(
+                config::data_dir().join("gemini_known_hosts"),
+            )),
+            tls,
+            connect_timeout: Duration::from_secs(config.connect_timeout),
+            max_redirects: config.max_redirects,
+        }
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Loaded, RetumiError> {
+        let mut url = Url::parse(url).map_err(|err| RetumiError::UrlError(err.to_string()))?;
+        let mut redirects = Vec::new();
+
+        loop {
+            let (status, meta, body, truncated) = self.request(&url).await?;
+            match status / 10 {
+                1 => {
+                    return Ok(Loaded::Prompt(Prompt {
+                        text: meta,
+                        url: url.to_string(),
+                        sensitive: status == 11,
+                    }))
+                }
+                2 => {
+                    let content_type = if meta.is_empty() {
+                        String::from("text/gemini; charset=utf-8")
+                    } else {
+                        meta
+                    };
+                    return Ok(Loaded::Page(Response {
+                        url: url.to_string(),
+                        content_type: Some(content_type),
+                        body,
+                        redirects,
+                        truncated,
+                    }));
+                }
+                3 => {
+                    if redirects.len() >= self.max_redirects {
+                        return Err(RetumiError::RedirectError(format!(
+                            "gave up after {} redirects at {url}",
+                            redirects.len()
+                        )));
+                    }
+                    let next = url
+                        .join(&meta)
+                        .map_err(|err| RetumiError::UrlError(err.to_string()))?;
+                    // Following it here would send a Gemini request to, say, a web server
+                    if next.scheme() != "gemini" {
+                        return Err(RetumiError::RedirectError(format!(
+                            "{url} redirects to {next}, which isn't a Gemini URL"
+                        )));
+                    }
+                    // 30 and 31 mean the same as their HTTP counterparts
+                    let status = if status == 31 {
+                        StatusCode::MOVED_PERMANENTLY
+                    } else {
+                        StatusCode::FOUND
+                    };
+                    redirects.push(Hop {
+                        url: url.to_string(),
+                        status,
+                    });
+                    url = next;
+                }
+                6 => {
+                    return Err(RetumiError::GeminiError(format!(
+                        "{url} requires a client certificate, which isn't supported ({meta})"
+                    )))
+                }
+                _ => return Err(RetumiError::GeminiError(format!("{status} {meta}"))),
+            }
+        }
+    }
+
+    /// Make a single request, returning the status, meta, body and whether
+    /// the body was cut off
+    async fn request(&self, url: &Url) -> Result<(u8, String, Vec<u8>, bool), RetumiError> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| RetumiError::UrlError(format!("{url} has no host")))?;
+        let port = url.port().unwrap_or(DEFAULT_PORT);
+
+        let tcp = tokio::time::timeout(self.connect_timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| RetumiError::GeminiError(format!("timed out connecting to {host}")))??;
+
+        // We pin certificates ourselves rather than trusting a CA
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|err| RetumiError::UrlError(err.to_string()))?;
+        let mut stream = TlsConnector::from(Arc::new(self.tls.pinning_config()?))
+            .connect(name, tcp)
+            .await?;
+
+        let certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(<[_]>::first)
+            .ok_or_else(|| RetumiError::GeminiError(format!("{host} sent no certificate")))?;
+        self.known_hosts
+            .lock()
+            .unwrap()
+            .check(&format!("{host}:{port}"), &fingerprint(certificate))?;
+
+        stream.write_all(format!("{url}\r\n").as_bytes()).await?;
+        let mut response = Vec::new();
+        let limit = MAX_HEADER_LEN + MAX_BODY;
+        let truncated = match net::read_capped(&mut stream, limit, &mut response).await {
+            Ok(truncated) => truncated,
+            // Plenty of servers hang up without sending a TLS close_notify
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {
+                false
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if truncated {
+            tracing::warn!("{url} is larger than {MAX_BODY} bytes, cutting it off");
+        }
+
+        let (status, meta, body) = split_header(response)?;
+        Ok((status, meta, body, truncated))
+    }
+}
+
+/// Split a response into the status and meta from its header line, and the body
+fn split_header(mut response: Vec<u8>) -> Result<(u8, String, Vec<u8>), RetumiError> {
+    let header_end = response
+        .windows(2)
+        .take(MAX_HEADER_LEN)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(|| RetumiError::GeminiError(String::from("malformed response header")))?;
+    let header = String::from_utf8_lossy(&response[..header_end]).into_owned();
+    let body = response.split_off(header_end + 2);
+
+    let (status, meta) = header.split_once(' ').unwrap_or((&header, ""));
+    let status = status
+        .parse()
+        .map_err(|_| RetumiError::GeminiError(format!("invalid status {status:?}")))?;
+    Ok((status, meta.trim().to_string(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known_hosts(name: &str) -> KnownHosts {
+        let path = std::env::temp_dir()
+            .join(format!("retumi-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_file(&path);
+        KnownHosts::load(path)
+    }
+
+    #[test]
+    fn hosts_are_trusted_on_first_use() {
+        let mut hosts = known_hosts("first_use");
+        hosts.check("example.org:1965", "aa").unwrap();
+        hosts.check("example.org:1965", "aa").unwrap();
+        // Pinned per port
+        hosts.check("example.org:1966", "bb").unwrap();
+
+        // And remembered
+        let reloaded = KnownHosts::load(hosts.path.clone());
+        assert_eq!(reloaded.hosts, hosts.hosts);
+        std::fs::remove_file(&hosts.path).unwrap();
+    }
+
+    #[test]
+    fn changed_certificates_are_refused() {
+        let mut hosts = known_hosts("changed");
+        hosts.check("example.org:1965", "aa").unwrap();
+        assert!(matches!(
+            hosts.check("example.org:1965", "bb"),
+            Err(RetumiError::GeminiError(_))
+        ));
+        assert_eq!(hosts.hosts["example.org:1965"], "aa");
+        std::fs::remove_file(&hosts.path).unwrap();
+    }
+
+    #[test]
+    fn headers_are_split_from_the_body() {
+        let (status, meta, body) =
+            split_header(b"20 text/gemini; lang=en\r\n# Hi\r\n".to_vec()).unwrap();
+        assert_eq!((status, meta.as_str()), (20, "text/gemini; lang=en"));
+        assert_eq!(body, b"# Hi\r\n");
+
+        let (status, meta, body) = split_header(b"51\r\n".to_vec()).unwrap();
+        assert_eq!((status, meta.as_str(), body.len()), (51, "", 0));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert!(split_header(b"20 text/gemini".to_vec()).is_err());
+        assert!(split_header(b"OK text/gemini\r\n".to_vec()).is_err());
+        let long = format!("20 {}\r\n", "x".repeat(MAX_HEADER_LEN));
+        assert!(split_header(long.into_bytes()).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::doc::escape;
+
+/// Convert a text/gemini document to HTML, so that it goes through the same
+/// rendering and link navigation as any web page
+pub fn to_html(text: &str) -> String {
+    let mut html = String::from("<!DOCTYPE html><html><body>");
+    let mut preformatted = false;
+    let mut in_list = false;
+
+    for line in text.lines() {
+        if line.starts_with("```") {
+            if in_list {
+                html.push_str("</ul>");
+                in_list = false;
+            }
+            html.push_str(if preformatted { "</pre>" } else { "<pre>" });
+            preformatted = !preformatted;
+            continue;
+        }
+        if preformatted {
+            html.push_str(&escape(line));
+            html.push('\n');
+            continue;
+        }
+
+        let item = line.strip_prefix("* ");
+        if in_list && item.is_none() {
+            html.push_str("</ul>");
+        } else if !in_list && item.is_some() {
+            html.push_str("<ul>");
+        }
+        in_list = item.is_some();
+
+        if let Some(item) = item {
+            html.push_str(&format!("<li>{}</li>", escape(item)));
+        } else if let Some(link) = line.strip_prefix("=>") {
+            let link = link.trim();
+            let (url, label) = link
+                .split_once(char::is_whitespace)
+                .map_or((link, link), |(url, label)| (url, label.trim()));
+            html.push_str(&format!(
+                "<div><a href=\"{}\">{}</a></div>",
+                escape(url),
+                escape(label)
+            ));
+        } else if let Some(heading) = heading(line) {
+            html.push_str(&heading);
+        } else if let Some(quote) = line.strip_prefix('>') {
+            html.push_str(&format!(
+                "<blockquote>{}</blockquote>",
+                escape(quote.trim())
+            ));
+        } else if line.trim().is_empty() {
+            html.push_str("<br>");
+        } else {
+            html.push_str(&format!("<div>{}</div>", escape(line)));
+        }
+    }
+
+    if preformatted {
+        html.push_str("</pre>");
+    }
+    if in_list {
+        html.push_str("</ul>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn heading(line: &str) -> Option<String> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    if !(1..=3).contains(&level) {
+        return None;
+    }
+
+    Some(format!(
+        "<h{level}>{}</h{level}>",
+        escape(line[level..].trim())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(text: &str) -> String {
+        to_html(text)
+            .strip_prefix("<!DOCTYPE html><html><body>")
+            .and_then(|html| html.strip_suffix("</body></html>"))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn links_with_and_without_labels() {
+        assert_eq!(
+            body("=> gemini://example.org/ Example\n=>/relative"),
+            "<div><a href=\"gemini://example.org/\">Example</a></div>\
+             <div><a href=\"/relative\">/relative</a></div>"
+        );
+    }
+
+    #[test]
+    fn headings_quotes_and_text_are_escaped() {
+        assert_eq!(
+            body("# A & B\n### <c>\n#### Four\n> quoted"),
+            "<h1>A &amp; B</h1><h3>&lt;c&gt;</h3><div>#### Four</div>\
+             <blockquote>quoted</blockquote>"
+        );
+    }
+
+    #[test]
+    fn list_items_are_grouped() {
+        assert_eq!(
+            body("* one\n* two\ntext\n* three"),
+            "<ul><li>one</li><li>two</li></ul><div>text</div><ul><li>three</li></ul>"
+        );
+    }
+
+    #[test]
+    fn preformatted_text_is_kept_as_is() {
+        assert_eq!(
+            body("```alt\n* not a list\n=> not a link\n```\n"),
+            "<pre>* not a list\n=&gt; not a link\n</pre>"
+        );
+        // An unclosed block is closed at the end
+        assert_eq!(body("```\ncode"), "<pre>code\n</pre>");
+    }
+
+    #[test]
+    fn lists_close_before_preformatted_text() {
+        assert_eq!(
+            body("* item\n```\ncode\n```"),
+            "<ul><li>item</li></ul><pre>code\n</pre>"
+        );
+    }
+}
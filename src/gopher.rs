@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use percent_encoding::{AsciiSet, CONTROLS};
+use reqwest::Url;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::config::HttpConfig;
+use crate::doc::escape;
+use crate::error::RetumiError;
+use crate::event::{Loaded, Prompt, Response};
+use crate::net::{self, MAX_BODY};
+
+const DEFAULT_PORT: u16 = 70;
+// Characters in a selector that can't appear as-is in a URL path
+const SELECTOR: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`');
+/// Our own type for menus, which are converted to HTML when rendered
+pub const MENU_MIME: &str = "application/gopher-menu";
+
+pub struct GopherClient {
+    connect_timeout: Duration,
+}
+
+impl GopherClient {
+    pub fn new(config: &HttpConfig) -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(config.connect_timeout),
+        }
+    }
+
+    /// Fetch a `gopher://host:port/<type><selector>[%09<search>]` URL (RFC 4266)
+    pub async fn fetch(&self, url: &str) -> Result<Loaded, RetumiError> {
+        let parsed = Url::parse(url).map_err(|err| RetumiError::UrlError(err.to_string()))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| RetumiError::UrlError(format!("{url} has no host")))?;
+        let port = parsed.port().unwrap_or(DEFAULT_PORT);
+        let (item_type, selector) = item(&parsed);
+        let selector = selector.as_str();
+
+        if item_type == '7' && !selector.contains('\t') {
+            return Ok(Loaded::Prompt(Prompt {
+                text: format!("Search {host}"),
+                url: url.to_string(),
+                sensitive: false,
+            }));
+        }
+
+        let mut stream =
+            tokio::time::timeout(self.connect_timeout, TcpStream::connect((host, port)))
+                .await
+                .map_err(|_| {
+                    RetumiError::GopherError(format!("timed out connecting to {host}"))
+                })??;
+        stream
+            .write_all(format!("{selector}\r\n").as_bytes())
+            .await?;
+        let mut body = Vec::new();
+        let truncated = net::read_capped(&mut stream, MAX_BODY, &mut body).await?;
+        if truncated {
+            tracing::warn!("{url} is larger than {MAX_BODY} bytes, cutting it off");
+        }
+
+        let content_type = match item_type {
+            '1' | '7' => MENU_MIME,
+            '0' => {
+                strip_terminator(&mut body);
+                "text/plain"
+            }
+            'h' => "text/html",
+            'g' => "image/gif",
+            'I' => image_mime(selector),
+            _ => "application/octet-stream",
+        };
+
+        Ok(Loaded::Page(Response {
+            url: url.to_string(),
+            content_type: Some(content_type.to_string()),
+            body,
+            redirects: Vec::new(),
+            truncated,
+        }))
+    }
+}
+
+/// The item type and selector in a URL's path, where a search follows a `%09`
+fn item(url: &Url) -> (char, String) {
+    let mut path = percent_encoding::percent_decode_str(url.path())
+        .decode_utf8_lossy()
+        .into_owned();
+    // A '?' is just part of the selector as far as gopher is concerned
+    if let Some(query) = url.query() {
+        path = format!("{path}?{query}");
+    }
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    let mut chars = path.chars();
+    let item_type = chars.next().unwrap_or('1');
+    (item_type, chars.as_str().to_string())
+}
+
+/// Text items and menus end with a line holding a single '.'
+fn strip_terminator(body: &mut Vec<u8>) {
+    for terminator in [&b"\r\n.\r\n"[..], b"\n.\n", b"\r\n."] {
+        if body.ends_with(terminator) {
+            // Keep the newline that ends the last real line
+            let dot = terminator.iter().position(|&b| b == b'.').unwrap();
+            body.truncate(body.len() - terminator.len() + dot);
+            return;
+        }
+    }
+}
+
+fn image_mime(selector: &str) -> &'static str {
+    let extension = selector
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        // Type I promises an image without saying which kind
+        _ => "image/x-unknown",
+    }
+}
+
+/// Convert a gopher menu into HTML with a link for each item
+pub fn menu_to_html(menu: &str, base: &str) -> String {
+    let default_host = Url::parse(base)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+        .unwrap_or_default();
+
+    let mut html = String::from("<!DOCTYPE html><html><body><pre>");
+    for line in menu.lines() {
+        let line = line.trim_end_matches('\r');
+        if line == "." {
+            break;
+        }
+
+        let mut chars = line.chars();
+        let Some(item_type) = chars.next() else {
+            html.push('\n');
+            continue;
+        };
+        let mut fields = chars.as_str().split('\t');
+        let display = escape(fields.next().unwrap_or_default());
+        let selector = fields.next().unwrap_or_default();
+        let host = fields
+            .next()
+            .filter(|h| !h.is_empty())
+            .unwrap_or(&default_host);
+        let port = fields
+            .next()
+            .and_then(|p| p.trim().parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+
+        let item = match item_type {
+            'i' => format!("      {display}"),
+            '3' => format!("[ERR] {display}"),
+            // Telnet sessions are out of our league
+            '8' | 'T' => format!("[TEL] {display} ({host}:{port})"),
+            'h' if selector.starts_with("URL:") => link("[WWW]", &selector[4..], &display),
+            _ => {
+                let selector =
+                    percent_encoding::utf8_percent_encode(selector, SELECTOR).to_string();
+                let url = format!("gopher://{host}:{port}/{item_type}{selector}");
+                link(label(item_type), &url, &display)
+            }
+        };
+        html.push_str(&item);
+        html.push('\n');
+    }
+    html.push_str("</pre></body></html>");
+    html
+}
+
+fn link(label: &str, url: &str, display: &str) -> String {
+    format!("{label} <a href=\"{}\">{display}</a>", escape(url))
+}
+
+fn label(item_type: char) -> &'static str {
+    match item_type {
+        '0' => "[TXT]",
+        '1' => "[DIR]",
+        '7' => "[ASK]",
+        'h' => "[WWW]",
+        'g' | 'I' => "[IMG]",
+        's' => "[SND]",
+        _ => "[BIN]",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripped(body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        strip_terminator(&mut body);
+        body
+    }
+
+    fn menu(lines: &str) -> String {
+        menu_to_html(lines, "gopher://example.org/")
+            .strip_prefix("<!DOCTYPE html><html><body><pre>")
+            .and_then(|html| html.strip_suffix("</pre></body></html>"))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn terminators_are_stripped() {
+        assert_eq!(stripped(b"text\r\n.\r\n"), b"text\r\n");
+        assert_eq!(stripped(b"text\n.\n"), b"text\n");
+        assert_eq!(stripped(b"text\r\n."), b"text\r\n");
+        // A dot that's part of the text stays
+        assert_eq!(stripped(b"the end."), b"the end.");
+        assert_eq!(stripped(b"text\n"), b"text\n");
+    }
+
+    #[test]
+    fn menu_items_link_to_their_host_and_port() {
+        assert_eq!(
+            menu("1Docs\t/docs\tother.org\t7070\r\n0About\t/about.txt\texample.org\t70\r\n"),
+            "[DIR] <a href=\"gopher://other.org:7070/1/docs\">Docs</a>\n\
+             [TXT] <a href=\"gopher://example.org:70/0/about.txt\">About</a>\n"
+        );
+    }
+
+    #[test]
+    fn missing_hosts_and_ports_fall_back() {
+        assert_eq!(
+            menu("0No host\t/a\t\t71\n0No port\t/b\tother.org\n0Bad port\t/c\tother.org\tx\n"),
+            "[TXT] <a href=\"gopher://example.org:71/0/a\">No host</a>\n\
+             [TXT] <a href=\"gopher://other.org:70/0/b\">No port</a>\n\
+             [TXT] <a href=\"gopher://other.org:70/0/c\">Bad port</a>\n"
+        );
+    }
+
+    #[test]
+    fn special_item_types() {
+        assert_eq!(
+            menu(
+                "iJust <text>\tfake\t(NULL)\t0\n\
+                 3Oops\t\terror.host\t1\n\
+                 7Search\t/find\texample.org\t70\n\
+                 hSite\tURL:https://example.com/?a=1&b=2\texample.org\t70\n"
+            ),
+            "      Just &lt;text&gt;\n\
+             [ERR] Oops\n\
+             [ASK] <a href=\"gopher://example.org:70/7/find\">Search</a>\n\
+             [WWW] <a href=\"https://example.com/?a=1&amp;b=2\">Site</a>\n"
+        );
+    }
+
+    #[test]
+    fn selectors_are_encoded() {
+        assert_eq!(
+            menu("0Odd\t/a b?%\texample.org\t70\n"),
+            "[TXT] <a href=\"gopher://example.org:70/0/a%20b%3F%25\">Odd</a>\n"
+        );
+    }
+
+    #[test]
+    fn menus_stop_at_the_terminator() {
+        assert_eq!(menu("iBefore\r\n.\r\niAfter\r\n"), "      Before\n");
+    }
+
+    #[test]
+    fn searches_follow_a_tab() {
+        let url = Url::parse("gopher://example.org/7/find%09rust%20lang").unwrap();
+        assert_eq!(item(&url), ('7', String::from("/find\trust lang")));
+        let url = Url::parse("gopher://example.org/0/a?b").unwrap();
+        assert_eq!(item(&url), ('0', String::from("/a?b")));
+        let url = Url::parse("gopher://example.org").unwrap();
+        assert_eq!(item(&url).0, '1');
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use html2text::markup5ever_rcdom::RcDom;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use tuirealm::props::{Color, TextSpan};
+use tuirealm::ratatui::style::Modifier;
+
+use crate::doc;
+
+/// Stands in for an image in the document, followed by its index
+const MARKER: char = '\u{E002}';
+/// Images beyond this many on a page are left as their alt text
+const MAX_IMAGES: usize = 32;
+/// The most rows an image may take, so one picture can't fill several screens
+const MAX_ROWS: usize = 24;
+/// Anything smaller is an icon or a tracking pixel, and is left as its alt text
+const MIN_PIXELS: u32 = 8;
+/// Kitty takes image data in chunks no bigger than this
+const KITTY_CHUNK: usize = 4096;
+
+/// How images are shown, as set in the config
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    /// Whatever the terminal looks like it supports
+    #[default]
+    Auto,
+    Kitty,
+    Iterm2,
+    Sixel,
+    /// Unicode half blocks, which any terminal with colour can show
+    Blocks,
+    /// Don't fetch images at all
+    Off,
+}
+
+impl ImageMode {
+    /// How to draw images, or `None` if they're off
+    pub fn protocol(self) -> Option<Protocol> {
+        match self {
+            Self::Auto => Some(Protocol::detect()),
+            Self::Kitty => Some(Protocol::Kitty),
+            Self::Iterm2 => Some(Protocol::Iterm2),
+            Self::Sixel => Some(Protocol::Sixel),
+            Self::Blocks => Some(Protocol::Blocks),
+            Self::Off => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm2,
+    Sixel,
+    Blocks,
+}
+
+impl Protocol {
+    /// Guess from the environment, as asking the terminal would mean
+    /// reading its reply from under the event listener
+    fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+        // Multiplexers swallow graphics unless they're set up to pass them on
+        if std::env::var_os("TMUX").is_some() || term.starts_with("screen") {
+            Self::Blocks
+        } else if term == "xterm-kitty"
+            || term == "xterm-ghostty"
+            || program == "ghostty"
+            || std::env::var_os("KITTY_WINDOW_ID").is_some()
+        {
+            Self::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" || var("LC_TERMINAL") == "iTerm2" {
+            Self::Iterm2
+        } else if term.contains("sixel")
+            || ["foot", "mlterm", "yaft"]
+                .iter()
+                .any(|t| term.starts_with(t))
+            || program == "contour"
+        {
+            Self::Sixel
+        } else {
+            Self::Blocks
+        }
+    }
+}
+
+/// The size of a terminal cell in pixels. Only sixels need it exactly;
+/// the other protocols scale to the cells.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CellSize {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl CellSize {
+    /// Ask the terminal, or guess if it won't say
+    pub fn query() -> Self {
+        match crossterm::terminal::window_size() {
+            Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+                Self {
+                    width: usize::from(size.width / size.columns).max(1),
+                    height: usize::from(size.height / size.rows).max(1),
+                }
+            }
+            _ => Self::default(),
+        }
+    }
+}
+
+impl Default for CellSize {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 20,
+        }
+    }
+}
+
+/// An image on the page, and what to say in its place when images are off
+#[derive(Clone, Debug)]
+pub struct Picture {
+    /// Which of the page's images it shows
+    pub image: usize,
+    pub alt: String,
+}
+
+/// An image ready to be drawn at a cell on screen, once the frame around it has been
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    pub x: u16,
+    pub y: u16,
+    /// The escape sequence that draws it
+    pub data: String,
+}
+
+/// Filled in by the page as it draws, and painted by the model afterwards
+pub type Placements = Arc<Mutex<Vec<Placement>>>;
+
+/// The URLs of the images in `dom` worth fetching, each once.
+/// `resolve` makes a `src` absolute.
+pub fn sources(dom: &RcDom, resolve: impl Fn(&str) -> String) -> Vec<String> {
+    let mut seen = HashSet::new();
+    doc::images(&dom.document)
+        .iter()
+        .filter_map(|node| doc::attribute(node, "src"))
+        .map(|src| resolve(&src))
+        .filter(|url| seen.insert(url.clone()))
+        .take(MAX_IMAGES)
+        .collect()
+}
+
+/// Decode an image fetched from `url`, unless it's too small to be worth showing
+pub fn decode(url: &str, bytes: &[u8]) -> Option<DynamicImage> {
+    let image = image::load_from_memory(bytes)
+        .inspect_err(|err| tracing::warn!("could not decode image {url}: {err}"))
+        .ok()?;
+    (image.width() >= MIN_PIXELS && image.height() >= MIN_PIXELS).then_some(image)
+}
+
+/// Swap every `<img>` whose source is one of the `images` for a marker,
+/// returning the pictures in marker order. `resolve` makes a `src` absolute.
+pub fn extract(
+    dom: &RcDom,
+    images: &[(String, DynamicImage)],
+    resolve: impl Fn(&str) -> String,
+) -> Vec<Picture> {
+    let mut pictures = Vec::new();
+    for node in doc::images(&dom.document) {
+        let Some(src) = doc::attribute(&node, "src") else {
+            continue;
+        };
+        let url = resolve(&src);
+        let Some(image) = images.iter().position(|(u, _)| *u == url) else {
+            continue;
+        };
+
+        let marker = doc::element("div", Vec::new());
+        doc::prepend_text(&marker, &format!("{MARKER}{}", pictures.len()));
+        doc::replace(&node, &marker);
+        node.parent.take();
+        pictures.push(Picture {
+            image,
+            alt: doc::attribute(&node, "alt")
+                .or_else(|| doc::attribute(&node, "title"))
+                .unwrap_or_default(),
+        });
+    }
+    pictures
+}
+
+/// Which picture a rendered line stands in for, if any
+pub fn marker_index(text: &str) -> Option<usize> {
+    text.trim().strip_prefix(MARKER)?.parse().ok()
+}
+
+/// How many columns and rows `image` takes up, at most `width` columns wide
+pub fn size(image: &DynamicImage, width: usize, cell: CellSize) -> (usize, usize) {
+    let (w, h) = (
+        image.width().max(1) as usize,
+        image.height().max(1) as usize,
+    );
+    let rows = |columns: usize| (h * columns * cell.width).div_ceil(w * cell.height).max(1);
+    let mut columns = w.div_ceil(cell.width).clamp(1, width.max(1));
+    if rows(columns) > MAX_ROWS {
+        columns = (columns * MAX_ROWS / rows(columns)).max(1);
+    }
+    (columns, rows(columns).min(MAX_ROWS))
+}
+
+/// Draw `image` in `columns` by `rows` cells of half blocks, two pixels to a cell
+pub fn half_blocks(image: &DynamicImage, columns: usize, rows: usize) -> Vec<Vec<TextSpan>> {
+    let pixels = image
+        .resize_exact(columns as u32, rows as u32 * 2, FilterType::Triangle)
+        .to_rgba8();
+    let colour = |x: usize, y: usize| {
+        let pixel = pixels.get_pixel(x as u32, y as u32);
+        (pixel[3] >= 128).then(|| Color::Rgb(pixel[0], pixel[1], pixel[2]))
+    };
+
+    (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|x| {
+                    // A half block's other half shows the background, so
+                    // transparency has to be the background
+                    let (content, fg, bg) = match (colour(x, row * 2), colour(x, row * 2 + 1)) {
+                        (Some(top), bottom) => ("▀", top, bottom.unwrap_or(Color::Reset)),
+                        (None, Some(bottom)) => ("▄", bottom, Color::Reset),
+                        (None, None) => (" ", Color::Reset, Color::Reset),
+                    };
+                    TextSpan {
+                        content: content.to_string(),
+                        fg,
+                        bg,
+                        modifiers: Modifier::empty(),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Scale `image` to fill `columns` by `rows` cells
+pub fn scale(image: &DynamicImage, columns: usize, rows: usize, cell: CellSize) -> RgbaImage {
+    image
+        .resize_exact(
+            (columns * cell.width) as u32,
+            (rows * cell.height) as u32,
+            FilterType::Triangle,
+        )
+        .to_rgba8()
+}
+
+/// The escape sequence drawing `rows` of `scaled`, which is `columns` by
+/// `height` cells
+pub fn encode(
+    protocol: Protocol,
+    scaled: &RgbaImage,
+    columns: usize,
+    height: usize,
+    rows: Range<usize>,
+) -> Option<String> {
+    let (w, h) = scaled.dimensions();
+    let top = h * rows.start as u32 / height.max(1) as u32;
+    let bottom = (h * rows.end as u32 / height.max(1) as u32)
+        .max(top + 1)
+        .min(h);
+    let count = rows.len();
+    let pixels = imageops::crop_imm(scaled, 0, top, w, bottom - top).to_image();
+
+    match protocol {
+        Protocol::Kitty => Some(kitty(&pixels, columns, count)),
+        Protocol::Iterm2 => iterm2(pixels, columns, count),
+        Protocol::Sixel => Some(sixel(&pixels)),
+        Protocol::Blocks => None,
+    }
+}
+
+/// Draw `placements` over what's on screen, clearing kitty's previous
+/// images first as they aren't overwritten by text
+pub fn paint(
+    out: &mut impl Write,
+    protocol: Option<Protocol>,
+    placements: &[Placement],
+) -> std::io::Result<()> {
+    if protocol == Some(Protocol::Kitty) {
+        write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+    }
+    for placement in placements {
+        // Save the cursor and put it back, so the next frame finds it where it left it
+        write!(
+            out,
+            "\x1b7\x1b[{};{}H{}\x1b8",
+            placement.y + 1,
+            placement.x + 1,
+            placement.data
+        )?;
+    }
+    out.flush()
+}
+
+fn kitty(pixels: &RgbaImage, columns: usize, rows: usize) -> String {
+    let data = STANDARD.encode(pixels.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        // Base64 is ASCII, so any chunk of it is text
+        let chunk = String::from_utf8_lossy(chunk);
+        if i == 0 {
+            out.push_str(&format!(
+                "\x1b_Ga=T,f=32,s={},v={},c={columns},r={rows},C=1,q=2,m={more};{chunk}\x1b\\",
+                pixels.width(),
+                pixels.height()
+            ));
+        } else {
+            out.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+    out
+}
+
+fn iterm2(pixels: RgbaImage, columns: usize, rows: usize) -> Option<String> {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(pixels)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .inspect_err(|err| tracing::warn!("could not encode image for the terminal: {err}"))
+        .ok()?;
+    Some(format!(
+        "\x1b]1337;File=inline=1;size={};width={columns};height={rows};preserveAspectRatio=0:{}\x07",
+        png.len(),
+        STANDARD.encode(&png)
+    ))
+}
+
+/// Sixels in a 6×6×6 colour cube, leaving transparent pixels alone
+fn sixel(pixels: &RgbaImage) -> String {
+    let (width, height) = pixels.dimensions();
+    let mut out = format!("\x1bP0;1;0q\"1;1;{width};{height}");
+    for i in 0..216 {
+        let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+        out.push_str(&format!("#{i};2;{};{};{}", r * 20, g * 20, b * 20));
+    }
+
+    let level = |v: u8| (usize::from(v) * 5 + 127) / 255;
+    for band in (0..height).step_by(6) {
+        // The bits set in each column of the band, for each colour used in it
+        let mut colours: Vec<Option<Vec<u8>>> = vec![None; 216];
+        for dy in 0..6.min(height - band) {
+            for x in 0..width {
+                let pixel = pixels.get_pixel(x, band + dy);
+                if pixel[3] < 128 {
+                    continue;
+                }
+                let colour = level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]);
+                colours[colour].get_or_insert_with(|| vec![0; width as usize])[x as usize] |=
+                    1 << dy;
+            }
+        }
+
+        let mut first = true;
+        for (colour, bits) in colours.iter().enumerate() {
+            let Some(bits) = bits else {
+                continue;
+            };
+            // Go back to the start of the band to lay the next colour over it
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out.push_str(&format!("#{colour}"));
+            let mut run: Option<(u8, usize)> = None;
+            for &bit in bits {
+                match &mut run {
+                    Some((last, count)) if *last == bit => *count += 1,
+                    _ => {
+                        if let Some((last, count)) = run {
+                            push_run(&mut out, last, count);
+                        }
+                        run = Some((bit, 1));
+                    }
+                }
+            }
+            if let Some((last, count)) = run {
+                push_run(&mut out, last, count);
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, bits: u8, count: usize) {
+    let sixel = char::from(63 + bits);
+    if count >= 4 {
+        out.push_str(&format!("!{count}{sixel}"));
+    } else {
+        out.extend(std::iter::repeat_n(sixel, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+    use image::Rgba;
+
+    const CELL: CellSize = CellSize {
+        width: 10,
+        height: 20,
+    };
+
+    fn blank(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::new_rgba8(width, height)
+    }
+
+    fn pixels(width: u32, rows: &[&[[u8; 4]]]) -> RgbaImage {
+        RgbaImage::from_fn(width, rows.len() as u32, |x, y| {
+            Rgba(rows[y as usize][x as usize])
+        })
+    }
+
+    /// What a sixel image draws, after the header and palette
+    fn sixel_body(pixels: &RgbaImage) -> String {
+        let out = sixel(pixels);
+        let palette_end = out.find("#215;2;100;100;100").unwrap() + "#215;2;100;100;100".len();
+        out[palette_end..].to_string()
+    }
+
+    #[test]
+    fn images_keep_their_aspect_in_cells() {
+        assert_eq!(size(&blank(100, 50), 80, CELL), (10, 3));
+        let square = CellSize {
+            width: 10,
+            height: 10,
+        };
+        assert_eq!(size(&blank(200, 100), 80, square), (20, 10));
+        // Held to the width of the page
+        assert_eq!(size(&blank(1000, 100), 40, CELL), (40, 2));
+    }
+
+    #[test]
+    fn tall_images_are_held_to_max_rows() {
+        // Narrowed to keep the aspect
+        assert_eq!(size(&blank(100, 2000), 80, CELL), (2, 20));
+        // Unless they're a column wide already
+        assert_eq!(size(&blank(8, 10000), 80, CELL), (1, MAX_ROWS));
+    }
+
+    #[test]
+    fn transparent_half_blocks_show_the_background() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let clear = [0, 0, 0, 0];
+        let image = DynamicImage::ImageRgba8(pixels(
+            4,
+            &[&[red, clear, clear, red], &[blue, blue, clear, clear]],
+        ));
+        let cells: Vec<_> = half_blocks(&image, 4, 1)[0]
+            .iter()
+            .map(|span| (span.content.as_str(), span.fg, span.bg))
+            .collect();
+        assert_eq!(
+            cells,
+            [
+                ("▀", Color::Rgb(255, 0, 0), Color::Rgb(0, 0, 255)),
+                ("▄", Color::Rgb(0, 0, 255), Color::Reset),
+                (" ", Color::Reset, Color::Reset),
+                ("▀", Color::Rgb(255, 0, 0), Color::Reset),
+            ]
+        );
+    }
+
+    #[test]
+    fn markers_name_their_picture() {
+        assert_eq!(marker_index(&format!("{MARKER}3")), Some(3));
+        assert_eq!(marker_index(&format!("  {MARKER}12 ")), Some(12));
+        assert_eq!(marker_index("3"), None);
+        assert_eq!(marker_index(&format!("{MARKER}x")), None);
+        assert_eq!(marker_index(&format!("text {MARKER}1")), None);
+    }
+
+    #[test]
+    fn fetched_images_are_swapped_for_markers() {
+        let dom = html5ever::parse_document(RcDom::default(), Default::default()).one(
+            "<p>Before<img src=\"a.png\" alt=\"A\"><img src=\"b.png\" title=\"B\">\
+             <img src=\"missing.png\" alt=\"Missing\"><img alt=\"No source\"></p>",
+        );
+        let images = [
+            (String::from("http://x/b.png"), blank(8, 8)),
+            (String::from("http://x/a.png"), blank(8, 8)),
+        ];
+        let pictures = extract(&dom, &images, |src| format!("http://x/{src}"));
+
+        let found: Vec<_> = pictures.iter().map(|p| (p.image, p.alt.as_str())).collect();
+        assert_eq!(found, [(1, "A"), (0, "B")]);
+        // Images we don't have stay where they were
+        assert_eq!(doc::images(&dom.document).len(), 2);
+        let text = doc::text_content(&dom.document);
+        assert!(
+            text.contains(&format!("Before {MARKER}0 {MARKER}1")),
+            "{text:?}"
+        );
+    }
+
+    #[test]
+    fn sixel_runs_are_compressed() {
+        let black = [0, 0, 0, 255];
+        assert_eq!(sixel_body(&pixels(3, &[&[black; 3]])), "#0@@@-\x1b\\");
+        assert_eq!(sixel_body(&pixels(5, &[&[black; 5]])), "#0!5@-\x1b\\");
+    }
+
+    #[test]
+    fn sixel_colours_are_laid_over_each_other() {
+        let black = [0, 0, 0, 255];
+        let white = [255, 255, 255, 255];
+        let clear = [255, 255, 255, 0];
+        assert_eq!(
+            sixel_body(&pixels(3, &[&[black, white, clear]])),
+            "#0@??$#215?@?-\x1b\\"
+        );
+        // Seven rows take two bands
+        let column: [&[[u8; 4]]; 7] = [&[black]; 7];
+        assert_eq!(sixel_body(&pixels(1, &column)), "#0~-#0@-\x1b\\");
+    }
+}
@@ -0,0 +1,186 @@
+use crate::render::Line;
+
+/// Keys used for labels, the easiest to reach first
+const ALPHABET: [char; 9] = ['a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HintAction {
+    Follow,
+    Focus,
+    Copy,
+    Window,
+}
+
+impl HintAction {
+    pub fn describe(self) -> &'static str {
+        match self {
+            HintAction::Follow => "follow",
+            HintAction::Focus => "focus",
+            HintAction::Copy => "copy URL of",
+            HintAction::Window => "open a new window for",
+        }
+    }
+}
+
+/// A label drawn over the start of a link
+#[derive(Clone, Debug)]
+pub struct Hint {
+    pub label: String,
+    pub link: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub enum HintInput {
+    Pending,
+    Chosen(usize),
+    NoMatch,
+}
+
+pub struct Hints {
+    pub action: HintAction,
+    hints: Vec<Hint>,
+    typed: String,
+}
+
+impl Hints {
+    /// Label each link that starts within `count` lines from `first`.
+    /// Returns `None` if there aren't any.
+    pub fn new(action: HintAction, lines: &[Line], first: usize, count: usize) -> Option<Self> {
+        let mut hints: Vec<Hint> = Vec::new();
+        for (n, line) in lines.iter().enumerate().skip(first).take(count) {
+            for span in &line.links {
+                // A wrapped link only needs labelling once
+                if !hints.iter().any(|h| h.link == span.index) {
+                    hints.push(Hint {
+                        label: String::new(),
+                        link: span.index,
+                        line: n,
+                        column: span.start,
+                    });
+                }
+            }
+        }
+        if hints.is_empty() {
+            return None;
+        }
+
+        for (hint, label) in hints.iter_mut().zip(labels(hints.len())) {
+            hint.label = label;
+        }
+        Some(Self {
+            action,
+            hints,
+            typed: String::new(),
+        })
+    }
+
+    /// The hints still matching what's been typed
+    pub fn visible(&self) -> impl Iterator<Item = &Hint> {
+        self.hints
+            .iter()
+            .filter(|h| h.label.starts_with(&self.typed))
+    }
+
+    pub fn push(&mut self, key: char) -> HintInput {
+        self.typed.push(key.to_ascii_lowercase());
+        let mut matching = self.visible();
+        match (matching.next(), matching.next()) {
+            (None, _) => HintInput::NoMatch,
+            (Some(hint), None) if hint.label == self.typed => HintInput::Chosen(hint.link),
+            _ => HintInput::Pending,
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.typed.pop();
+    }
+}
+
+/// `count` distinct labels, all the same length so none is a prefix of another
+fn labels(count: usize) -> Vec<String> {
+    let mut len = 1;
+    while ALPHABET.len().pow(len) < count {
+        len += 1;
+    }
+
+    (0..count)
+        .map(|mut n| {
+            let mut label = vec![ALPHABET[0]; len as usize];
+            for slot in label.iter_mut().rev() {
+                *slot = ALPHABET[n % ALPHABET.len()];
+                n /= ALPHABET.len();
+            }
+            label.into_iter().collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::LinkSpan;
+
+    fn line(links: &[usize]) -> Line {
+        Line {
+            links: links
+                .iter()
+                .enumerate()
+                .map(|(i, &index)| LinkSpan {
+                    index,
+                    start: i * 10,
+                    end: i * 10 + 5,
+                })
+                .collect(),
+            ..Line::default()
+        }
+    }
+
+    #[test]
+    fn labels_use_one_key_while_they_can() {
+        assert_eq!(labels(3), ["a", "s", "d"]);
+        assert_eq!(labels(ALPHABET.len()).len(), ALPHABET.len());
+        assert!(labels(ALPHABET.len()).iter().all(|l| l.len() == 1));
+    }
+
+    #[test]
+    fn labels_are_distinct_and_none_is_a_prefix_of_another() {
+        for count in [10, 81, 82, 800] {
+            let labels = labels(count);
+            assert_eq!(labels.len(), count);
+            let len = labels[0].len();
+            assert!(labels.iter().all(|l| l.len() == len));
+            let mut sorted = labels.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), count);
+        }
+        assert_eq!(labels(10)[..2], ["aa", "as"]);
+        assert_eq!(labels(82)[0].len(), 3);
+    }
+
+    #[test]
+    fn hints_label_only_visible_links_once() {
+        let lines = [line(&[0]), line(&[1, 2]), line(&[2]), line(&[3])];
+        let hints = Hints::new(HintAction::Follow, &lines, 1, 2).unwrap();
+        let labelled: Vec<(usize, usize, usize)> = hints
+            .visible()
+            .map(|h| (h.link, h.line, h.column))
+            .collect();
+        assert_eq!(labelled, [(1, 1, 0), (2, 1, 10)]);
+
+        assert!(Hints::new(HintAction::Follow, &lines, 4, 2).is_none());
+    }
+
+    #[test]
+    fn typing_narrows_down_the_hints() {
+        let lines: Vec<Line> = (0..20).map(|i| line(&[i])).collect();
+        let mut hints = Hints::new(HintAction::Copy, &lines, 0, 20).unwrap();
+
+        assert!(matches!(hints.push('S'), HintInput::Pending));
+        assert_eq!(hints.visible().count(), ALPHABET.len());
+        assert!(matches!(hints.push('d'), HintInput::Chosen(11)));
+        hints.pop();
+        assert!(matches!(hints.push('x'), HintInput::NoMatch));
+    }
+}
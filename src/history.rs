@@ -0,0 +1,25 @@
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Clone, Debug)]
+pub struct Visit {
+    pub url: String,
+    pub at: SystemTime,
+}
+
+/// Every page loaded this session, oldest first
+#[derive(Clone, Default)]
+pub struct History(Arc<Mutex<Vec<Visit>>>);
+
+impl History {
+    pub fn record(&self, url: &str) {
+        self.0.lock().unwrap().push(Visit {
+            url: url.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    pub fn visits(&self) -> Vec<Visit> {
+        self.0.lock().unwrap().clone()
+    }
+}
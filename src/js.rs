@@ -180,6 +180,42 @@ fn initialize_context(
                 return new Node(handle);
             }
         }
+    }
+
+    location = { href: '', hash: '' }
+
+    window = {
+        listeners: {},
+        onhashchange: null,
+
+        addEventListener: function(type, listener) {
+            (this.listeners[type] = this.listeners[type] || []).push(listener);
+        },
+
+        removeEventListener: function(type, listener) {
+            this.listeners[type] = (this.listeners[type] || []).filter(l => l !== listener);
+        },
+
+        dispatchEvent: function(event) {
+            const handler = this['on' + event.type];
+            if (typeof handler == 'function') {
+                handler(event);
+            }
+            (this.listeners[event.type] || []).forEach(l => l(event));
+        }
+    }
+
+    function setLocation(href) {
+        location.href = href;
+        const hash = href.indexOf('#');
+        location.hash = hash < 0 || hash == href.length - 1 ? '' : href.substring(hash);
+    }
+
+    // The engine outlives every page, so forget the last one's handlers
+    function resetPage(href) {
+        window.listeners = {};
+        window.onhashchange = null;
+        setLocation(href);
     }";
 
     ctx.eval(Source::from_bytes(runtime_js))
@@ -214,6 +250,24 @@ pub fn run_worker(rx: Receiver<WorkerMsg>, tx: Sender<JsMessage>) -> Result<(),
     }
 }
 
+/// Code that prepares the engine for a newly loaded page at `url`
+pub fn page_loaded(url: &str) -> String {
+    format!("resetPage({});", js_string(url))
+}
+
+/// Code that moves the page to `new_url` and fires `hashchange`
+pub fn hashchange(old_url: &str, new_url: &str) -> String {
+    format!(
+        "setLocation({new}); window.dispatchEvent({{ type: 'hashchange', oldURL: {old}, newURL: {new} }});",
+        old = js_string(old_url),
+        new = js_string(new_url)
+    )
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
 pub fn exec(
     dom: &mut RcDom,
     js_state: &mut EngineContext,
@@ -2,15 +2,39 @@
 
 use crossbeam::channel;
 
+use crate::config::Config;
 use crate::error::RetumiError;
 use crate::js::{JsMessage, WorkerMsg};
 
+mod about;
+mod auth;
 mod browser;
+mod cache;
+mod charset;
+mod clipboard;
+mod config;
+mod content;
+mod css;
+mod data_url;
 mod doc;
+mod download;
 mod error;
 mod event;
+mod find;
+mod gemini;
+mod gemtext;
+mod gopher;
+mod graphics;
+mod hints;
+mod history;
 mod js;
+mod net;
+mod render;
+mod table;
+mod theme;
+mod tls;
 mod ui;
+mod window;
 
 // Based on https://ratatui.rs/recipes/apps/log-with-tracing/
 pub(crate) mod tracelog {
@@ -44,6 +68,7 @@ pub(crate) mod tracelog {
 }
 
 pub async fn run_main() -> Result<(), RetumiError> {
+    let config = Config::load()?;
     let (msg_tx, msg_rx) = channel::unbounded::<JsMessage>();
     let (worker_tx, worker_rx) = channel::unbounded::<WorkerMsg>();
 
@@ -62,7 +87,9 @@ pub async fn run_main() -> Result<(), RetumiError> {
             })?
     };
 
-    let mut model = ui::Model::new(msg_rx, worker_tx.clone());
+    // A URL to start at, as when opening a link in a new window
+    let start = std::env::args().nth(1);
+    let mut model = ui::Model::new(msg_rx, worker_tx.clone(), config, start)?;
     model.run()?;
 
     worker_tx.send(WorkerMsg::Shutdown)?;
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_LANGUAGE, AUTHORIZATION, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LOCATION, USER_AGENT,
+};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::HttpConfig;
+use crate::error::RetumiError;
+use crate::tls::TlsSettings;
+
+/// The most we'll read of a response that isn't streamed to disk
+pub const MAX_BODY: usize = 32 * 1024 * 1024;
+
+/// A `reqwest::Client` shared by every load, plus the per-domain
+/// header overrides that the client itself has no notion of.
+#[derive(Clone)]
+pub struct Session {
+    client: Client,
+    /// Only used for hosts with a certificate exception
+    insecure: Client,
+    tls: TlsSettings,
+    defaults: HeaderMap,
+    overrides: HashMap<String, HeaderMap>,
+    max_redirects: usize,
+}
+
+/// A response that sent us somewhere else
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hop {
+    pub url: String,
+    #[serde(with = "status_code")]
+    pub status: StatusCode,
+}
+
+// StatusCode has no serde support of its own
+mod status_code {
+    use reqwest::StatusCode;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        StatusCode::from_u16(u16::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+impl Session {
+    pub fn new(config: &HttpConfig) -> Result<Self, RetumiError> {
+        let mut defaults = HeaderMap::new();
+        defaults.insert(USER_AGENT, header_value(&config.user_agent)?);
+        defaults.insert(ACCEPT_LANGUAGE, header_value(&config.accept_language)?);
+        let tls = TlsSettings::new(config)?;
+
+        let build = |insecure: bool| -> Result<Client, RetumiError> {
+            let mut builder = Client::builder()
+                .connect_timeout(Duration::from_secs(config.connect_timeout))
+                .read_timeout(Duration::from_secs(config.read_timeout))
+                // We follow redirects ourselves so that every hop can be recorded
+                .redirect(Policy::none())
+                .use_preconfigured_tls(tls.client_config(insecure)?);
+
+            if let Some(proxy) = &config.proxy {
+                builder = builder.proxy(Proxy::all(proxy)?);
+            }
+            Ok(builder.build()?)
+        };
+
+        let mut overrides = HashMap::new();
+        for (domain, headers) in &config.headers {
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                    RetumiError::ConfigError(format!("invalid header name {name:?}"))
+                })?;
+                map.insert(name, header_value(value)?);
+            }
+            overrides.insert(domain.trim_start_matches('.').to_ascii_lowercase(), map);
+        }
+
+        Ok(Self {
+            client: build(false)?,
+            insecure: build(true)?,
+            tls,
+            defaults,
+            overrides,
+            max_redirects: config.max_redirects,
+        })
+    }
+
+    pub fn tls(&self) -> &TlsSettings {
+        &self.tls
+    }
+
+    /// The headers every request for `url` is sent with
+    pub fn headers(&self, url: &str) -> HeaderMap {
+        let mut headers = self.defaults.clone();
+        if let Some(host) = host(url) {
+            for (domain, overrides) in &self.overrides {
+                if host == *domain || host.ends_with(&format!(".{domain}")) {
+                    headers.extend(overrides.clone());
+                }
+            }
+        }
+        headers
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        let client = match host(url) {
+            Some(host) if self.tls.is_exception(&host) => &self.insecure,
+            _ => &self.client,
+        };
+
+        client.get(url).headers(self.headers(url))
+    }
+
+    /// GET `url` with the `extra` headers, following redirects and
+    /// returning the final response along with the hops that led to it
+    pub async fn send(
+        &self,
+        url: &str,
+        extra: &HeaderMap,
+    ) -> Result<(Response, Vec<Hop>), RetumiError> {
+        let mut url = url.to_string();
+        let mut extra = extra.clone();
+        let mut hops = Vec::new();
+        loop {
+            let response = self.get(&url).headers(extra.clone()).send().await?;
+            let status = response.status();
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| response.url().join(l).ok());
+
+            match location {
+                Some(next) if status.is_redirection() && status != StatusCode::NOT_MODIFIED => {
+                    if hops.len() >= self.max_redirects {
+                        return Err(RetumiError::RedirectError(format!(
+                            "gave up after {} redirects at {url}",
+                            hops.len()
+                        )));
+                    }
+                    tracing::info!("{status} from {url} to {next}");
+                    // Credentials are only meant for the origin that asked for them
+                    if next.origin() != response.url().origin() {
+                        extra.remove(AUTHORIZATION);
+                    }
+                    // Validators describe our copy of the first URL, not of where it leads
+                    extra.remove(IF_NONE_MATCH);
+                    extra.remove(IF_MODIFIED_SINCE);
+                    hops.push(Hop { url, status });
+                    url = next.to_string();
+                }
+                _ => return Ok((response, hops)),
+            }
+        }
+    }
+}
+
+/// Read `reader` to the end, keeping at most `limit` bytes in `buf`.
+/// Returns whether there was more than that.
+pub async fn read_capped(
+    reader: impl AsyncRead + Unpin,
+    limit: usize,
+    buf: &mut Vec<u8>,
+) -> Result<bool, std::io::Error> {
+    reader.take(limit as u64 + 1).read_to_end(buf).await?;
+    let truncated = buf.len() > limit;
+    buf.truncate(limit);
+    Ok(truncated)
+}
+
+fn host(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(String::from))
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, RetumiError> {
+    HeaderValue::from_str(value)
+        .map_err(|_| RetumiError::ConfigError(format!("invalid header value {value:?}")))
+}
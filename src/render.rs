@@ -0,0 +1,278 @@
+use tuirealm::props::{Color, Style, TextSpan};
+use tuirealm::ratatui::text;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The columns of a line taken up by one link
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkSpan {
+    /// Position of the link in the document, counting from 0
+    pub index: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Which part of a picture a line shows, for terminals that can draw it
+/// over the half blocks standing in for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageRow {
+    /// Which of the page's images it is, in the order they arrived
+    pub index: usize,
+    pub row: usize,
+    /// How many rows the whole picture takes
+    pub rows: usize,
+    pub column: usize,
+    pub columns: usize,
+}
+
+/// One row of a laid out page
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub spans: Vec<TextSpan>,
+    pub links: Vec<LinkSpan>,
+    pub image: Option<ImageRow>,
+}
+
+impl Line {
+    pub fn width(&self) -> usize {
+        self.spans.iter().map(|s| s.content.width()).sum()
+    }
+
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.content.as_str()).collect()
+    }
+
+    /// Append `span`, marking it as part of link `link` if given
+    pub fn push(&mut self, span: TextSpan, link: Option<usize>) {
+        let start = self.width();
+        let end = start + span.content.width();
+        if let Some(index) = link {
+            match self.links.last_mut() {
+                Some(last) if last.index == index && last.end == start => last.end = end,
+                _ => self.links.push(LinkSpan { index, start, end }),
+            }
+        }
+        self.spans.push(span);
+    }
+
+    /// Add `other` to the end of this line, keeping its links
+    pub fn append(&mut self, other: Line) {
+        let offset = self.width();
+        if let Some(mut image) = other.image {
+            image.column += offset;
+            self.image = Some(image);
+        }
+        for mut link in other.links {
+            link.start += offset;
+            link.end += offset;
+            match self.links.last_mut() {
+                Some(last) if last.index == link.index && last.end == link.start => {
+                    last.end = link.end
+                }
+                _ => self.links.push(link),
+            }
+        }
+        self.spans.extend(other.spans);
+    }
+
+    /// Move everything `columns` to the right
+    pub fn indent(&mut self, columns: usize) {
+        if columns == 0 {
+            return;
+        }
+        self.spans.insert(0, TextSpan::new(" ".repeat(columns)));
+        if let Some(image) = &mut self.image {
+            image.column += columns;
+        }
+        for link in &mut self.links {
+            link.start += columns;
+            link.end += columns;
+        }
+    }
+
+    /// Apply `restyle` to the text between columns `start` and `end`,
+    /// splitting spans where needed
+    pub fn highlight(&mut self, start: usize, end: usize, restyle: impl Fn(&mut TextSpan)) {
+        let mut spans = Vec::with_capacity(self.spans.len() + 2);
+        let mut column = 0;
+        for span in self.spans.drain(..) {
+            let len = span.content.width();
+            let from = start.clamp(column, column + len) - column;
+            let to = end.clamp(column, column + len) - column;
+            column += len;
+            if from == to {
+                spans.push(span);
+                continue;
+            }
+
+            let (from, to) = (byte_at(&span.content, from), byte_at(&span.content, to));
+            let piece = |a: usize, b: usize| TextSpan {
+                content: span.content[a..b].to_string(),
+                ..span.clone()
+            };
+            if from > 0 {
+                spans.push(piece(0, from));
+            }
+            let mut hit = piece(from, to);
+            restyle(&mut hit);
+            spans.push(hit);
+            if to < span.content.len() {
+                spans.push(piece(to, span.content.len()));
+            }
+        }
+        self.spans = spans;
+    }
+}
+
+impl From<TextSpan> for Line {
+    fn from(span: TextSpan) -> Self {
+        Self {
+            spans: vec![span],
+            links: Vec::new(),
+            image: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Line> for text::Line<'a> {
+    fn from(line: &'a Line) -> Self {
+        line.spans
+            .iter()
+            .map(|span| {
+                let style = Style::default()
+                    .fg(span.fg)
+                    .bg(span.bg)
+                    .add_modifier(span.modifiers);
+                text::Span::styled(span.content.as_str(), style)
+            })
+            .collect()
+    }
+}
+
+/// A span of `text` in `style`, with the terminal's colours wherever it has none
+pub fn span(text: impl Into<String>, style: Style) -> TextSpan {
+    TextSpan {
+        content: text.into(),
+        fg: style.fg.unwrap_or(Color::Reset),
+        bg: style.bg.unwrap_or(Color::Reset),
+        modifiers: style.add_modifier,
+    }
+}
+
+/// Lay `style` over `span`, keeping whatever it leaves unset
+pub fn overlay(span: &mut TextSpan, style: Style) {
+    span.fg = style.fg.unwrap_or(span.fg);
+    span.bg = style.bg.unwrap_or(span.bg);
+    span.modifiers |= style.add_modifier;
+}
+
+/// Lay out text that has one span per line, breaking lines longer than `width`
+pub fn wrap(spans: Vec<TextSpan>, width: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for span in spans {
+        let mut rest = span.content.as_str();
+        loop {
+            // Take at least one character, however wide, so that we get somewhere
+            let split = match rest.char_indices().nth(1) {
+                Some((first, _)) => byte_at(rest, width).max(first),
+                None => rest.len(),
+            };
+            let (row, tail) = rest.split_at(split);
+            lines.push(Line::from(TextSpan {
+                content: row.to_string(),
+                ..span.clone()
+            }));
+            if tail.is_empty() {
+                break;
+            }
+            rest = tail;
+        }
+    }
+    lines
+}
+
+/// Where in `text` the characters from `column` on start, counting columns
+/// as the terminal does. A wide character straddling `column` goes after it.
+fn byte_at(text: &str, column: usize) -> usize {
+    let mut width = 0;
+    for (i, c) in text.char_indices() {
+        width += c.width().unwrap_or(0);
+        if width > column {
+            return i;
+        }
+    }
+    text.len()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Where each link starts: its index, line, and first and last columns
+fn link_positions(lines: &[Line]) -> Vec<(usize, usize, usize, usize)> {
+    let mut positions: Vec<(usize, usize, usize, usize)> = Vec::new();
+    for (n, line) in lines.iter().enumerate() {
+        for link in &line.links {
+            if !positions.iter().any(|p| p.0 == link.index) {
+                positions.push((link.index, n, link.start, link.end));
+            }
+        }
+    }
+    positions
+}
+
+/// The link drawn closest to link `from` in `direction`.
+/// Nearer lines win over nearer columns, as text is read line by line.
+pub fn nearest_link(lines: &[Line], from: usize, direction: Direction) -> Option<usize> {
+    let positions = link_positions(lines);
+    let &(_, line, start, end) = positions.iter().find(|p| p.0 == from)?;
+
+    positions
+        .iter()
+        .filter(|p| p.0 != from)
+        .filter_map(|&(index, l, s, e)| {
+            let dy = l as isize - line as isize;
+            // Columns between the two links, or 0 if they overlap
+            let gap = if e <= start {
+                start - e
+            } else if s >= end {
+                s - end
+            } else {
+                0
+            };
+            let distance = match direction {
+                Direction::Down if dy > 0 => (dy.unsigned_abs(), gap),
+                Direction::Up if dy < 0 => (dy.unsigned_abs(), gap),
+                Direction::Right if s >= end => (dy.unsigned_abs(), gap),
+                Direction::Left if e <= start => (dy.unsigned_abs(), gap),
+                _ => return None,
+            };
+            Some((distance, index))
+        })
+        .min()
+        .map(|(_, index)| index)
+}
+
+/// The text of every link, in document order
+pub fn link_texts(lines: &[Line], count: usize) -> Vec<String> {
+    let mut texts = vec![String::new(); count];
+    for line in lines {
+        if line.links.is_empty() {
+            continue;
+        }
+        let content = line.text();
+        for link in &line.links {
+            if let Some(text) = texts.get_mut(link.index) {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(&content[byte_at(&content, link.start)..byte_at(&content, link.end)]);
+                *text = text.trim().to_string();
+            }
+        }
+    }
+    texts
+}
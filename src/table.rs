@@ -0,0 +1,571 @@
+use html2text::markup5ever_rcdom::{Handle, NodeData, RcDom};
+use html5ever::local_name;
+use tuirealm::props::{Style, TextSpan};
+use unicode_width::UnicodeWidthStr;
+
+use crate::doc;
+use crate::render::{self, Line};
+
+/// Stands in for a table in the document, followed by its index, so the
+/// table can be laid out where the marker is rendered
+const MARKER: char = '\u{E001}';
+/// Words longer than this may be broken to keep a table's columns side by side
+const MAX_WORD: usize = 16;
+const MAX_SPAN: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct Cell {
+    /// `None` for the gaps left by short rows
+    pub node: Option<Handle>,
+    /// A `<th>`, or any cell in the `<thead>`
+    pub header: bool,
+    pub row: usize,
+    pub column: usize,
+    pub rows: usize,
+    pub columns: usize,
+}
+
+impl Cell {
+    /// The narrowest and widest the cell's text can comfortably be
+    fn extent(&self) -> (usize, usize) {
+        let text = self
+            .node
+            .as_ref()
+            .map(doc::text_content)
+            .unwrap_or_default();
+        let longest = text.split(' ').map(UnicodeWidthStr::width).max();
+        (
+            longest.unwrap_or_default().clamp(1, MAX_WORD),
+            text.width().max(1),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Table {
+    /// The `<table>` element, which is no longer in the document
+    pub node: Handle,
+    pub caption: Option<Handle>,
+    /// In document order, then the gaps
+    pub cells: Vec<Cell>,
+    rows: usize,
+    columns: usize,
+    /// The index of the cell at each row and column
+    grid: Vec<Vec<usize>>,
+}
+
+impl Table {
+    fn parse(node: Handle) -> Self {
+        let mut caption = None;
+        let mut rows = Vec::new();
+        find_rows(&node, false, &mut caption, &mut rows);
+
+        let mut cells: Vec<Cell> = Vec::new();
+        let mut grid: Vec<Vec<Option<usize>>> = vec![Vec::new(); rows.len()];
+        for (r, (tr, head)) in rows.iter().enumerate() {
+            let mut column = 0;
+            for node in row_cells(tr) {
+                while grid[r].get(column).is_some_and(Option::is_some) {
+                    column += 1;
+                }
+                // Stop short of any cell reaching down from the rows above
+                let free = grid[r].iter().skip(column).position(Option::is_some);
+                let columns = span(&node, "colspan")
+                    .clamp(1, MAX_SPAN)
+                    .min(free.unwrap_or(MAX_SPAN));
+                // A rowspan of 0 reaches the end of the table
+                let height = match span(&node, "rowspan") {
+                    0 => rows.len() - r,
+                    n => n.min(rows.len() - r),
+                };
+
+                for row in &mut grid[r..r + height] {
+                    if row.len() < column + columns {
+                        row.resize(column + columns, None);
+                    }
+                    row[column..column + columns].fill(Some(cells.len()));
+                }
+                cells.push(Cell {
+                    header: *head || is_element(&node, "th"),
+                    node: Some(node),
+                    row: r,
+                    column,
+                    rows: height,
+                    columns,
+                });
+                column += columns;
+            }
+        }
+
+        let columns = grid.iter().map(Vec::len).max().unwrap_or(0);
+        let mut filled = Vec::with_capacity(grid.len());
+        for (r, row) in grid.into_iter().enumerate() {
+            let mut indices = Vec::with_capacity(columns);
+            for c in 0..columns {
+                match row.get(c).copied().flatten() {
+                    Some(i) => indices.push(i),
+                    None => {
+                        indices.push(cells.len());
+                        cells.push(Cell {
+                            node: None,
+                            header: false,
+                            row: r,
+                            column: c,
+                            rows: 1,
+                            columns: 1,
+                        });
+                    }
+                }
+            }
+            filled.push(indices);
+        }
+
+        Self {
+            node,
+            caption,
+            cells,
+            rows: filled.len(),
+            columns,
+            grid: filled,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The cells starting in row `r`, left to right, with their indices
+    pub fn row(&self, r: usize) -> impl Iterator<Item = (usize, &Cell)> {
+        self.grid[r]
+            .iter()
+            .enumerate()
+            .filter(move |&(c, &i)| self.cells[i].column == c && self.cells[i].row == r)
+            .map(|(_, &i)| (i, &self.cells[i]))
+    }
+
+    /// How wide the text of each column can be for the table to fit in
+    /// `width`, or `None` if it can't without squashing the words
+    pub fn column_widths(&self, width: usize) -> Option<Vec<usize>> {
+        // A border either side of every column, and a space either side of the text
+        let available = width.checked_sub(3 * self.columns + 1)?;
+        let mut min = vec![1; self.columns];
+        let mut max = vec![1; self.columns];
+        for cell in self.cells.iter().filter(|c| c.columns == 1) {
+            let (narrowest, widest) = cell.extent();
+            min[cell.column] = min[cell.column].max(narrowest);
+            max[cell.column] = max[cell.column].max(widest);
+        }
+        // Spanning cells get room from the columns they span, less the borders between
+        for cell in self.cells.iter().filter(|c| c.columns > 1) {
+            let (narrowest, widest) = cell.extent();
+            let borders = 3 * (cell.columns - 1);
+            let spanned = cell.column..cell.column + cell.columns;
+            grow(&mut min[spanned.clone()], narrowest.saturating_sub(borders));
+            grow(&mut max[spanned], widest.saturating_sub(borders));
+        }
+        for (max, min) in max.iter_mut().zip(&min) {
+            *max = (*max).max(*min);
+        }
+
+        if min.iter().sum::<usize>() > available {
+            return None;
+        }
+        if max.iter().sum::<usize>() <= available {
+            return Some(max);
+        }
+
+        // Share out the room left over in proportion to how much more each column wants
+        let extra = available - min.iter().sum::<usize>();
+        let slack: usize = max.iter().zip(&min).map(|(max, min)| max - min).sum();
+        let mut widths: Vec<usize> = min
+            .iter()
+            .zip(&max)
+            .map(|(min, max)| min + extra * (max - min) / slack)
+            .collect();
+        let mut left = available - widths.iter().sum::<usize>();
+        let mut column = 0;
+        while left > 0 {
+            if widths[column] < max[column] {
+                widths[column] += 1;
+                left -= 1;
+            }
+            column = (column + 1) % widths.len();
+        }
+        Some(widths)
+    }
+
+    /// How wide the text of `cell` is with the columns `widths` wide
+    pub fn cell_width(&self, cell: &Cell, widths: &[usize]) -> usize {
+        let spanned: usize = widths[cell.column..cell.column + cell.columns].iter().sum();
+        spanned + 3 * (cell.columns - 1)
+    }
+
+    /// Draw the table with its columns `widths` wide, given the lines of each cell
+    pub fn draw(&self, widths: &[usize], contents: &[Vec<Line>], border: Style) -> Vec<Line> {
+        if self.rows == 0 || self.columns == 0 {
+            return Vec::new();
+        }
+
+        let mut heights = vec![1; self.rows];
+        for (cell, lines) in self.cells.iter().zip(contents) {
+            if cell.rows == 1 {
+                heights[cell.row] = heights[cell.row].max(lines.len());
+            }
+        }
+        // Cells spanning rows make the last of them taller if they need the room
+        for (cell, lines) in self.cells.iter().zip(contents) {
+            let spanned = &mut heights[cell.row..cell.row + cell.rows];
+            let room: usize = spanned.iter().sum();
+            if let Some(last) = spanned.last_mut() {
+                *last += lines.len().saturating_sub(room);
+            }
+        }
+
+        let mut lines = Vec::new();
+        for r in 0..self.rows {
+            lines.push(self.rule(r, widths, border));
+            for n in 0..heights[r] {
+                let mut line = Line::from(render::span("│", border));
+                let cells = self.grid[r]
+                    .iter()
+                    .enumerate()
+                    .filter(|&(c, &i)| self.cells[i].column == c);
+                for (_, &i) in cells {
+                    let cell = &self.cells[i];
+                    let offset = heights[cell.row..r].iter().sum::<usize>() + n;
+                    let text = contents[i].get(offset).cloned().unwrap_or_default();
+                    let padding = self.cell_width(cell, widths).saturating_sub(text.width());
+                    line.push(TextSpan::new(" "), None);
+                    line.append(text);
+                    line.push(TextSpan::new(" ".repeat(padding + 1)), None);
+                    line.push(render::span("│", border), None);
+                }
+                lines.push(line);
+            }
+        }
+        lines.push(self.rule(self.rows, widths, border));
+        lines
+    }
+
+    /// The border above row `r`
+    fn rule(&self, r: usize, widths: &[usize], border: Style) -> Line {
+        let above = r.checked_sub(1).map(|r| &self.grid[r]);
+        let below = self.grid.get(r);
+        // Whether the border runs along column `c`, rather than through a cell
+        let across = |c: usize| match (above, below) {
+            (Some(above), Some(below)) => above[c] != below[c],
+            _ => true,
+        };
+        // Whether there's a border before column `c` in `row`
+        let between = |row: Option<&Vec<usize>>, c: usize| {
+            row.is_some_and(|row| c == 0 || c == self.columns || row[c - 1] != row[c])
+        };
+
+        let mut text = String::new();
+        for c in 0..=self.columns {
+            let left = c > 0 && across(c - 1);
+            let right = c < self.columns && across(c);
+            text.push(junction(between(above, c), between(below, c), left, right));
+            if c < self.columns {
+                let fill = if right { '─' } else { ' ' };
+                text.extend(std::iter::repeat_n(fill, widths[c] + 2));
+            }
+        }
+        Line::from(render::span(text, border))
+    }
+
+    /// How many rows at the top are all headers, unless they all are
+    pub fn header_rows(&self) -> usize {
+        let headers = self
+            .grid
+            .iter()
+            .take_while(|row| row.iter().all(|&i| self.cells[i].header))
+            .count();
+        if headers == self.rows {
+            0
+        } else {
+            headers
+        }
+    }
+
+    /// The text of the header over each column, to label cells with when
+    /// the table's rows are stacked
+    pub fn labels(&self) -> Vec<Option<String>> {
+        let Some(row) = self.header_rows().checked_sub(1) else {
+            return vec![None; self.columns];
+        };
+        self.grid[row]
+            .iter()
+            .map(|&i| {
+                let text = self.cells[i].node.as_ref().map(doc::text_content)?;
+                Some(text).filter(|t| !t.is_empty())
+            })
+            .collect()
+    }
+}
+
+/// Take every table out of `dom`, innermost ones included, leaving a
+/// marker for each to be rendered in its place
+pub fn extract(dom: &RcDom) -> Vec<Table> {
+    fn walker(node: &Handle, result: &mut Vec<Handle>) {
+        if is_element(node, "table") {
+            result.push(node.clone());
+        }
+        for child in node.children.borrow().iter() {
+            walker(child, result);
+        }
+    }
+
+    let mut tables = Vec::new();
+    walker(&dom.document, &mut tables);
+    tables
+        .into_iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let marker = doc::element("div", Vec::new());
+            doc::prepend_text(&marker, &format!("{MARKER}{i}"));
+            doc::replace(&node, &marker);
+            node.parent.take();
+            Table::parse(node)
+        })
+        .collect()
+}
+
+/// Which table a rendered line stands in for, if any
+pub fn marker_index(text: &str) -> Option<usize> {
+    text.trim().strip_prefix(MARKER)?.parse().ok()
+}
+
+/// Collect the rows of a table, and whether they're in its `<thead>`,
+/// without going into tables inside it
+fn find_rows(
+    node: &Handle,
+    head: bool,
+    caption: &mut Option<Handle>,
+    rows: &mut Vec<(Handle, bool)>,
+) {
+    for child in node.children.borrow().iter() {
+        let NodeData::Element { name, .. } = &child.data else {
+            continue;
+        };
+        match name.local {
+            local_name!("table") => {}
+            local_name!("tr") => rows.push((child.clone(), head)),
+            local_name!("caption") => {
+                caption.get_or_insert_with(|| child.clone());
+            }
+            local_name!("thead") => find_rows(child, true, caption, rows),
+            _ => find_rows(child, head, caption, rows),
+        }
+    }
+}
+
+fn row_cells(row: &Handle) -> Vec<Handle> {
+    fn walker(node: &Handle, result: &mut Vec<Handle>) {
+        for child in node.children.borrow().iter() {
+            if is_element(child, "td") || is_element(child, "th") {
+                result.push(child.clone());
+            } else if !is_element(child, "table") && !is_element(child, "tr") {
+                walker(child, result);
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    walker(row, &mut result);
+    result
+}
+
+fn is_element(node: &Handle, tag: &str) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if &*name.local == tag)
+}
+
+/// A `colspan` or `rowspan`, which is 1 unless it says otherwise
+fn span(node: &Handle, name: &str) -> usize {
+    doc::attribute(node, name)
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(1)
+}
+
+/// Widen `widths` evenly until they add up to at least `needed`
+fn grow(widths: &mut [usize], needed: usize) {
+    let have: usize = widths.iter().sum();
+    let Some(extra) = needed.checked_sub(have) else {
+        return;
+    };
+    let count = widths.len();
+    for (i, width) in widths.iter_mut().enumerate() {
+        *width += extra / count + usize::from(i < extra % count);
+    }
+}
+
+/// The corner or crossing for borders going each of these ways
+fn junction(up: bool, down: bool, left: bool, right: bool) -> char {
+    match (up, down, left, right) {
+        (true, true, true, true) => '┼',
+        (true, true, true, false) => '┤',
+        (true, true, false, true) => '├',
+        (true, false, true, true) => '┴',
+        (false, true, true, true) => '┬',
+        (false, true, false, true) => '┌',
+        (false, true, true, false) => '┐',
+        (true, false, false, true) => '└',
+        (true, false, true, false) => '┘',
+        (true, _, false, false) | (_, true, false, false) => '│',
+        (false, false, true, _) | (false, false, _, true) => '─',
+        (false, false, false, false) => ' ',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+
+    fn table(html: &str) -> Table {
+        let dom = html5ever::parse_document(RcDom::default(), Default::default()).one(html);
+        extract(&dom).remove(0)
+    }
+
+    /// The text of the cell at each row and column
+    fn layout(table: &Table) -> Vec<Vec<String>> {
+        table
+            .grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&i| {
+                        let node = table.cells[i].node.as_ref();
+                        node.map(doc::text_content).unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spanning_cells_fill_the_grid() {
+        let table = table(
+            "<table><tr><td rowspan=2>A<td colspan=2>B\
+             <tr><td>C<td>D\
+             <tr><td colspan=3>E</table>",
+        );
+        assert_eq!(
+            layout(&table),
+            [["A", "B", "B"], ["A", "C", "D"], ["E", "E", "E"]]
+        );
+        let a = &table.cells[0];
+        assert_eq!((a.row, a.column, a.rows, a.columns), (0, 0, 2, 1));
+    }
+
+    #[test]
+    fn colspan_stops_at_a_cell_from_above() {
+        let table = table(
+            "<table><tr><td>A<td rowspan=2>B<td>C\
+             <tr><td colspan=3>D</table>",
+        );
+        assert_eq!(layout(&table), [["A", "B", "C"], ["D", "B", ""]]);
+        assert_eq!(table.cells[3].columns, 1);
+    }
+
+    #[test]
+    fn rowspan_zero_reaches_the_end_and_short_rows_get_gaps() {
+        let table = table(
+            "<table><tr><td rowspan=0>A<td>B<td>C\
+             <tr><td>D\
+             <tr><td>E<td>F</table>",
+        );
+        assert_eq!(
+            layout(&table),
+            [["A", "B", "C"], ["A", "D", ""], ["A", "E", "F"]]
+        );
+        assert_eq!(table.cells[0].rows, 3);
+        assert!(table.cells.iter().any(|c| c.node.is_none()));
+    }
+
+    #[test]
+    fn columns_get_as_much_as_they_want_if_it_fits() {
+        let table = table("<table><tr><td>aaaa bbbb<td>cc dd ee ff</table>");
+        // 7 columns of borders and padding
+        assert_eq!(table.column_widths(40), Some(vec![9, 11]));
+        assert_eq!(table.column_widths(27), Some(vec![9, 11]));
+    }
+
+    #[test]
+    fn slack_is_shared_in_proportion_to_what_columns_want() {
+        let table = table("<table><tr><td>aaaa bbbb<td>cc dd ee ff</table>");
+        // 12 to share out: the narrowest are 4 and 2, so 6 more go 5:9
+        let widths = table.column_widths(19).unwrap();
+        assert_eq!(widths, [7, 5]);
+        assert_eq!(widths.iter().sum::<usize>(), 12);
+
+        let widths = table.column_widths(13).unwrap();
+        assert_eq!(widths, [4, 2]);
+    }
+
+    #[test]
+    fn spanning_cells_widen_the_columns_under_them() {
+        let table = table(
+            "<table><tr><td>a<td>b\
+             <tr><td colspan=2>abcdefghijkl</table>",
+        );
+        // 12 wide, less the 3 of the border between the two columns
+        assert_eq!(table.column_widths(80), Some(vec![5, 4]));
+    }
+
+    #[test]
+    fn too_narrow_for_the_words_gives_up() {
+        let table = table("<table><tr><td>abcdefgh<td>ijklmnop</table>");
+        assert_eq!(table.column_widths(23), Some(vec![8, 8]));
+        assert_eq!(table.column_widths(22), None);
+        assert_eq!(table.column_widths(3), None);
+    }
+
+    #[test]
+    fn long_words_may_be_broken() {
+        let word = "x".repeat(40);
+        let table = table(&format!("<table><tr><td>{word}</table>"));
+        assert_eq!(table.column_widths(4 + MAX_WORD), Some(vec![MAX_WORD]));
+    }
+
+    #[test]
+    fn borders_join_around_spanning_cells() {
+        let table = table(
+            "<table><tr><td rowspan=2>A<td colspan=2>B\
+             <tr><td>C<td>D</table>",
+        );
+        let contents: Vec<Vec<Line>> = ["A", "B", "C", "D"]
+            .into_iter()
+            .map(|text| vec![Line::from(TextSpan::new(text))])
+            .collect();
+        let lines: Vec<String> = table
+            .draw(&[1, 1, 1], &contents, Style::default())
+            .iter()
+            .map(Line::text)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "┌───┬───────┐",
+                "│ A │ B     │",
+                "│   ├───┬───┤",
+                "│   │ C │ D │",
+                "└───┴───┴───┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn header_rows_label_stacked_cells() {
+        let table = table(
+            "<table><thead><tr><th>Name<th></thead>\
+             <tr><td>Ada<td>1815</table>",
+        );
+        assert_eq!(table.header_rows(), 1);
+        assert_eq!(table.labels(), [Some(String::from("Name")), None]);
+
+        let table = table("<table><tr><th>Only<th>Headers</table>");
+        assert_eq!(table.header_rows(), 0);
+        assert_eq!(table.labels(), [None, None]);
+    }
+}
@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use tuirealm::props::{Color, Style};
+use tuirealm::ratatui::style::Modifier;
+
+use crate::config;
+use crate::error::RetumiError;
+
+const BUILT_IN: [&str; 3] = ["dark", "light", "high-contrast"];
+
+/// The colours of everything on screen: the text of pages, and the UI around
+/// them. Page styles are laid over `text`, so anything they leave unset
+/// shows through.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub text: Style,
+    pub link: Style,
+    /// The selected link
+    pub active_link: Style,
+    pub emphasis: Style,
+    pub strong: Style,
+    pub strikeout: Style,
+    pub code: Style,
+    pub preformat: Style,
+    /// The placeholder shown where an image would be
+    pub image: Style,
+    /// `<h1>` to `<h6>`
+    pub headings: [Style; 6],
+    /// Table header cells
+    pub table_header: Style,
+    pub table_border: Style,
+    pub url_bar: Style,
+    pub error: Style,
+    pub status: Style,
+    /// Prompts and login forms
+    pub prompt: Style,
+    /// The highlighted row of the link list and downloads
+    pub selection: Style,
+    pub download_done: Style,
+    pub download_failed: Style,
+    pub download_cancelled: Style,
+    /// Link hint labels
+    pub hint: Style,
+    pub find_match: Style,
+    pub find_current: Style,
+    /// Objects and arrays in JSON documents, and the keys they're under
+    pub json_key: Style,
+    pub json_string: Style,
+    pub json_number: Style,
+    /// `true`, `false` and `null`
+    pub json_literal: Style,
+    pub xml_tag: Style,
+    /// The redirect chain shown above a page
+    pub redirect: Style,
+}
+
+/// Shared between the model, which switches it, and the components drawing with it
+pub type SharedTheme = Arc<Mutex<Theme>>;
+
+impl Theme {
+    /// The style of a heading at `level`, counting from 1
+    pub fn heading(&self, level: usize) -> Style {
+        self.headings[level.clamp(1, 6) - 1]
+    }
+
+    /// The built-in theme called `name`, or the one in the themes directory
+    pub fn load(name: &str) -> Result<Self, RetumiError> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme);
+        }
+
+        let path = dir().join(format!("{name}.toml"));
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| RetumiError::ConfigError(format!("{}: {err}", path.display())))?;
+        Self::parse(&contents)
+            .map_err(|err| RetumiError::ConfigError(format!("{}: {err}", path.display())))
+    }
+
+    /// Every theme there is to choose from: the built-in ones, then any
+    /// in the themes directory
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|n| n.to_string()).collect();
+        let mut files: Vec<String> = std::fs::read_dir(dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "toml"))
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .filter(|name| !names.contains(name))
+            .collect();
+        files.sort();
+        names.extend(files);
+        names
+    }
+
+    fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::default()),
+            "light" => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// A theme file names a style for any of the parts of a theme, e.g.
+    /// `link = "underlined blue"`, and can start from another with `base`
+    fn parse(contents: &str) -> Result<Self, String> {
+        let table: toml::Table = contents.parse().map_err(|err| format!("{err}"))?;
+        let mut theme = match table.get("base") {
+            Some(toml::Value::String(base)) => Self::built_in(base)
+                .ok_or_else(|| format!("there is no built-in theme called {base}"))?,
+            Some(_) => return Err(String::from("base should be the name of a theme")),
+            None => Self::default(),
+        };
+
+        for (key, value) in table.iter().filter(|(key, _)| *key != "base") {
+            let toml::Value::String(value) = value else {
+                return Err(format!("{key} should be a string"));
+            };
+            let style = parse_style(value).map_err(|err| format!("{key}: {err}"))?;
+            *theme
+                .part(key)
+                .ok_or_else(|| format!("themes have no part called {key}"))? = style;
+        }
+        Ok(theme)
+    }
+
+    fn part(&mut self, key: &str) -> Option<&mut Style> {
+        Some(match key {
+            "text" => &mut self.text,
+            "link" => &mut self.link,
+            "active_link" => &mut self.active_link,
+            "emphasis" => &mut self.emphasis,
+            "strong" => &mut self.strong,
+            "strikeout" => &mut self.strikeout,
+            "code" => &mut self.code,
+            "preformat" => &mut self.preformat,
+            "image" => &mut self.image,
+            "table_header" => &mut self.table_header,
+            "table_border" => &mut self.table_border,
+            "url_bar" => &mut self.url_bar,
+            "error" => &mut self.error,
+            "status" => &mut self.status,
+            "prompt" => &mut self.prompt,
+            "selection" => &mut self.selection,
+            "download_done" => &mut self.download_done,
+            "download_failed" => &mut self.download_failed,
+            "download_cancelled" => &mut self.download_cancelled,
+            "hint" => &mut self.hint,
+            "find_match" => &mut self.find_match,
+            "find_current" => &mut self.find_current,
+            "json_key" => &mut self.json_key,
+            "json_string" => &mut self.json_string,
+            "json_number" => &mut self.json_number,
+            "json_literal" => &mut self.json_literal,
+            "xml_tag" => &mut self.xml_tag,
+            "redirect" => &mut self.redirect,
+            heading => {
+                let level: usize = heading.strip_prefix("heading")?.parse().ok()?;
+                self.headings.get_mut(level.checked_sub(1)?)?
+            }
+        })
+    }
+
+    fn light() -> Self {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        Self {
+            link: Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::UNDERLINED),
+            active_link: Style::default()
+                .fg(Color::Black)
+                .bg(Color::LightBlue)
+                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            strong: bold,
+            code: Style::default().fg(Color::Red).bg(Color::Gray),
+            preformat: Style::default().fg(Color::Magenta),
+            image: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::ITALIC),
+            headings: [
+                bold.fg(Color::Magenta).add_modifier(Modifier::UNDERLINED),
+                bold.fg(Color::Blue),
+                bold.fg(Color::Green),
+                bold.fg(Color::Red),
+                bold,
+                bold,
+            ],
+            table_border: Style::default().fg(Color::Gray),
+            status: Style::default().fg(Color::Magenta),
+            prompt: Style::default().fg(Color::Magenta),
+            selection: Style::default().fg(Color::Black).bg(Color::LightBlue),
+            download_cancelled: Style::default().fg(Color::Magenta),
+            find_current: bold.fg(Color::White).bg(Color::Red),
+            json_number: Style::default().fg(Color::Blue),
+            xml_tag: bold.fg(Color::Blue),
+            redirect: Style::default().fg(Color::Magenta),
+            ..Self::default()
+        }
+    }
+
+    fn high_contrast() -> Self {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let heading = bold.fg(Color::White).add_modifier(Modifier::UNDERLINED);
+        Self {
+            text: Style::default().fg(Color::White).bg(Color::Black),
+            link: bold
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::UNDERLINED),
+            active_link: bold
+                .fg(Color::Black)
+                .bg(Color::LightYellow)
+                .add_modifier(Modifier::UNDERLINED),
+            strong: bold.fg(Color::White),
+            code: Style::default().fg(Color::LightCyan),
+            preformat: Style::default().fg(Color::LightCyan),
+            image: bold.fg(Color::LightMagenta),
+            headings: [heading; 6],
+            table_header: heading,
+            table_border: Style::default().fg(Color::White),
+            url_bar: Style::default().fg(Color::White),
+            error: bold.fg(Color::LightRed),
+            status: Style::default().fg(Color::LightYellow),
+            prompt: Style::default().fg(Color::LightYellow),
+            selection: Style::default().fg(Color::Black).bg(Color::White),
+            download_done: Style::default().fg(Color::LightGreen),
+            download_failed: Style::default().fg(Color::LightRed),
+            download_cancelled: Style::default().fg(Color::LightYellow),
+            hint: bold.fg(Color::Black).bg(Color::LightCyan),
+            find_match: Style::default().fg(Color::Black).bg(Color::LightGreen),
+            find_current: bold.fg(Color::Black).bg(Color::LightYellow),
+            json_key: Style::default().fg(Color::White),
+            json_string: Style::default().fg(Color::LightGreen),
+            json_number: Style::default().fg(Color::LightCyan),
+            json_literal: Style::default().fg(Color::LightMagenta),
+            xml_tag: bold.fg(Color::LightCyan),
+            redirect: Style::default().fg(Color::LightYellow),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        Self {
+            text: Style::default(),
+            link: Style::default().add_modifier(Modifier::UNDERLINED),
+            active_link: Style::default()
+                .bg(Color::Blue)
+                .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            emphasis: Style::default().add_modifier(Modifier::ITALIC),
+            strong: bold.fg(Color::Green),
+            strikeout: Style::default().add_modifier(Modifier::CROSSED_OUT),
+            code: Style::default().fg(Color::LightYellow).bg(Color::DarkGray),
+            preformat: Style::default().fg(Color::LightYellow),
+            image: Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::ITALIC),
+            headings: [
+                bold.fg(Color::LightMagenta)
+                    .add_modifier(Modifier::UNDERLINED),
+                bold.fg(Color::LightCyan),
+                bold.fg(Color::LightGreen),
+                bold.fg(Color::LightYellow),
+                bold,
+                bold,
+            ],
+            table_header: bold,
+            table_border: Style::default().fg(Color::DarkGray),
+            url_bar: Style::default().fg(Color::Blue),
+            error: Style::default().fg(Color::Red),
+            status: Style::default().fg(Color::Yellow),
+            prompt: Style::default().fg(Color::Yellow),
+            selection: Style::default().bg(Color::Blue),
+            download_done: Style::default().fg(Color::Green),
+            download_failed: Style::default().fg(Color::Red),
+            download_cancelled: Style::default().fg(Color::Yellow),
+            hint: bold.fg(Color::Black).bg(Color::Yellow),
+            find_match: Style::default().fg(Color::Black).bg(Color::Yellow),
+            find_current: bold.fg(Color::Black).bg(Color::LightRed),
+            json_key: Style::default(),
+            json_string: Style::default().fg(Color::Green),
+            json_number: Style::default().fg(Color::Cyan),
+            json_literal: Style::default().fg(Color::Magenta),
+            xml_tag: bold.fg(Color::Blue),
+            redirect: Style::default().fg(Color::Yellow),
+        }
+    }
+}
+
+/// The colour of a style, for components that only take one
+pub fn foreground(style: Style) -> Color {
+    style.fg.unwrap_or(Color::Reset)
+}
+
+fn dir() -> PathBuf {
+    config::config_dir().join("themes")
+}
+
+/// Parse a style such as `bold yellow on blue`: modifiers, then the
+/// foreground colour, then optionally `on` and the background colour
+fn parse_style(text: &str) -> Result<Style, String> {
+    let mut style = Style::default();
+    let mut background = false;
+    for word in text.split_whitespace() {
+        let modifier = match word.to_ascii_lowercase().as_str() {
+            "on" => {
+                background = true;
+                continue;
+            }
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "crossed_out" | "strikethrough" => Modifier::CROSSED_OUT,
+            "reversed" => Modifier::REVERSED,
+            _ => {
+                let color = match word {
+                    "default" => Color::Reset,
+                    word => Color::from_str(word).map_err(|_| format!("unknown colour {word}"))?,
+                };
+                style = if background {
+                    style.bg(color)
+                } else {
+                    style.fg(color)
+                };
+                continue;
+            }
+        };
+        style = style.add_modifier(modifier);
+    }
+    Ok(style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles_are_modifiers_then_colours() {
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        assert_eq!(
+            parse_style("bold yellow on blue"),
+            Ok(bold.fg(Color::Yellow).bg(Color::Blue))
+        );
+        assert_eq!(
+            parse_style("Italic  UNDERLINED #ff8000"),
+            Ok(Style::default()
+                .fg(Color::Rgb(255, 128, 0))
+                .add_modifier(Modifier::ITALIC | Modifier::UNDERLINED))
+        );
+        assert_eq!(
+            parse_style("strikethrough on default"),
+            Ok(Style::default()
+                .bg(Color::Reset)
+                .add_modifier(Modifier::CROSSED_OUT))
+        );
+        assert_eq!(parse_style(""), Ok(Style::default()));
+        assert!(parse_style("bold chartreuse-ish").is_err());
+    }
+
+    #[test]
+    fn themes_start_from_their_base() {
+        let theme = Theme::parse("base = \"light\"\nlink = \"red\"\n").unwrap();
+        assert_eq!(
+            theme,
+            Theme {
+                link: Style::default().fg(Color::Red),
+                ..Theme::light()
+            }
+        );
+
+        let theme = Theme::parse("heading2 = \"bold\"\nxml_tag = \"green\"").unwrap();
+        let mut expected = Theme::default();
+        expected.headings[1] = Style::default().add_modifier(Modifier::BOLD);
+        expected.xml_tag = Style::default().fg(Color::Green);
+        assert_eq!(theme, expected);
+    }
+
+    #[test]
+    fn invalid_themes() {
+        for contents in [
+            "base = \"sepia\"",
+            "base = 1",
+            "links = \"red\"",
+            "heading7 = \"red\"",
+            "heading0 = \"red\"",
+            "link = 1",
+            "link = \"bold nocolour\"",
+            "link = ",
+        ] {
+            assert!(Theme::parse(contents).is_err(), "{contents}");
+        }
+    }
+
+    #[test]
+    fn built_in_themes_are_listed() {
+        for name in BUILT_IN {
+            assert!(Theme::built_in(name).is_some(), "{name}");
+        }
+        assert_eq!(Theme::built_in("dark"), Some(Theme::default()));
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::config::{self, HttpConfig};
+use crate::error::RetumiError;
+
+/// Why a server's certificate wasn't trusted
+#[derive(Clone, Debug, PartialEq)]
+pub enum CertProblem {
+    Expired,
+    NotYetValid,
+    SelfSigned,
+    UnknownIssuer,
+    HostnameMismatch,
+    Revoked,
+    Other(String),
+}
+
+impl CertProblem {
+    pub fn explanation(&self) -> String {
+        match self {
+            CertProblem::Expired => String::from(
+                "The certificate has expired. The site may be misconfigured, or your clock may be wrong.",
+            ),
+            CertProblem::NotYetValid => String::from(
+                "The certificate isn't valid yet. Check that your clock is set correctly.",
+            ),
+            CertProblem::SelfSigned => String::from(
+                "The certificate is self-signed, so nobody vouches for it. This is normal for development servers, but not for public sites.",
+            ),
+            CertProblem::UnknownIssuer => String::from(
+                "The certificate was issued by an authority we don't trust. If it's your organisation's own CA, add it with http.ca_file.",
+            ),
+            CertProblem::HostnameMismatch => String::from(
+                "The certificate belongs to a different site. Someone may be impersonating this one.",
+            ),
+            CertProblem::Revoked => String::from("The certificate has been revoked by its issuer."),
+            CertProblem::Other(reason) => format!("The certificate was rejected: {reason}"),
+        }
+    }
+}
+
+/// The parts of a certificate worth showing
+#[derive(Clone, Debug, Default)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub names: Vec<String>,
+    pub fingerprint: String,
+    self_signed: bool,
+}
+
+impl CertInfo {
+    fn parse(der: &[u8]) -> Self {
+        let fingerprint = fingerprint(der);
+        let cert = match X509Certificate::from_der(der) {
+            Ok((_, cert)) => cert,
+            Err(err) => {
+                return Self {
+                    subject: format!("(unreadable certificate: {err})"),
+                    fingerprint,
+                    ..Self::default()
+                }
+            }
+        };
+
+        let names = match cert.subject_alternative_name() {
+            Ok(Some(ext)) => ext
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    GeneralName::IPAddress(ip) => <[u8; 4]>::try_from(*ip)
+                        .map(IpAddr::from)
+                        .or_else(|_| <[u8; 16]>::try_from(*ip).map(IpAddr::from))
+                        .map(|ip| ip.to_string())
+                        .ok(),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            not_before: cert.validity().not_before.to_string(),
+            not_after: cert.validity().not_after.to_string(),
+            names,
+            fingerprint,
+            self_signed: cert.subject().as_raw() == cert.issuer().as_raw(),
+        }
+    }
+}
+
+/// What a server presented during the handshake, and what we made of it
+pub struct Inspection {
+    /// The leaf first, then any intermediates the server sent
+    pub chain: Vec<CertInfo>,
+    pub problem: Option<CertProblem>,
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The certificate fingerprint accepted without verification for each host,
+/// meant for self-signed development servers
+struct Exceptions {
+    path: PathBuf,
+    hosts: BTreeMap<String, String>,
+}
+
+impl Exceptions {
+    fn load(path: PathBuf) -> Self {
+        let hosts = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().split_once(' '))
+            .map(|(host, fingerprint)| (host.to_string(), fingerprint.trim().to_string()))
+            .collect();
+        Self { path, hosts }
+    }
+
+    fn add(&mut self, host: &str, fingerprint: String) -> Result<(), RetumiError> {
+        if self.hosts.get(host) == Some(&fingerprint) {
+            return Ok(());
+        }
+        self.hosts.insert(host.to_string(), fingerprint);
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let contents: String = self
+            .hosts
+            .iter()
+            .map(|(host, fingerprint)| format!("{host} {fingerprint}\n"))
+            .collect();
+        Ok(std::fs::write(&self.path, contents)?)
+    }
+}
+
+/// How hosts are named in handshakes and exceptions
+fn host_key(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase()
+}
+
+/// The certificates a server presented, and why they failed verification if they did
+struct Handshake {
+    chain: Vec<CertificateDer<'static>>,
+    error: Option<rustls::Error>,
+}
+
+type Handshakes = Arc<Mutex<HashMap<String, Handshake>>>;
+
+/// The one trust configuration behind every HTTPS connection
+#[derive(Clone)]
+pub struct TlsSettings {
+    roots: Arc<RootCertStore>,
+    exceptions: Arc<Mutex<Exceptions>>,
+    /// The most recent handshake with each host
+    handshakes: Handshakes,
+}
+
+impl TlsSettings {
+    pub fn new(config: &HttpConfig) -> Result<Self, RetumiError> {
+        let mut roots = RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in &native.errors {
+            tracing::warn!("could not load system root certificates: {err}");
+        }
+        let (added, _) = roots.add_parsable_certificates(native.certs);
+        if added == 0 {
+            tracing::warn!("no usable system root certificates, using the built-in ones");
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        let extra_roots = match &config.ca_file {
+            Some(path) => {
+                let pem = std::fs::read(path).map_err(|err| {
+                    RetumiError::ConfigError(format!("{}: {err}", path.display()))
+                })?;
+                CertificateDer::pem_slice_iter(&pem)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| {
+                        RetumiError::ConfigError(format!("{}: {err:?}", path.display()))
+                    })?
+            }
+            None => Vec::new(),
+        };
+        roots.add_parsable_certificates(extra_roots);
+
+        Ok(Self {
+            roots: Arc::new(roots),
+            exceptions: Arc::new(Mutex::new(Exceptions::load(
+                config::data_dir().join("tls_exceptions"),
+            ))),
+            handshakes: Arc::default(),
+        })
+    }
+
+    /// The rustls config for HTTPS. An `insecure` one also lets through the
+    /// certificates that hosts have exceptions for. Either way, every
+    /// handshake is recorded for `inspection`.
+    pub fn client_config(&self, insecure: bool) -> Result<ClientConfig, RetumiError> {
+        let lenience = if insecure {
+            Lenience::Exceptions
+        } else {
+            Lenience::None
+        };
+        let mut config = self.config(lenience, self.handshakes.clone())?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    /// A rustls config that lets any certificate through, for protocols
+    /// that pin certificates themselves
+    pub fn pinning_config(&self) -> Result<ClientConfig, RetumiError> {
+        self.config(Lenience::All, Handshakes::default())
+    }
+
+    fn config(
+        &self,
+        lenience: Lenience,
+        handshakes: Handshakes,
+    ) -> Result<ClientConfig, RetumiError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = Arc::new(Recorder {
+            inner: WebPkiServerVerifier::builder_with_provider(
+                self.roots.clone(),
+                provider.clone(),
+            )
+            .build()
+            .map_err(|err| RetumiError::TlsError(err.to_string()))?,
+            handshakes,
+            exceptions: self.exceptions.clone(),
+            lenience,
+        });
+        Ok(ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| RetumiError::TlsError(err.to_string()))?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth())
+    }
+
+    pub fn is_exception(&self, host: &str) -> bool {
+        self.exception(host).is_some()
+    }
+
+    /// The fingerprint of the certificate accepted from `host` without verification
+    pub fn exception(&self, host: &str) -> Option<String> {
+        self.exceptions
+            .lock()
+            .unwrap()
+            .hosts
+            .get(&host_key(host))
+            .cloned()
+    }
+
+    /// Accept the certificate `host` presented last time from now on, but no other
+    pub fn add_exception(&self, host: &str) -> Result<(), RetumiError> {
+        let key = host_key(host);
+        let leaf = self
+            .handshakes
+            .lock()
+            .unwrap()
+            .get(&key)
+            .and_then(|handshake| handshake.chain.first().map(|der| fingerprint(der)))
+            .ok_or_else(|| RetumiError::TlsError(format!("{host} hasn't sent a certificate")))?;
+        tracing::info!("accepting the certificate {leaf} from {host} from now on");
+        self.exceptions.lock().unwrap().add(&key, leaf)
+    }
+
+    /// What `host` presented the last time we connected to it, and what we made of it
+    pub fn inspection(&self, host: &str) -> Option<Inspection> {
+        let handshakes = self.handshakes.lock().unwrap();
+        let handshake = handshakes.get(&host_key(host))?;
+
+        let chain: Vec<CertInfo> = handshake
+            .chain
+            .iter()
+            .map(|der| CertInfo::parse(der))
+            .collect();
+        let problem = handshake.error.as_ref().map(|err| classify(err, &chain));
+        Some(Inspection { chain, problem })
+    }
+}
+
+fn classify(error: &rustls::Error, chain: &[CertInfo]) -> CertProblem {
+    let rustls::Error::InvalidCertificate(error) = error else {
+        return CertProblem::Other(error.to_string());
+    };
+
+    match error {
+        CertificateError::Expired | CertificateError::ExpiredContext { .. } => CertProblem::Expired,
+        CertificateError::NotValidYet | CertificateError::NotValidYetContext { .. } => {
+            CertProblem::NotYetValid
+        }
+        CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. } => {
+            CertProblem::HostnameMismatch
+        }
+        CertificateError::Revoked => CertProblem::Revoked,
+        CertificateError::UnknownIssuer if chain.len() == 1 && chain[0].self_signed => {
+            CertProblem::SelfSigned
+        }
+        CertificateError::UnknownIssuer => CertProblem::UnknownIssuer,
+        other => CertProblem::Other(format!("{other:?}")),
+    }
+}
+
+/// Whether a failed request failed because of the server's certificate
+pub fn is_certificate_error(err: &RetumiError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        // The TLS stream reports handshake failures as I/O errors wrapping the rustls one
+        let inner = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|io| io.get_ref())
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+        if let Some(rustls::Error::InvalidCertificate(_)) =
+            inner.or_else(|| err.downcast_ref::<rustls::Error>())
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Which certificates that fail verification a `Recorder` lets through anyway
+#[derive(Debug)]
+enum Lenience {
+    None,
+    /// The one each host has an exception for
+    Exceptions,
+    All,
+}
+
+/// Verifies certificates like rustls would, but records what each server
+/// sent and what was wrong with it
+struct Recorder {
+    inner: Arc<WebPkiServerVerifier>,
+    handshakes: Handshakes,
+    exceptions: Arc<Mutex<Exceptions>>,
+    lenience: Lenience,
+}
+
+impl Recorder {
+    fn accepts(&self, host: &str, leaf: &CertificateDer<'_>) -> bool {
+        match self.lenience {
+            Lenience::None => false,
+            Lenience::Exceptions => self
+                .exceptions
+                .lock()
+                .unwrap()
+                .hosts
+                .get(host)
+                .is_some_and(|accepted| *accepted == fingerprint(leaf)),
+            Lenience::All => true,
+        }
+    }
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("lenience", &self.lenience)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerCertVerifier for Recorder {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let mut chain = vec![end_entity.clone().into_owned()];
+        chain.extend(intermediates.iter().map(|c| c.clone().into_owned()));
+
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(ip) => IpAddr::from(*ip).to_string(),
+            other => format!("{other:?}"),
+        };
+        let accepted = verified.is_err() && self.accepts(&host, end_entity);
+        self.handshakes.lock().unwrap().insert(
+            host,
+            Handshake {
+                chain,
+                error: verified.as_ref().err().cloned(),
+            },
+        );
+
+        if accepted {
+            return Ok(ServerCertVerified::assertion());
+        }
+        verified
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
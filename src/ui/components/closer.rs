@@ -2,18 +2,35 @@ use tui_realm_stdlib::Phantom;
 use tuirealm::event::{Key, KeyEvent};
 use tuirealm::{Component, Event, MockComponent};
 
-use crate::event::RetumiEvent;
+use crate::event::{LoadStatus, RetumiEvent};
 use crate::ui::Msg;
 
-#[derive(Default, MockComponent)]
+#[derive(MockComponent)]
 pub struct Closer {
     component: Phantom,
+    status: LoadStatus,
+}
+
+impl Closer {
+    pub fn new(status: LoadStatus) -> Self {
+        Self {
+            component: Phantom::default(),
+            status,
+        }
+    }
 }
 
 impl Component<Msg, RetumiEvent> for Closer {
     fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
         match ev {
-            Event::Keyboard(KeyEvent { code: Key::Esc, .. }) => Some(Msg::Quit),
+            // Esc cancels a load in progress, and only quits when there's nothing to cancel
+            Event::Keyboard(KeyEvent { code: Key::Esc, .. }) => {
+                if self.status.lock().unwrap().is_some() {
+                    Some(Msg::CancelLoad)
+                } else {
+                    Some(Msg::Quit)
+                }
+            }
             _ => None,
         }
     }
@@ -0,0 +1,129 @@
+use tui_realm_stdlib::List;
+use tuirealm::command::{Cmd, Direction};
+use tuirealm::event::{Key, KeyEvent};
+use tuirealm::props::{Alignment, Color, Style, TextSpan};
+use tuirealm::{AttrValue, Attribute, Component, Event, MockComponent, State, StateValue};
+
+use crate::content::format_size;
+use crate::download::{Download, DownloadList, DownloadState};
+use crate::event::RetumiEvent;
+use crate::theme::{SharedTheme, Theme};
+use crate::ui::Msg;
+
+#[derive(MockComponent)]
+pub struct Downloads {
+    component: List,
+    list: DownloadList,
+    theme: SharedTheme,
+}
+
+impl Downloads {
+    pub fn new(list: DownloadList, theme: SharedTheme) -> Self {
+        let highlight = theme.lock().unwrap().selection.bg.unwrap_or(Color::Reset);
+        Self {
+            component: List::default()
+                .title("Downloads (c: cancel, o: open)", Alignment::Left)
+                .scroll(true)
+                .highlighted_color(highlight)
+                .highlighted_str("> ")
+                .step(4),
+            list,
+            theme,
+        }
+    }
+
+    fn refresh(&mut self) {
+        let theme = self.theme.lock().unwrap().clone();
+        let rows = self
+            .list
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| vec![TextSpan::new(d.file_name()), status(d, &theme)])
+            .collect();
+        self.component
+            .attr(Attribute::Content, AttrValue::Table(rows));
+    }
+
+    fn selected(&self) -> Option<Download> {
+        match self.component.state() {
+            State::One(StateValue::Usize(index)) => self.list.lock().unwrap().get(index).cloned(),
+            _ => None,
+        }
+    }
+}
+
+fn status(download: &Download, theme: &Theme) -> TextSpan {
+    match &download.state {
+        DownloadState::InProgress => {
+            let received = format_size(download.received as usize);
+            let elapsed = download.started.elapsed().as_secs_f64().max(0.001);
+            let rate = format_size((download.received as f64 / elapsed) as usize);
+            let progress = match download.total {
+                Some(total) if total > 0 => format!(
+                    "  {:>3}%  {received} / {}",
+                    download.received * 100 / total,
+                    format_size(total as usize)
+                ),
+                _ => format!("  {received}"),
+            };
+            TextSpan::new(format!("{progress}  ({rate}/s)"))
+        }
+        DownloadState::Finished => styled(
+            format!("  done  {}", format_size(download.received as usize)),
+            theme.download_done,
+        ),
+        DownloadState::Failed(err) => styled(format!("  failed: {err}"), theme.download_failed),
+        DownloadState::Cancelled => styled("  cancelled", theme.download_cancelled),
+    }
+}
+
+fn styled(text: impl Into<String>, style: Style) -> TextSpan {
+    TextSpan {
+        content: text.into(),
+        fg: style.fg.unwrap_or(Color::Reset),
+        bg: style.bg.unwrap_or(Color::Reset),
+        modifiers: style.add_modifier,
+    }
+}
+
+impl Component<Msg, RetumiEvent> for Downloads {
+    fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
+        match ev {
+            Event::User(RetumiEvent::DownloadsChanged) => {
+                self.refresh();
+                Some(Msg::None)
+            }
+            Event::Keyboard(KeyEvent { code: Key::Up, .. }) => {
+                self.perform(Cmd::Move(Direction::Up));
+                Some(Msg::None)
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Down, ..
+            }) => {
+                self.perform(Cmd::Move(Direction::Down));
+                Some(Msg::None)
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('c'),
+                ..
+            }) => self
+                .selected()
+                .filter(|d| d.state == DownloadState::InProgress)
+                .map(|d| Msg::CancelDownload(d.id)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('o'),
+                ..
+            }) => self
+                .selected()
+                .filter(|d| d.state == DownloadState::Finished)
+                .map(|d| Msg::OpenDownload(d.path)),
+            Event::Keyboard(KeyEvent { code: Key::Tab, .. })
+            | Event::Keyboard(KeyEvent {
+                code: Key::Char('D'),
+                ..
+            }) => Some(Msg::ToggleDownloads),
+            _ => None,
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use tui_realm_stdlib::Label;
-use tuirealm::props::Color;
 use tuirealm::{Component, Event, MockComponent};
 
 use crate::event::RetumiEvent;
+use crate::theme::{self, Theme};
 use crate::ui::Msg;
 
 #[derive(MockComponent)]
@@ -10,10 +10,10 @@ pub struct ErrorBar {
     component: Label,
 }
 
-impl Default for ErrorBar {
-    fn default() -> Self {
+impl ErrorBar {
+    pub fn new(theme: &Theme) -> Self {
         Self {
-            component: Label::default().foreground(Color::Red),
+            component: Label::default().foreground(theme::foreground(theme.error)),
         }
     }
 }
@@ -0,0 +1,130 @@
+use tui_realm_stdlib::Input;
+use tuirealm::command::{Cmd, CmdResult, Direction, Position};
+use tuirealm::event::{Key, KeyEvent, KeyModifiers};
+use tuirealm::props::{Alignment, BorderType, Borders, InputType};
+use tuirealm::{AttrValue, Attribute, Component, Event, MockComponent};
+
+use crate::auth::Credentials;
+use crate::event::{Login, RetumiEvent};
+use crate::theme::{self, Theme};
+use crate::ui::Msg;
+
+/// Asks for a username, then a password, in a single input box
+#[derive(MockComponent)]
+pub struct LoginForm {
+    component: Input,
+    login: Login,
+    username: Option<String>,
+    remember: bool,
+}
+
+impl LoginForm {
+    pub fn new(login: Login, theme: &Theme) -> Self {
+        let color = theme::foreground(theme.prompt);
+        let mut form = Self {
+            component: Input::default()
+                .foreground(color)
+                .borders(
+                    Borders::default()
+                        .modifiers(BorderType::Double)
+                        .color(color),
+                )
+                .input_type(InputType::Text),
+            login,
+            username: None,
+            remember: false,
+        };
+        form.update_title();
+        form
+    }
+
+    fn update_title(&mut self) {
+        let field = if self.username.is_some() {
+            "Password"
+        } else {
+            "Username"
+        };
+        let rejected = if self.login.rejected {
+            "Login failed. "
+        } else {
+            ""
+        };
+        let remember = if self.remember { "x" } else { " " };
+        let title = format!(
+            "{rejected}{field} for \"{}\" [{remember}] remember (Tab)",
+            self.login.realm
+        );
+        self.component
+            .attr(Attribute::Title, AttrValue::Title((title, Alignment::Left)));
+    }
+
+    fn submit(&mut self) -> Msg {
+        let value = self.component.states.get_value();
+        match self.username.take() {
+            None => {
+                self.username = Some(value);
+                self.component
+                    .attr(Attribute::Value, AttrValue::String(String::new()));
+                self.component.attr(
+                    Attribute::InputType,
+                    AttrValue::InputType(InputType::Password('*')),
+                );
+                self.update_title();
+                Msg::None
+            }
+            Some(username) => Msg::LoginSubmit(
+                self.login.clone(),
+                Credentials {
+                    username,
+                    password: value,
+                },
+                self.remember,
+            ),
+        }
+    }
+}
+
+impl Component<Msg, RetumiEvent> for LoginForm {
+    fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
+        let _ = match ev {
+            Event::Keyboard(KeyEvent {
+                code: Key::Left, ..
+            }) => self.perform(Cmd::Move(Direction::Left)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Right, ..
+            }) => self.perform(Cmd::Move(Direction::Right)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Home, ..
+            }) => self.perform(Cmd::GoTo(Position::Begin)),
+            Event::Keyboard(KeyEvent { code: Key::End, .. }) => {
+                self.perform(Cmd::GoTo(Position::End))
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Delete, ..
+            }) => self.perform(Cmd::Cancel),
+            Event::Keyboard(KeyEvent {
+                code: Key::Backspace,
+                ..
+            }) => self.perform(Cmd::Delete),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char(ch),
+                modifiers: KeyModifiers::NONE,
+            })
+            | Event::Keyboard(KeyEvent {
+                code: Key::Char(ch),
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.perform(Cmd::Type(ch)),
+            Event::Keyboard(KeyEvent { code: Key::Tab, .. }) => {
+                self.remember = !self.remember;
+                self.update_title();
+                CmdResult::None
+            }
+            Event::Keyboard(KeyEvent { code: Key::Esc, .. }) => return Some(Msg::LoginCancel),
+            Event::Keyboard(KeyEvent {
+                code: Key::Enter, ..
+            }) => return Some(self.submit()),
+            _ => CmdResult::None,
+        };
+        Some(Msg::None)
+    }
+}
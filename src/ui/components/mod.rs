@@ -1,9 +1,17 @@
 mod closer;
+mod downloads;
 mod error_bar;
+mod login;
 mod page;
+mod prompt;
+mod status_bar;
 mod url_bar;
 
 pub use closer::Closer;
+pub use downloads::Downloads;
 pub use error_bar::ErrorBar;
-pub use page::Page;
+pub use login::LoginForm;
+pub use page::{Page, MODAL};
+pub use prompt::InputPrompt;
+pub use status_bar::StatusBar;
 pub use url_bar::UrlBar;
@@ -1,43 +1,289 @@
+use std::time::Instant;
+
 use crossbeam::channel::{Receiver, Sender};
-use tui_realm_stdlib::Textarea;
-use tuirealm::event::{Key, KeyEvent};
-use tuirealm::{Component, Event, MockComponent};
+use percent_encoding::NON_ALPHANUMERIC;
+use tuirealm::command::{Cmd, CmdResult};
+use tuirealm::event::{Key, KeyEvent, KeyModifiers};
+use tuirealm::ratatui::layout::{Margin, Rect};
+use tuirealm::ratatui::text;
+use tuirealm::ratatui::widgets::{
+    Block, List, ListState, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
+};
+use tuirealm::ratatui::Frame;
+use tuirealm::{AttrValue, Attribute, Component, Event, MockComponent, Props, State};
 
-use crate::browser::Browser;
-use crate::event::RetumiEvent;
+use crate::browser::{Activation, Browser, PendingRefresh};
+use crate::config::Config;
+use crate::event::{Loaded, Response, RetumiEvent, Subresource};
+use crate::find::Search;
+use crate::graphics::{Placement, Placements};
+use crate::hints::{HintAction, HintInput, Hints};
 use crate::js::{JsMessage, WorkerMsg};
+use crate::render::{self, Direction, ImageRow, Line};
+use crate::theme::{SharedTheme, Theme};
 use crate::ui::Msg;
 
-#[derive(MockComponent)]
+/// Set on the page while it's in a mode that Esc leaves
+pub const MODAL: &str = "modal";
+
+/// The numbered list of every link on the page
+struct LinkList {
+    state: ListState,
+    /// A link number being typed
+    number: String,
+}
+
 pub struct Page {
-    component: Textarea,
-    rx: Receiver<Option<String>>,
+    props: Props,
+    lines: Vec<Line>,
+    /// The first line on screen
+    scroll: usize,
+    /// How many lines fit on screen, as of the last draw
+    height: usize,
+    /// Labels for picking a link by typing
+    hints: Option<Hints>,
+    link_list: Option<LinkList>,
+    /// The find bar, while it's open
+    find: Option<Search>,
+    find_error: Option<String>,
+    rx: Receiver<Loaded>,
+    /// Asks the HTTP client for what the page needs once it's shown
+    subresources: Sender<Vec<Subresource>>,
     browser: Browser,
+    refresh: PendingRefresh,
+    allow_refresh: bool,
+    /// Where the images on screen go, for the model to paint after the frame
+    placements: Placements,
+    /// The runs of image rows `placements` was worked out from, so they're
+    /// only encoded again when they move
+    placed: Vec<(u16, u16, ImageRow, usize)>,
+    theme: SharedTheme,
+    /// The host and URL of a certificate error page we may add an exception for
+    trustable: Option<(String, String)>,
 }
 
 impl Component<Msg, RetumiEvent> for Page {
     fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
+        if let (Some(_), Event::Keyboard(key)) = (&self.hints, &ev) {
+            return self.on_hint_key(*key);
+        }
+        if let (Some(_), Event::Keyboard(key)) = (&self.link_list, &ev) {
+            return self.on_list_key(*key);
+        }
+        if let (Some(_), Event::Keyboard(key)) = (&self.find, &ev) {
+            if let Some(msg) = self.on_find_key(*key) {
+                return Some(msg);
+            }
+        }
+
         match ev {
             Event::Keyboard(KeyEvent { code: Key::Tab, .. }) => Some(Msg::PageBlur),
+            Event::Keyboard(KeyEvent {
+                code: Key::Down | Key::Char('j'),
+                modifiers: KeyModifiers::NONE,
+            }) => self.scroll_by(1),
+            Event::Keyboard(KeyEvent {
+                code: Key::Up | Key::Char('k'),
+                modifiers: KeyModifiers::NONE,
+            }) => self.scroll_by(-1),
+            Event::Keyboard(KeyEvent {
+                code: Key::PageDown,
+                ..
+            }) => self.scroll_by(self.height.max(1) as isize),
+            Event::Keyboard(KeyEvent {
+                code: Key::PageUp, ..
+            })
+            | Event::Keyboard(KeyEvent {
+                code: Key::Char('b'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => self.scroll_by(-(self.height.max(1) as isize)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('d'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => self.scroll_by((self.height / 2).max(1) as isize),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('u'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => self.scroll_by(-((self.height / 2).max(1) as isize)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Home | Key::Char('g'),
+                ..
+            }) => self.scroll_to(0),
+            Event::Keyboard(KeyEvent {
+                code: Key::End | Key::Char('G'),
+                ..
+            }) => self.scroll_to(usize::MAX),
             Event::Keyboard(KeyEvent {
                 code: Key::Char(' '),
                 ..
             }) => {
                 let contents = self.browser.cycle_link().unwrap();
-                Some(Msg::PageLoad(contents))
+                let msg = self.load(contents);
+                self.reveal_link();
+                Some(msg)
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('p'),
+                ..
+            }) => {
+                let count = self.browser.link_count();
+                let previous = match self.browser.current_link() {
+                    Some(link) => (link + count - 1) % count,
+                    None => count.checked_sub(1)?,
+                };
+                self.select(previous)
             }
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('['),
+                ..
+            }) if self.browser.link_count() > 0 => self.select(0),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char(']'),
+                ..
+            }) => self.select(self.browser.link_count().checked_sub(1)?),
+            Event::Keyboard(KeyEvent {
+                code: Key::Up,
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.move_link(Direction::Up),
+            Event::Keyboard(KeyEvent {
+                code: Key::Down,
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.move_link(Direction::Down),
+            Event::Keyboard(KeyEvent {
+                code: Key::Left,
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.move_link(Direction::Left),
+            Event::Keyboard(KeyEvent {
+                code: Key::Right,
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.move_link(Direction::Right),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('L'),
+                ..
+            }) => self.open_link_list(),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('/'),
+                ..
+            })
+            | Event::Keyboard(KeyEvent {
+                code: Key::Char('f'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => self.open_find(),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('f'),
+                modifiers: KeyModifiers::NONE,
+            }) => self.start_hinting(HintAction::Follow),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('F'),
+                ..
+            }) => self.start_hinting(HintAction::Focus),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('y'),
+                modifiers: KeyModifiers::NONE,
+            }) => self.start_hinting(HintAction::Copy),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('w'),
+                modifiers: KeyModifiers::NONE,
+            }) => self.start_hinting(HintAction::Window),
             Event::Keyboard(KeyEvent {
                 code: Key::Enter, ..
-            }) => self.browser.get_active_link().map(Msg::UrlSubmit),
-            Event::User(RetumiEvent::PageReady) => {
-                let msg = self.rx.recv().unwrap();
-                if let Some(contents) = msg {
-                    match self.browser.render_contents(&contents) {
-                        Ok(page) => Some(Msg::PageLoad(page)),
+            }) => match self.browser.activate() {
+                Ok(Activation::Follow(url)) => self.follow(url),
+                Ok(Activation::Redraw(contents)) => Some(self.load(contents)),
+                Ok(Activation::Nothing) => None,
+                Err(err) => Some(Msg::FillError(err.to_string())),
+            },
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('d'),
+                ..
+            }) => self.browser.get_active_link().map(Msg::Download),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('D'),
+                ..
+            }) => Some(Msg::ToggleDownloads),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('r'),
+                ..
+            }) => match self.browser.toggle_redirects() {
+                Ok(contents) => contents.map(|contents| self.load(contents)),
+                Err(err) => Some(Msg::FillError(err.to_string())),
+            },
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('t'),
+                ..
+            }) => self
+                .trustable
+                .take()
+                .map(|(host, url)| Msg::TrustHost(host, url)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('T'),
+                ..
+            }) => Some(Msg::NextTheme),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('i'),
+                ..
+            }) => self.browser.url().map(|url| {
+                let url = percent_encoding::utf8_percent_encode(url.as_str(), NON_ALPHANUMERIC);
+                Msg::UrlSubmit(format!("about:pageinfo?url={url}"))
+            }),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('I'),
+                ..
+            }) => match self.browser.toggle_images() {
+                Ok(contents) => contents.map(|contents| self.load(contents)),
+                Err(err) => Some(Msg::FillError(err.to_string())),
+            },
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('x'),
+                ..
+            }) => {
+                let blocked = self.refresh.lock().unwrap().take()?;
+                tracing::info!("blocked refresh to {}", blocked.url);
+                Some(Msg::None)
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Char('s'),
+                ..
+            }) => match self.browser.save_resource() {
+                Ok(contents) => contents.map(|contents| self.load(contents)),
+                Err(err) => Some(Msg::FillError(err.to_string())),
+            },
+            // The HTTP client only signals once the response is waiting for us
+            Event::User(RetumiEvent::PageReady) => match self.rx.try_recv().ok()? {
+                Loaded::Page(response) => {
+                    self.trustable = None;
+                    Some(self.show(response))
+                }
+                Loaded::Untrusted { page, host } => {
+                    self.trustable = host.map(|host| (host, page.url.clone()));
+                    Some(self.show(page))
+                }
+                Loaded::Download(path) => {
+                    tracing::info!("downloading to {}", path.display());
+                    Some(Msg::ShowDownloads)
+                }
+                Loaded::Prompt(prompt) => Some(Msg::Prompt(prompt)),
+                Loaded::Login(login) => Some(Msg::Login(login)),
+                Loaded::Failed(reason) => Some(Msg::FillError(reason)),
+                // Just redraw, without taking focus from wherever it is
+                Loaded::Subresource(subresource, response) => {
+                    match self.browser.add_subresource(&subresource, &response) {
+                        Ok(lines) => {
+                            self.lines = lines?;
+                            Some(Msg::None)
+                        }
                         Err(err) => Some(Msg::FillError(err.to_string())),
                     }
+                }
+            },
+            // Redraw so that `view` sees the new size
+            Event::WindowResize(..) => Some(Msg::None),
+            Event::Tick => {
+                let mut refresh = self.refresh.lock().unwrap();
+                if refresh.as_ref().is_some_and(|r| r.at <= Instant::now()) {
+                    refresh.take().map(|r| Msg::UrlSubmit(r.url))
                 } else {
-                    Some(Msg::FillError("could not load URL".to_string()))
+                    None
                 }
             }
             _ => None,
@@ -45,16 +291,560 @@ impl Component<Msg, RetumiEvent> for Page {
     }
 }
 
+impl MockComponent for Page {
+    fn view(&mut self, frame: &mut Frame, area: Rect) {
+        let inner = Block::bordered().inner(area);
+        let width = inner.width as usize;
+        match self.browser.resize(width) {
+            Ok(Some(lines)) => {
+                self.lines = lines;
+                // The labels would point at where the links used to be
+                self.stop_hinting();
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!("could not lay the page out at {width} columns: {err}"),
+        }
+        let theme = self.theme.lock().unwrap().clone();
+        match self.browser.set_theme(theme.clone()) {
+            Ok(Some(lines)) => self.lines = lines,
+            Ok(None) => {}
+            Err(err) => tracing::warn!("could not restyle the page: {err}"),
+        }
+        self.height = inner.height as usize;
+        self.scroll = self.scroll.min(self.max_scroll());
+        if self.link_list.is_some() {
+            self.clear_images();
+            self.draw_link_list(frame, area, &theme);
+            return;
+        }
+
+        let mut block = Block::bordered()
+            .title_bottom(text::Line::from(format!(" {} ", self.position())).right_aligned());
+        if let Some(hints) = &self.hints {
+            block = block.title_bottom(format!(
+                " Type a label to {} a link (Esc to cancel) ",
+                hints.action.describe()
+            ));
+        } else if let Some(search) = &self.find {
+            block = block.title_bottom(self.find_bar(search));
+        }
+        let lines: Vec<text::Line> = self
+            .lines
+            .iter()
+            .skip(self.scroll)
+            .take(self.height)
+            .map(text::Line::from)
+            .collect();
+        frame.render_widget(Paragraph::new(lines).style(theme.text).block(block), area);
+        self.place_images(inner);
+
+        if let Some(hints) = &self.hints {
+            for hint in hints.visible() {
+                let (Some(row), Some(room)) = (
+                    hint.line.checked_sub(self.scroll),
+                    (inner.width as usize).checked_sub(hint.column),
+                ) else {
+                    continue;
+                };
+                if row < self.height && room > 0 {
+                    frame.buffer_mut().set_stringn(
+                        inner.x + hint.column as u16,
+                        inner.y + row as u16,
+                        &hint.label,
+                        room,
+                        theme.hint,
+                    );
+                }
+            }
+        }
+
+        if self.max_scroll() > 0 {
+            let mut state = ScrollbarState::new(self.max_scroll() + 1)
+                .viewport_content_length(self.height)
+                .position(self.scroll);
+            frame.render_stateful_widget(
+                Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                    .begin_symbol(None)
+                    .end_symbol(None),
+                area.inner(Margin {
+                    vertical: 1,
+                    horizontal: 0,
+                }),
+                &mut state,
+            );
+        }
+    }
+
+    fn query(&self, attr: Attribute) -> Option<AttrValue> {
+        self.props.get(attr)
+    }
+
+    fn attr(&mut self, attr: Attribute, value: AttrValue) {
+        self.props.set(attr, value)
+    }
+
+    fn state(&self) -> State {
+        State::None
+    }
+
+    fn perform(&mut self, _cmd: Cmd) -> CmdResult {
+        CmdResult::None
+    }
+}
+
 impl Page {
     pub fn new(
-        rx: Receiver<Option<String>>,
+        rx: Receiver<Loaded>,
+        subresources: Sender<Vec<Subresource>>,
         msg_rx: Receiver<JsMessage>,
         worker_tx: Sender<WorkerMsg>,
+        refresh: PendingRefresh,
+        theme: SharedTheme,
+        placements: Placements,
+        config: &Config,
     ) -> Self {
         Self {
-            component: Textarea::default(),
+            props: Props::default(),
+            lines: Vec::new(),
+            scroll: 0,
+            height: 0,
+            hints: None,
+            link_list: None,
+            find: None,
+            find_error: None,
             rx,
-            browser: Browser::new(msg_rx, worker_tx),
+            subresources,
+            browser: Browser::new(
+                msg_rx,
+                worker_tx,
+                config.downloads.dir(),
+                config.browser.max_width,
+                config.browser.media,
+                theme.lock().unwrap().clone(),
+                config.browser.images,
+            ),
+            refresh,
+            allow_refresh: config.browser.meta_refresh,
+            placements,
+            placed: Vec::new(),
+            theme,
+            trustable: None,
+        }
+    }
+
+    fn show(&mut self, response: Response) -> Msg {
+        // The new page's images are numbered afresh
+        self.clear_images();
+        match self.browser.render_contents(&response) {
+            Ok(lines) => {
+                self.lines = lines;
+                self.scroll = self.browser.anchor_line().unwrap_or(0);
+                self.stop_hinting();
+                self.close_link_list();
+                self.close_find();
+                self.arm_refresh();
+                let subresources = self.browser.subresources();
+                if !subresources.is_empty() && self.subresources.send(subresources).is_err() {
+                    tracing::warn!("HTTP client gone, not fetching the rest of the page");
+                }
+                if response.truncated {
+                    Msg::Truncated(response.url)
+                } else {
+                    Msg::Navigated(response.url)
+                }
+            }
+            Err(err) => Msg::FillError(err.to_string()),
+        }
+    }
+
+    /// Note where each image on screen goes, and how to draw however much of it shows
+    fn place_images(&mut self, inner: Rect) {
+        // Runs of lines on screen showing the same picture: the first row and how many
+        let mut runs: Vec<(usize, ImageRow, usize)> = Vec::new();
+        let visible = self.lines.iter().skip(self.scroll).take(self.height);
+        for (n, line) in visible.enumerate() {
+            let Some(image) = line.image else {
+                continue;
+            };
+            match runs.last_mut() {
+                // The same image may be shown twice, one right after the other
+                Some((first, start, count))
+                    if start.index == image.index
+                        && *first + *count == n
+                        && start.row + *count == image.row =>
+                {
+                    *count += 1
+                }
+                _ => runs.push((n, image, 1)),
+            }
+        }
+
+        let placed: Vec<(u16, u16, ImageRow, usize)> = runs
+            .into_iter()
+            // A picture cut off at the side would wrap onto the next line
+            .filter(|(_, image, _)| image.column + image.columns <= inner.width as usize)
+            .map(|(first, image, count)| {
+                let x = inner.x + image.column as u16;
+                (x, inner.y + first as u16, image, count)
+            })
+            .collect();
+        if placed == self.placed {
+            return;
+        }
+
+        let mut placements = Vec::new();
+        for &(x, y, image, count) in &placed {
+            if let Some(data) = self.browser.graphic(&image, image.row..image.row + count) {
+                placements.push(Placement { x, y, data });
+            }
+        }
+        *self.placements.lock().unwrap() = placements;
+        self.placed = placed;
+    }
+
+    fn clear_images(&mut self) {
+        self.placements.lock().unwrap().clear();
+        self.placed.clear();
+    }
+
+    fn load(&mut self, lines: Vec<Line>) -> Msg {
+        self.lines = lines;
+        Msg::PageLoad
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.lines.len().saturating_sub(self.height)
+    }
+
+    fn scroll_to(&mut self, line: usize) -> Option<Msg> {
+        self.scroll = line.min(self.max_scroll());
+        Some(Msg::None)
+    }
+
+    fn scroll_by(&mut self, lines: isize) -> Option<Msg> {
+        self.scroll_to(self.scroll.saturating_add_signed(lines))
+    }
+
+    /// Scroll just far enough for `line` to be on screen
+    fn reveal(&mut self, line: usize) {
+        if line < self.scroll {
+            self.scroll = line;
+        } else if line >= self.scroll + self.height {
+            self.scroll = line + 1 - self.height.max(1);
+        }
+    }
+
+    fn reveal_link(&mut self) {
+        let Some(index) = self.browser.current_link() else {
+            return;
+        };
+        if let Some(line) = self
+            .lines
+            .iter()
+            .position(|line| line.links.iter().any(|link| link.index == index))
+        {
+            self.reveal(line);
+        }
+    }
+
+    fn start_hinting(&mut self, action: HintAction) -> Option<Msg> {
+        match Hints::new(action, &self.lines, self.scroll, self.height) {
+            Some(hints) => {
+                self.hints = Some(hints);
+                self.set_modal(true);
+                Some(Msg::None)
+            }
+            None => Some(Msg::FillError(String::from("There are no links on screen"))),
+        }
+    }
+
+    fn stop_hinting(&mut self) {
+        self.hints = None;
+        self.set_modal(false);
+    }
+
+    fn set_modal(&mut self, modal: bool) {
+        self.props
+            .set(Attribute::Custom(MODAL), AttrValue::Flag(modal));
+    }
+
+    fn on_hint_key(&mut self, key: KeyEvent) -> Option<Msg> {
+        let hints = self.hints.as_mut()?;
+        match key.code {
+            Key::Backspace => hints.pop(),
+            Key::Char(c) => match hints.push(c) {
+                HintInput::Pending => {}
+                HintInput::NoMatch => self.stop_hinting(),
+                HintInput::Chosen(link) => {
+                    let action = hints.action;
+                    self.stop_hinting();
+                    return self.on_hint(action, link);
+                }
+            },
+            Key::Esc => self.stop_hinting(),
+            _ => return None,
+        }
+        Some(Msg::None)
+    }
+
+    fn on_hint(&mut self, action: HintAction, link: usize) -> Option<Msg> {
+        match action {
+            HintAction::Follow => self.follow(self.browser.link_url(link)?),
+            HintAction::Focus => self.select(link),
+            HintAction::Copy => self.browser.link_url(link).map(Msg::CopyUrl),
+            HintAction::Window => self.browser.link_url(link).map(Msg::OpenWindow),
+        }
+    }
+
+    fn open_find(&mut self) -> Option<Msg> {
+        self.find.get_or_insert_with(Search::default);
+        self.set_modal(true);
+        Some(Msg::None)
+    }
+
+    fn close_find(&mut self) {
+        if self.find.take().is_none() {
+            return;
+        }
+        self.find_error = None;
+        self.set_modal(false);
+        self.lines = self.browser.find(None, 0);
+    }
+
+    /// Returns `None` for keys the page should handle as usual, like scrolling
+    fn on_find_key(&mut self, key: KeyEvent) -> Option<Msg> {
+        let search = self.find.as_mut()?;
+        self.lines = match (key.code, key.modifiers) {
+            (Key::Esc, _) => {
+                self.close_find();
+                return Some(Msg::None);
+            }
+            (Key::Enter | Key::Down, _) | (Key::Char('n'), KeyModifiers::CONTROL) => {
+                self.browser.find_next(true)
+            }
+            (Key::Up, _) | (Key::Char('p'), KeyModifiers::CONTROL) => self.browser.find_next(false),
+            (Key::Char('c'), KeyModifiers::ALT) => {
+                search.case_sensitive = !search.case_sensitive;
+                return self.search();
+            }
+            (Key::Char('r'), KeyModifiers::ALT) => {
+                search.regex = !search.regex;
+                return self.search();
+            }
+            (Key::Backspace, _) => {
+                search.query.pop();
+                return self.search();
+            }
+            (Key::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                search.query.push(c);
+                return self.search();
+            }
+            _ => return None,
+        };
+
+        self.reveal_match();
+        Some(Msg::None)
+    }
+
+    /// Search again for what's in the find bar, starting from the top of the screen
+    fn search(&mut self) -> Option<Msg> {
+        let search = self.find.as_ref()?;
+        let regex = match search.compile() {
+            Ok(regex) => {
+                self.find_error = None;
+                regex
+            }
+            Err(err) => {
+                // Probably a half-typed pattern, so just say so in the bar
+                self.find_error = Some(
+                    err.to_string()
+                        .lines()
+                        .last()
+                        .unwrap_or_default()
+                        .to_string(),
+                );
+                None
+            }
+        };
+
+        self.lines = self.browser.find(regex, self.scroll);
+        self.reveal_match();
+        Some(Msg::None)
+    }
+
+    fn reveal_match(&mut self) {
+        if let Some((line, ..)) = self.browser.current_match() {
+            self.reveal(line);
+        }
+    }
+
+    fn find_bar(&self, search: &Search) -> String {
+        let status = match (&self.find_error, self.browser.current_match()) {
+            (Some(err), _) => err.clone(),
+            (None, Some((_, i, count))) => format!("{} of {count}", i + 1),
+            (None, None) if search.query.is_empty() => String::new(),
+            (None, None) => String::from("no matches"),
+        };
+        let flag = |on: bool| if on { "x" } else { " " };
+        format!(
+            " Find: {}_ {status} [{}] case (Alt-c) [{}] regex (Alt-r) · Enter/Up next/previous · Esc close ",
+            search.query,
+            flag(search.case_sensitive),
+            flag(search.regex),
+        )
+    }
+
+    /// Go to `url`, which only means scrolling if it's a fragment of this page
+    fn follow(&mut self, url: String) -> Option<Msg> {
+        match self.browser.navigate_within(&url) {
+            Ok(Some(lines)) => {
+                self.lines = lines;
+                if let Some(line) = self.browser.anchor_line() {
+                    self.scroll = line;
+                }
+                Some(Msg::Navigated(url))
+            }
+            Ok(None) => Some(Msg::UrlSubmit(url)),
+            Err(err) => Some(Msg::FillError(err.to_string())),
+        }
+    }
+
+    /// Make link `index` the active one and bring it on screen
+    fn select(&mut self, index: usize) -> Option<Msg> {
+        match self.browser.select_link(index) {
+            Ok(contents) => {
+                let msg = self.load(contents);
+                self.reveal_link();
+                Some(msg)
+            }
+            Err(err) => Some(Msg::FillError(err.to_string())),
+        }
+    }
+
+    fn move_link(&mut self, direction: Direction) -> Option<Msg> {
+        let target = match self.browser.current_link() {
+            Some(link) => render::nearest_link(&self.lines, link, direction)?,
+            // Start from the first link on screen
+            None => {
+                self.lines
+                    .iter()
+                    .skip(self.scroll)
+                    .find_map(|line| line.links.first())?
+                    .index
+            }
+        };
+        self.select(target)
+    }
+
+    fn open_link_list(&mut self) -> Option<Msg> {
+        if self.browser.link_count() == 0 {
+            return Some(Msg::FillError(String::from("This page has no links")));
+        }
+
+        let mut state = ListState::default();
+        state.select(Some(self.browser.current_link().unwrap_or(0)));
+        self.link_list = Some(LinkList {
+            state,
+            number: String::new(),
+        });
+        self.set_modal(true);
+        Some(Msg::None)
+    }
+
+    fn draw_link_list(&mut self, frame: &mut Frame, area: Rect, theme: &Theme) {
+        let texts = render::link_texts(&self.lines, self.browser.link_count());
+        let digits = texts.len().to_string().len();
+        let items: Vec<String> = texts
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let url = self.browser.link_url(i).unwrap_or_default();
+                format!("{:>digits$}. {text}  {url}", i + 1)
+            })
+            .collect();
+
+        let Some(list) = self.link_list.as_mut() else {
+            return;
+        };
+        let title = match list.number.as_str() {
+            "" => String::from(" Links (Enter to follow, Esc to close) "),
+            number => format!(" Links: {number} "),
+        };
+        let widget = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(theme.selection);
+        frame.render_stateful_widget(widget, area, &mut list.state);
+    }
+
+    fn close_link_list(&mut self) {
+        if self.link_list.take().is_some() {
+            self.set_modal(false);
+        }
+    }
+
+    fn on_list_key(&mut self, key: KeyEvent) -> Option<Msg> {
+        let last = self.browser.link_count().saturating_sub(1);
+        let page = self.height.max(1);
+        let list = self.link_list.as_mut()?;
+        let selected = list.state.selected().unwrap_or(0);
+
+        let target = match key.code {
+            Key::Down | Key::Char('j') => selected + 1,
+            Key::Up | Key::Char('k') => selected.saturating_sub(1),
+            Key::PageDown => selected + page,
+            Key::PageUp => selected.saturating_sub(page),
+            Key::Home | Key::Char('g') => 0,
+            Key::End | Key::Char('G') => last,
+            Key::Char(c) if c.is_ascii_digit() => {
+                list.number.push(c);
+                match list.number.parse::<usize>() {
+                    Ok(n) if n > 0 => n - 1,
+                    _ => selected,
+                }
+            }
+            Key::Backspace => {
+                list.number.pop();
+                selected
+            }
+            Key::Enter => {
+                self.close_link_list();
+                return self.follow(self.browser.link_url(selected)?);
+            }
+            Key::Esc | Key::Char('L') | Key::Char('q') => {
+                self.close_link_list();
+                return Some(Msg::None);
+            }
+            _ => return None,
+        };
+
+        if !matches!(key.code, Key::Char(c) if c.is_ascii_digit()) && key.code != Key::Backspace {
+            list.number.clear();
+        }
+        list.state.select(Some(target.min(last)));
+        Some(Msg::None)
+    }
+
+    /// Where we are in the page, the way vi shows it
+    fn position(&self) -> String {
+        let max = self.max_scroll();
+        if max == 0 {
+            String::from("All")
+        } else if self.scroll == 0 {
+            String::from("Top")
+        } else if self.scroll >= max {
+            String::from("Bot")
+        } else {
+            format!("{}%", self.scroll * 100 / max)
+        }
+    }
+
+    fn arm_refresh(&mut self) {
+        let refresh = self.browser.take_refresh();
+        match refresh {
+            Some(refresh) if !self.allow_refresh => {
+                tracing::info!("meta refresh to {} is disabled", refresh.url)
+            }
+            refresh => *self.refresh.lock().unwrap() = refresh,
         }
     }
 }
@@ -0,0 +1,77 @@
+use tui_realm_stdlib::Input;
+use tuirealm::command::{Cmd, CmdResult, Direction, Position};
+use tuirealm::event::{Key, KeyEvent, KeyModifiers};
+use tuirealm::props::{Alignment, InputType};
+use tuirealm::{Component, Event, MockComponent};
+
+use crate::event::{Prompt, RetumiEvent};
+use crate::theme::{self, Theme};
+use crate::ui::Msg;
+
+/// Asks for the input a server needs before it will answer, e.g. a search query
+#[derive(MockComponent)]
+pub struct InputPrompt {
+    component: Input,
+    prompt: Prompt,
+}
+
+impl InputPrompt {
+    pub fn new(prompt: Prompt, theme: &Theme) -> Self {
+        let input_type = if prompt.sensitive {
+            InputType::Password('*')
+        } else {
+            InputType::Text
+        };
+
+        Self {
+            component: Input::default()
+                .foreground(theme::foreground(theme.prompt))
+                .title(format!("{} (Esc to cancel)", prompt.text), Alignment::Left)
+                .input_type(input_type),
+            prompt,
+        }
+    }
+}
+
+impl Component<Msg, RetumiEvent> for InputPrompt {
+    fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
+        let _ = match ev {
+            Event::Keyboard(KeyEvent {
+                code: Key::Left, ..
+            }) => self.perform(Cmd::Move(Direction::Left)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Right, ..
+            }) => self.perform(Cmd::Move(Direction::Right)),
+            Event::Keyboard(KeyEvent {
+                code: Key::Home, ..
+            }) => self.perform(Cmd::GoTo(Position::Begin)),
+            Event::Keyboard(KeyEvent { code: Key::End, .. }) => {
+                self.perform(Cmd::GoTo(Position::End))
+            }
+            Event::Keyboard(KeyEvent {
+                code: Key::Delete, ..
+            }) => self.perform(Cmd::Cancel),
+            Event::Keyboard(KeyEvent {
+                code: Key::Backspace,
+                ..
+            }) => self.perform(Cmd::Delete),
+            Event::Keyboard(KeyEvent {
+                code: Key::Char(ch),
+                modifiers: KeyModifiers::NONE,
+            })
+            | Event::Keyboard(KeyEvent {
+                code: Key::Char(ch),
+                modifiers: KeyModifiers::SHIFT,
+            }) => self.perform(Cmd::Type(ch)),
+            Event::Keyboard(KeyEvent { code: Key::Esc, .. }) => return Some(Msg::PromptCancel),
+            Event::Keyboard(KeyEvent {
+                code: Key::Enter, ..
+            }) => {
+                let input = self.component.states.get_value();
+                return Some(Msg::UrlSubmit(self.prompt.answer(&input)));
+            }
+            _ => CmdResult::None,
+        };
+        Some(Msg::None)
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Instant;
+
+use tui_realm_stdlib::Label;
+use tuirealm::{AttrValue, Attribute, Component, Event, MockComponent};
+
+use crate::browser::PendingRefresh;
+use crate::content::format_size;
+use crate::event::{LoadStatus, RetumiEvent};
+use crate::theme::{self, Theme};
+use crate::ui::Msg;
+
+#[derive(MockComponent)]
+pub struct StatusBar {
+    component: Label,
+    status: LoadStatus,
+    pending: PendingRefresh,
+}
+
+impl StatusBar {
+    pub fn new(status: LoadStatus, pending: PendingRefresh, theme: &Theme) -> Self {
+        Self {
+            component: Label::default().foreground(theme::foreground(theme.status)),
+            status,
+            pending,
+        }
+    }
+
+    /// Returns whether there's anything to show
+    fn refresh(&mut self) -> bool {
+        let text = match self.status.lock().unwrap().as_ref() {
+            Some(load) => {
+                let received = match load.total {
+                    Some(total) => format!(
+                        "{} of {}",
+                        format_size(load.received as usize),
+                        format_size(total as usize)
+                    ),
+                    None => format_size(load.received as usize),
+                };
+                format!(
+                    "Loading {}… {received}, {:.1}s (Esc to cancel)",
+                    load.url,
+                    load.started.elapsed().as_secs_f64()
+                )
+            }
+            None => match self.pending.lock().unwrap().as_ref() {
+                Some(refresh) => format!(
+                    "Refreshing to {} in {}s (x to block)",
+                    refresh.url,
+                    refresh
+                        .at
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f64()
+                        .ceil()
+                ),
+                None => String::new(),
+            },
+        };
+
+        let active = !text.is_empty();
+        self.component
+            .attr(Attribute::Text, AttrValue::String(text));
+        active
+    }
+}
+
+impl Component<Msg, RetumiEvent> for StatusBar {
+    fn on(&mut self, ev: Event<RetumiEvent>) -> Option<Msg> {
+        match ev {
+            Event::User(RetumiEvent::LoadProgress) => {
+                self.refresh();
+                Some(Msg::None)
+            }
+            // Keep the elapsed time and refresh countdown ticking
+            Event::Tick if self.refresh() => Some(Msg::None),
+            _ => None,
+        }
+    }
+}
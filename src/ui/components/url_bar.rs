@@ -1,10 +1,11 @@
 use tui_realm_stdlib::Input;
 use tuirealm::command::{Cmd, CmdResult, Direction, Position};
 use tuirealm::event::{Key, KeyEvent, KeyModifiers};
-use tuirealm::props::{Alignment, Color, InputType};
+use tuirealm::props::{Alignment, InputType};
 use tuirealm::{Component, Event, MockComponent};
 
 use crate::event::RetumiEvent;
+use crate::theme::{self, Theme};
 use crate::ui::Msg;
 
 #[derive(MockComponent)]
@@ -55,11 +56,11 @@ impl Component<Msg, RetumiEvent> for UrlBar {
     }
 }
 
-impl Default for UrlBar {
-    fn default() -> Self {
+impl UrlBar {
+    pub fn new(theme: &Theme) -> Self {
         Self {
             component: Input::default()
-                .foreground(Color::Blue)
+                .foreground(theme::foreground(theme.url_bar))
                 .title("URL", Alignment::Center)
                 .input_type(InputType::Text),
         }
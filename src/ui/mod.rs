@@ -1,8 +1,12 @@
 mod components;
 mod model;
 
+use std::path::PathBuf;
+
 pub use model::Model;
-use tuirealm::props::TextSpan;
+
+use crate::auth::Credentials;
+use crate::event::{Login, Prompt};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Msg {
@@ -10,9 +14,33 @@ pub enum Msg {
     Quit,
     UrlBlur,
     UrlSubmit(String),
-    PageLoad(Vec<TextSpan>),
+    CancelLoad,
+    /// The page's contents changed
+    PageLoad,
+    /// A new page was loaded from the given (post-redirect) URL
+    Navigated(String),
+    /// Like `Navigated`, but the page was cut off at the size limit
+    Truncated(String),
     FillError(String),
+    Prompt(Prompt),
+    PromptCancel,
+    Login(Login),
+    /// The credentials, and whether to remember them
+    LoginSubmit(Login, Credentials, bool),
+    LoginCancel,
+    /// Accept any certificate from the host, then load the URL
+    TrustHost(String, String),
     PageBlur,
+    CopyUrl(String),
+    /// Open the URL in another retumi, in a new terminal window
+    OpenWindow(String),
+    Download(String),
+    CancelDownload(usize),
+    OpenDownload(PathBuf),
+    ShowDownloads,
+    ToggleDownloads,
+    /// Switch to the next theme there is
+    NextTheme,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
@@ -21,4 +49,8 @@ pub enum Id {
     ErrorBar,
     Page,
     Closer,
+    Downloads,
+    StatusBar,
+    Prompt,
+    Login,
 }
@@ -1,19 +1,31 @@
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use crate::browser::PendingRefresh;
+use crate::clipboard;
+use crate::config::Config;
+use crate::content::format_size;
+use crate::download;
 use crate::error::RetumiError;
-use crate::event::{HttpClient, RetumiEvent};
+use crate::event::{HttpClient, LoadStatus, RetumiEvent};
+use crate::graphics::{self, Placement, Placements, Protocol};
 use crate::js::{JsMessage, WorkerMsg};
+use crate::net::{Session, MAX_BODY};
+use crate::theme::{self, SharedTheme, Theme};
+use crate::window;
 
 use crossbeam::channel::{Receiver, Sender};
 use tuirealm::event::{Key, KeyEvent, KeyModifiers};
-use tuirealm::props::{PropPayload, PropValue};
-use tuirealm::ratatui::layout::{Constraint, Direction, Layout};
+use tuirealm::ratatui::layout::{Constraint, Direction, Flex, Layout, Rect};
+use tuirealm::ratatui::widgets::Clear;
 use tuirealm::terminal::{CrosstermTerminalAdapter, TerminalAdapter, TerminalBridge};
 use tuirealm::{
     Application, AttrValue, Attribute, EventListenerCfg, Sub, SubClause, SubEventClause, Update,
 };
 
-use super::components::{Closer, ErrorBar, Page, UrlBar};
+use super::components::{
+    Closer, Downloads, ErrorBar, InputPrompt, LoginForm, Page, StatusBar, UrlBar, MODAL,
+};
 use super::{Id, Msg};
 
 pub struct Model<T>
@@ -26,62 +38,159 @@ where
     pub terminal: TerminalBridge<T>,
     http_tx: Sender<Msg>,
     has_error: bool,
+    show_downloads: bool,
+    load_status: LoadStatus,
+    refresh: PendingRefresh,
+    theme: SharedTheme,
+    theme_name: String,
+    placements: Placements,
+    /// How images are drawn, if they're on
+    protocol: Option<Protocol>,
+    /// The images on screen, and the size of the frame they were painted over
+    painted: Vec<Placement>,
+    painted_frame: Rect,
 }
 
 impl Model<CrosstermTerminalAdapter> {
-    pub fn new(msg_rx: Receiver<JsMessage>, worker_tx: Sender<WorkerMsg>) -> Self {
+    pub fn new(
+        msg_rx: Receiver<JsMessage>,
+        worker_tx: Sender<WorkerMsg>,
+        config: Config,
+        start: Option<String>,
+    ) -> Result<Self, RetumiError> {
+        let session = Session::new(&config.http)?;
         let (http_tx, http_rx) = crossbeam::channel::bounded(16);
         let (content_tx, content_rx) = crossbeam::channel::bounded(16);
+        let (subresources_tx, subresources_rx) = crossbeam::channel::unbounded();
+        let downloads = Arc::new(Mutex::new(Vec::new()));
+        let load_status: LoadStatus = Arc::new(Mutex::new(None));
+        let refresh: PendingRefresh = Arc::new(Mutex::new(None));
+        let theme: SharedTheme = Arc::new(Mutex::new(Theme::load(&config.browser.theme)?));
+        let current = theme.lock().unwrap().clone();
+        let placements: Placements = Arc::new(Mutex::new(Vec::new()));
 
         let mut app = Application::init(
             EventListenerCfg::default()
                 .crossterm_input_listener(Duration::from_millis(10), 10)
+                .tick_interval(Duration::from_millis(250))
                 .add_port(
-                    Box::new(HttpClient::new(http_rx, content_tx)),
+                    Box::new(HttpClient::new(
+                        http_rx,
+                        content_tx,
+                        subresources_rx,
+                        session,
+                        &config,
+                        downloads.clone(),
+                        load_status.clone(),
+                    )),
                     Duration::from_millis(10),
                     10,
                 ),
         );
 
         assert!(app
-            .mount(Id::UrlBar, Box::new(UrlBar::default()), vec![])
+            .mount(Id::UrlBar, Box::new(UrlBar::new(&current)), vec![])
             .is_ok());
         assert!(app
             .mount(
                 Id::Page,
-                Box::new(Page::new(content_rx, msg_rx, worker_tx)),
+                Box::new(Page::new(
+                    content_rx,
+                    subresources_tx,
+                    msg_rx,
+                    worker_tx,
+                    refresh.clone(),
+                    theme.clone(),
+                    placements.clone(),
+                    &config
+                )),
+                vec![
+                    Sub::new(
+                        SubEventClause::User(RetumiEvent::PageReady),
+                        SubClause::Always
+                    ),
+                    Sub::new(SubEventClause::Tick, SubClause::Always),
+                    Sub::new(SubEventClause::WindowResize, SubClause::Always),
+                ]
+            )
+            .is_ok());
+        assert!(app
+            .mount(Id::ErrorBar, Box::new(ErrorBar::new(&current)), vec![])
+            .is_ok());
+        assert!(app
+            .mount(
+                Id::Downloads,
+                Box::new(Downloads::new(downloads, theme.clone())),
                 vec![Sub::new(
-                    SubEventClause::User(RetumiEvent::PageReady),
+                    SubEventClause::User(RetumiEvent::DownloadsChanged),
                     SubClause::Always
                 )]
             )
             .is_ok());
         assert!(app
-            .mount(Id::ErrorBar, Box::new(ErrorBar::default()), vec![])
+            .mount(
+                Id::StatusBar,
+                Box::new(StatusBar::new(
+                    load_status.clone(),
+                    refresh.clone(),
+                    &current
+                )),
+                vec![
+                    Sub::new(
+                        SubEventClause::User(RetumiEvent::LoadProgress),
+                        SubClause::Always
+                    ),
+                    Sub::new(SubEventClause::Tick, SubClause::Always),
+                ]
+            )
             .is_ok());
         assert!(app
             .mount(
                 Id::Closer,
-                Box::new(Closer::default()),
+                Box::new(Closer::new(load_status.clone())),
                 vec![Sub::new(
                     SubEventClause::Keyboard(KeyEvent {
                         code: Key::Esc,
                         modifiers: KeyModifiers::NONE,
                     }),
-                    SubClause::Always
+                    // Open prompts and page modes handle Esc themselves
+                    SubClause::Not(Box::new(SubClause::Or(
+                        Box::new(SubClause::Or(
+                            Box::new(SubClause::IsMounted(Id::Prompt)),
+                            Box::new(SubClause::IsMounted(Id::Login)),
+                        )),
+                        Box::new(SubClause::HasAttrValue(
+                            Id::Page,
+                            Attribute::Custom(MODAL),
+                            AttrValue::Flag(true),
+                        )),
+                    )))
                 )]
             )
             .is_ok());
         assert!(app.active(&Id::UrlBar).is_ok());
 
-        Self {
+        let mut model = Self {
             app,
             quit: false,
             redraw: true,
             terminal: TerminalBridge::init_crossterm().expect("failed to initialize terminal"),
             http_tx,
             has_error: false,
+            show_downloads: false,
+            load_status,
+            refresh,
+            theme,
+            theme_name: config.browser.theme,
+            placements,
+            protocol: config.browser.images.protocol(),
+            painted: Vec::new(),
+            painted_frame: Rect::default(),
+        };
+        if let Some(url) = start {
+            model.do_load_page(url)?;
         }
+        Ok(model)
     }
 
     pub fn run(&mut self) -> Result<(), RetumiError> {
@@ -120,10 +229,24 @@ impl Model<CrosstermTerminalAdapter> {
     }
 
     pub fn view(&mut self) {
+        let mut frame = Rect::default();
         assert!(self
             .terminal
             .draw(|f| {
+                frame = f.area();
+                let loading = self.load_status.lock().unwrap().is_some()
+                    || self.refresh.lock().unwrap().is_some();
+                let prompting = self.app.mounted(&Id::Prompt);
                 let mut constraints = vec![Constraint::Length(3), Constraint::Fill(1)];
+                if prompting {
+                    constraints.push(Constraint::Length(3));
+                }
+                if self.show_downloads {
+                    constraints.push(Constraint::Length(8));
+                }
+                if loading {
+                    constraints.push(Constraint::Length(1));
+                }
                 if self.has_error {
                     constraints.push(Constraint::Length(1));
                 }
@@ -135,11 +258,47 @@ impl Model<CrosstermTerminalAdapter> {
                 self.app.view(&Id::UrlBar, f, chunks[0]);
                 self.app.view(&Id::Page, f, chunks[1]);
 
+                let mut next = 2;
+                if prompting {
+                    self.app.view(&Id::Prompt, f, chunks[next]);
+                    next += 1;
+                }
+                if self.show_downloads {
+                    self.app.view(&Id::Downloads, f, chunks[next]);
+                    next += 1;
+                }
+                if loading {
+                    self.app.view(&Id::StatusBar, f, chunks[next]);
+                    next += 1;
+                }
                 if self.has_error {
-                    self.app.view(&Id::ErrorBar, f, chunks[2]);
+                    self.app.view(&Id::ErrorBar, f, chunks[next]);
+                }
+                if self.app.mounted(&Id::Login) {
+                    let popup = centered(f.area(), 60, 3);
+                    f.render_widget(Clear, popup);
+                    self.app.view(&Id::Login, f, popup);
                 }
             })
             .is_ok());
+
+        // Images go on top of the frame, except under the login popup. They
+        // stay on screen until they move or a resize clears it, so only
+        // paint them again then.
+        let placements = self.placements.lock().unwrap();
+        let placements = if self.app.mounted(&Id::Login) {
+            &[]
+        } else {
+            placements.as_slice()
+        };
+        if placements == self.painted && frame == self.painted_frame {
+            return;
+        }
+        if let Err(err) = graphics::paint(&mut std::io::stdout(), self.protocol, placements) {
+            tracing::warn!("could not draw images: {err}");
+        }
+        self.painted = placements.to_vec();
+        self.painted_frame = frame;
     }
 
     fn maybe_error(&self, res: Result<(), RetumiError>) -> Option<Msg> {
@@ -150,10 +309,80 @@ impl Model<CrosstermTerminalAdapter> {
     }
 
     fn do_load_page(&mut self, url: String) -> Result<(), RetumiError> {
+        self.send_http(Msg::UrlSubmit(url))
+    }
+
+    fn send_http(&mut self, msg: Msg) -> Result<(), RetumiError> {
         self.http_tx
-            .send(Msg::UrlSubmit(url))
+            .send(msg)
             .map_err(|_| RetumiError::ChannelError)
     }
+
+    fn close_prompt(&mut self) {
+        if self.app.mounted(&Id::Prompt) {
+            assert!(self.app.umount(&Id::Prompt).is_ok());
+            assert!(self.app.active(&Id::Page).is_ok());
+        }
+    }
+
+    fn close_login(&mut self) {
+        if self.app.mounted(&Id::Login) {
+            assert!(self.app.umount(&Id::Login).is_ok());
+            assert!(self.app.active(&Id::Page).is_ok());
+        }
+    }
+
+    fn next_theme(&mut self) -> Result<(), RetumiError> {
+        let names = Theme::names();
+        let next = names
+            .iter()
+            .position(|name| *name == self.theme_name)
+            .map_or(0, |i| (i + 1) % names.len());
+        // Move on even if it fails to load, so that the one after can be tried
+        self.theme_name = names[next].clone();
+        let theme = Theme::load(&self.theme_name)?;
+        tracing::info!("switching to the {} theme", self.theme_name);
+
+        // The page and downloads pick it up when they next draw
+        let colors = [
+            (Id::UrlBar, theme.url_bar),
+            (Id::ErrorBar, theme.error),
+            (Id::StatusBar, theme.status),
+            (Id::Prompt, theme.prompt),
+            (Id::Login, theme.prompt),
+        ];
+        for (id, style) in colors {
+            if self.app.mounted(&id) {
+                assert!(self
+                    .app
+                    .attr(
+                        &id,
+                        Attribute::Foreground,
+                        AttrValue::Color(theme::foreground(style))
+                    )
+                    .is_ok());
+            }
+        }
+        if let Some(color) = theme.selection.bg {
+            assert!(self
+                .app
+                .attr(
+                    &Id::Downloads,
+                    Attribute::HighlightedColor,
+                    AttrValue::Color(color)
+                )
+                .is_ok());
+        }
+
+        *self.theme.lock().unwrap() = theme;
+        Ok(())
+    }
+
+    fn set_downloads_visible(&mut self, visible: bool) {
+        self.show_downloads = visible;
+        let focus = if visible { Id::Downloads } else { Id::Page };
+        assert!(self.app.active(&focus).is_ok());
+    }
 }
 
 impl Update<Msg> for Model<CrosstermTerminalAdapter> {
@@ -171,22 +400,75 @@ impl Update<Msg> for Model<CrosstermTerminalAdapter> {
                     None
                 }
                 Msg::UrlSubmit(url) => {
+                    // Navigating anywhere supersedes a pending refresh
+                    *self.refresh.lock().unwrap() = None;
+                    self.close_prompt();
                     let res = self.do_load_page(url);
                     self.maybe_error(res)
                 }
-                Msg::PageLoad(contents) => {
+                Msg::PageLoad => {
                     assert!(self.app.active(&Id::Page).is_ok());
                     self.has_error = false;
+                    None
+                }
+                Msg::Navigated(url) => {
                     assert!(self
                         .app
-                        .attr(
-                            &Id::Page,
-                            Attribute::Text,
-                            AttrValue::Payload(PropPayload::Vec(
-                                contents.into_iter().map(PropValue::TextSpan).collect()
-                            )),
+                        .attr(&Id::UrlBar, Attribute::Value, AttrValue::String(url))
+                        .is_ok());
+                    Some(Msg::PageLoad)
+                }
+                Msg::Truncated(url) => {
+                    let mut msg = Some(Msg::Navigated(url));
+                    while msg.is_some() {
+                        msg = self.update(msg);
+                    }
+                    Some(Msg::FillError(format!(
+                        "Only the first {} of this page could be shown",
+                        format_size(MAX_BODY)
+                    )))
+                }
+                Msg::Prompt(prompt) => {
+                    self.close_prompt();
+                    assert!(self
+                        .app
+                        .mount(
+                            Id::Prompt,
+                            Box::new(InputPrompt::new(prompt, &self.theme.lock().unwrap())),
+                            vec![]
                         )
                         .is_ok());
+                    assert!(self.app.active(&Id::Prompt).is_ok());
+                    None
+                }
+                Msg::PromptCancel => {
+                    self.close_prompt();
+                    None
+                }
+                Msg::Login(login) => {
+                    self.close_login();
+                    assert!(self
+                        .app
+                        .mount(
+                            Id::Login,
+                            Box::new(LoginForm::new(login, &self.theme.lock().unwrap())),
+                            vec![]
+                        )
+                        .is_ok());
+                    assert!(self.app.active(&Id::Login).is_ok());
+                    None
+                }
+                Msg::LoginSubmit(login, credentials, remember) => {
+                    self.close_login();
+                    let res = self.send_http(Msg::LoginSubmit(login, credentials, remember));
+                    self.maybe_error(res)
+                }
+                Msg::TrustHost(host, url) => {
+                    let res = self.send_http(Msg::TrustHost(host, url));
+                    self.maybe_error(res)
+                }
+                Msg::LoginCancel => {
+                    self.close_login();
                     None
                 }
                 Msg::FillError(err) => {
@@ -201,6 +483,43 @@ impl Update<Msg> for Model<CrosstermTerminalAdapter> {
                         .is_ok());
                     None
                 }
+                Msg::Download(url) => {
+                    let res = self.send_http(Msg::Download(url));
+                    self.set_downloads_visible(true);
+                    self.maybe_error(res)
+                }
+                Msg::CancelLoad => {
+                    let res = self.send_http(Msg::CancelLoad);
+                    self.maybe_error(res)
+                }
+                Msg::CancelDownload(id) => {
+                    let res = self.send_http(Msg::CancelDownload(id));
+                    self.maybe_error(res)
+                }
+                Msg::CopyUrl(url) => {
+                    let res = clipboard::copy(&url);
+                    self.maybe_error(res)
+                }
+                Msg::OpenWindow(url) => {
+                    let res = window::open(&url);
+                    self.maybe_error(res)
+                }
+                Msg::OpenDownload(path) => {
+                    let res = download::open(&path);
+                    self.maybe_error(res)
+                }
+                Msg::ShowDownloads => {
+                    self.set_downloads_visible(true);
+                    None
+                }
+                Msg::ToggleDownloads => {
+                    self.set_downloads_visible(!self.show_downloads);
+                    None
+                }
+                Msg::NextTheme => {
+                    let res = self.next_theme();
+                    self.maybe_error(res)
+                }
                 Msg::Quit => {
                     self.quit = true;
                     None
@@ -211,3 +530,14 @@ impl Update<Msg> for Model<CrosstermTerminalAdapter> {
         }
     }
 }
+
+/// A box `percent` of the width of `area` and `height` rows tall, in its middle
+fn centered(area: Rect, percent: u16, height: u16) -> Rect {
+    let [row] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [popup] = Layout::horizontal([Constraint::Percentage(percent)])
+        .flex(Flex::Center)
+        .areas(row);
+    popup
+}
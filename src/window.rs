@@ -0,0 +1,68 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::error::RetumiError;
+
+/// Start another retumi showing `url`, in a terminal window of its own.
+/// `$TERMINAL` picks the terminal, as long as it takes a command after `-e`.
+pub fn open(url: &str) -> Result<(), RetumiError> {
+    let exe = std::env::current_exe()?;
+    let mut command = match std::env::var_os("TERMINAL") {
+        Some(terminal) => {
+            let mut command = Command::new(terminal);
+            command.arg("-e").arg(exe);
+            detach(&mut command);
+            command
+        }
+        None => default_terminal(&exe),
+    };
+
+    command.arg(url).spawn()?;
+    Ok(())
+}
+
+/// Keep what a terminal launcher prints off our own screen
+fn detach(command: &mut Command) {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+}
+
+#[cfg(target_os = "macos")]
+fn default_terminal(exe: &Path) -> Command {
+    // Terminal.app only runs commands given as text, so let AppleScript quote them
+    let mut command = Command::new("osascript");
+    command.args([
+        "-e",
+        "on run argv",
+        "-e",
+        "tell application \"Terminal\" to do script \
+         (quoted form of item 1 of argv & \" \" & quoted form of item 2 of argv)",
+        "-e",
+        "end run",
+    ]);
+    command.arg(exe);
+    detach(&mut command);
+    command
+}
+
+/// Windows gives the new process a console of its own, without going through
+/// cmd, which would interpret characters such as & in the URL
+#[cfg(target_os = "windows")]
+fn default_terminal(exe: &Path) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NEW_CONSOLE: u32 = 0x0000_0010;
+    let mut command = Command::new(exe);
+    command.creation_flags(CREATE_NEW_CONSOLE);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn default_terminal(exe: &Path) -> Command {
+    let mut command = Command::new("x-terminal-emulator");
+    command.arg("-e").arg(exe);
+    detach(&mut command);
+    command
+}